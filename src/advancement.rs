@@ -0,0 +1,324 @@
+use bevy::prelude::*;
+use bevy_ggrs::{GgrsSchedule, RollbackFrameCount};
+use std::collections::BTreeMap;
+
+use crate::combat::PlayerKilled;
+use crate::networking::RollbackSet;
+use crate::rules::MatchRules;
+use crate::GameState;
+
+// Right of Way
+// ============
+//
+// The Nidhogg rules from design.md. Nobody may advance until somebody has
+// been killed, and then only the player who made the last kill may. Running
+// off the opponent's end of the arena (east for handle 0, who starts on the
+// west side; west for handle 1) scores an advancement and moves the fight
+// one chunk along. Falling off the north or south side is a death, never an
+// advancement.
+//
+// The first to `MatchRules::advancements_to_win` wins. That's decided
+// here too, on a simulated frame, and the match ends once that frame is
+// confirmed (see `session_events.rs`).
+//
+// All of it lives in `MatchState`, which is rolled back and checksummed like
+// any component, so a mispredicted kill also takes back the right of way it
+// handed out.
+pub struct AdvancementPlugin;
+
+impl Plugin for AdvancementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchState>()
+            .init_resource::<MatchRules>()
+            .add_event::<EdgeCrossed>()
+            .add_event::<PlayerAdvanced>()
+            .add_systems(OnEnter(GameState::InGame), reset_match_state)
+            .add_systems(
+                GgrsSchedule,
+                (apply_kills, apply_edge_crossings, check_for_winner)
+                    .chain()
+                    .in_set(RollbackSet::Advancement),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edge {
+    North, // -Z
+    South, // +Z
+    East,  // +X
+    West,  // -X
+}
+
+impl Edge {
+    // Falling off these is a death
+    pub fn is_side(self) -> bool {
+        matches!(self, Edge::North | Edge::South)
+    }
+}
+
+// The edge a player has to reach to advance: the far end of the arena from
+// where their handle spawns
+pub fn goal_edge(player_id: u32) -> Edge {
+    if player_id.is_multiple_of(2) {
+        Edge::East
+    } else {
+        Edge::West
+    }
+}
+
+// Which way along x a player advances: +1 east, -1 west
+pub fn forward(player_id: u32) -> f32 {
+    match goal_edge(player_id) {
+        Edge::West => -1.0,
+        _ => 1.0,
+    }
+}
+
+// Sent when a player reaches one of the arena's edges
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EdgeCrossed {
+    pub player: Entity,
+    pub player_id: u32,
+    pub edge: Edge,
+}
+
+// Sent when a player scores an advancement
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlayerAdvanced {
+    pub player_id: u32,
+    pub advancements: u32,
+    pub chunk: i32, // The chunk the fight moved into
+}
+
+// What reaching an edge amounts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeOutcome {
+    Advanced,
+    Fell,
+    // Own end of the arena, or the goal without the right of way
+    Blocked,
+}
+
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct MatchState {
+    // Handle of the last player to kill their opponent
+    pub right_of_way: Option<u32>,
+    // Chunk the fight is in. East advances count up, west ones down.
+    pub chunk: i32,
+    // Ordered, so the checksum doesn't depend on hash order
+    pub advancements: BTreeMap<u32, u32>,
+    pub winner: Option<u32>,
+    pub won_on: i32, // Rollback frame the winner was decided on
+    // Every connected player asked for a pause on this frame, see
+    // `networking::agree_on_pause`
+    pub paused: bool,
+}
+
+impl MatchState {
+    pub fn record_kill(&mut self, shooter_id: u32, victim_id: u32) {
+        if shooter_id != victim_id {
+            self.right_of_way = Some(shooter_id);
+        }
+    }
+
+    // Nobody advances once the match is won
+    pub fn can_advance(&self, player_id: u32, edge: Edge) -> bool {
+        self.winner.is_none()
+            && self.right_of_way == Some(player_id)
+            && edge == goal_edge(player_id)
+    }
+
+    pub fn advancements(&self, player_id: u32) -> u32 {
+        self.advancements.get(&player_id).copied().unwrap_or(0)
+    }
+
+    // A fall is a death nobody gets credit for, so the right of way stays
+    // with whoever made the last kill
+    pub fn record_edge(&mut self, player_id: u32, edge: Edge) -> EdgeOutcome {
+        if edge.is_side() {
+            return EdgeOutcome::Fell;
+        }
+        if !self.can_advance(player_id, edge) {
+            return EdgeOutcome::Blocked;
+        }
+
+        *self.advancements.entry(player_id).or_default() += 1;
+        self.chunk += if edge == Edge::East { 1 } else { -1 };
+        EdgeOutcome::Advanced
+    }
+
+    // Settles the match on `frame` if someone has advanced often enough.
+    // Returns the winner the first time.
+    pub fn decide_winner(&mut self, advancements_to_win: u32, frame: i32) -> Option<u32> {
+        if self.winner.is_some() {
+            return None;
+        }
+        let (&winner, _) = self
+            .advancements
+            .iter()
+            .find(|(_, &advancements)| advancements >= advancements_to_win)?;
+        self.winner = Some(winner);
+        self.won_on = frame;
+        Some(winner)
+    }
+}
+
+fn reset_match_state(mut match_state: ResMut<MatchState>) {
+    *match_state = MatchState::default();
+}
+
+pub fn apply_kills(
+    mut kill_reader: EventReader<PlayerKilled>,
+    mut match_state: ResMut<MatchState>,
+) {
+    for kill in kill_reader.read() {
+        match_state.record_kill(kill.shooter_id, kill.victim_id);
+    }
+}
+
+pub fn apply_edge_crossings(
+    mut edge_reader: EventReader<EdgeCrossed>,
+    mut match_state: ResMut<MatchState>,
+    mut advance_writer: EventWriter<PlayerAdvanced>,
+) {
+    for crossing in edge_reader.read() {
+        if match_state.record_edge(crossing.player_id, crossing.edge) != EdgeOutcome::Advanced {
+            continue;
+        }
+        let advancements = match_state.advancements(crossing.player_id);
+        info!(
+            "Player {} advanced into chunk {} ({} advancements)",
+            crossing.player_id, match_state.chunk, advancements
+        );
+        advance_writer.write(PlayerAdvanced {
+            player_id: crossing.player_id,
+            advancements,
+            chunk: match_state.chunk,
+        });
+    }
+}
+
+pub fn check_for_winner(
+    rules: Res<MatchRules>,
+    frame: Res<RollbackFrameCount>,
+    mut match_state: ResMut<MatchState>,
+) {
+    if let Some(winner) = match_state.decide_winner(rules.advancements_to_win, frame.0) {
+        info!(
+            "Player {} wins on frame {}, once it's confirmed",
+            winner, frame.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn nobody_advances_before_the_first_kill() {
+        let mut state = MatchState::default();
+        assert_eq!(state.record_edge(0, Edge::East), EdgeOutcome::Blocked);
+        assert_eq!(state.record_edge(1, Edge::West), EdgeOutcome::Blocked);
+        assert_eq!(state, MatchState::default());
+    }
+
+    #[test]
+    fn only_the_last_killer_advances() {
+        let mut state = MatchState::default();
+        state.record_kill(0, 1);
+        assert_eq!(state.right_of_way, Some(0));
+        assert_eq!(state.record_edge(1, Edge::West), EdgeOutcome::Blocked);
+        assert_eq!(state.record_edge(0, Edge::East), EdgeOutcome::Advanced);
+
+        // Killing back takes the right of way
+        state.record_kill(1, 0);
+        assert_eq!(state.record_edge(0, Edge::East), EdgeOutcome::Blocked);
+        assert_eq!(state.record_edge(1, Edge::West), EdgeOutcome::Advanced);
+        assert_eq!(state.advancements(0), 1);
+        assert_eq!(state.advancements(1), 1);
+    }
+
+    #[test]
+    fn only_the_opponents_edge_scores() {
+        let mut state = MatchState::default();
+        state.record_kill(0, 1);
+        assert_eq!(state.record_edge(0, Edge::West), EdgeOutcome::Blocked);
+        assert_eq!(state.record_edge(0, Edge::East), EdgeOutcome::Advanced);
+        assert_eq!(state.record_edge(0, Edge::East), EdgeOutcome::Advanced);
+        assert_eq!(state.chunk, 2);
+        assert_eq!(state.advancements(0), 2);
+
+        state.record_kill(1, 0);
+        assert_eq!(state.record_edge(1, Edge::East), EdgeOutcome::Blocked);
+        assert_eq!(state.record_edge(1, Edge::West), EdgeOutcome::Advanced);
+        assert_eq!(state.chunk, 1);
+    }
+
+    #[test]
+    fn falling_off_the_side_is_a_death_not_an_advancement() {
+        let mut state = MatchState::default();
+        state.record_kill(0, 1);
+        assert_eq!(state.record_edge(0, Edge::North), EdgeOutcome::Fell);
+        assert_eq!(state.record_edge(1, Edge::South), EdgeOutcome::Fell);
+        assert_eq!(state.right_of_way, Some(0));
+        assert_eq!(state.chunk, 0);
+        assert_eq!(state.advancements(0), 0);
+    }
+
+    #[test]
+    fn first_to_enough_advancements_wins() {
+        let mut state = MatchState::default();
+        state.record_kill(1, 0);
+        for _ in 0..4 {
+            state.record_edge(1, Edge::West);
+        }
+        assert_eq!(state.decide_winner(5, 100), None);
+
+        state.record_edge(1, Edge::West);
+        assert_eq!(state.decide_winner(5, 101), Some(1));
+        assert_eq!((state.winner, state.won_on), (Some(1), 101));
+        // Decided once, and nothing moves after that
+        assert_eq!(state.decide_winner(5, 102), None);
+        assert_eq!(state.won_on, 101);
+        assert_eq!(state.record_edge(1, Edge::West), EdgeOutcome::Blocked);
+        assert_eq!(state.advancements(1), 5);
+    }
+
+    #[test]
+    fn kill_and_edge_events_drive_the_match_state() {
+        let mut world = World::new();
+        world.init_resource::<MatchState>();
+        world.init_resource::<Events<PlayerKilled>>();
+        world.init_resource::<Events<EdgeCrossed>>();
+        world.init_resource::<Events<PlayerAdvanced>>();
+        let player = world.spawn_empty().id();
+
+        world.send_event(PlayerKilled {
+            victim: player,
+            victim_id: 0,
+            shooter_id: 1,
+        });
+        world.send_event(EdgeCrossed {
+            player,
+            player_id: 1,
+            edge: Edge::West,
+        });
+        world.run_system_once(apply_kills).unwrap();
+        world.run_system_once(apply_edge_crossings).unwrap();
+
+        let state = world.resource::<MatchState>();
+        assert_eq!(state.right_of_way, Some(1));
+        assert_eq!(state.chunk, -1);
+        let advanced = world
+            .resource::<Events<PlayerAdvanced>>()
+            .iter_current_update_events()
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(advanced.len(), 1);
+        assert_eq!(advanced[0].player_id, 1);
+        assert_eq!(advanced[0].advancements, 1);
+    }
+}
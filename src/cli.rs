@@ -0,0 +1,194 @@
+use bevy::prelude::*;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::netsim::NetworkConditions;
+
+const USAGE: &str = "\
+Usage: ribbon-game [OPTIONS]
+
+Options:
+  --synctest                 Start straight into a GGRS SyncTest session
+  --check-distance <FRAMES>  Frames SyncTest rolls back and resimulates
+  --signaling-url <URL>      Matchbox signaling server, e.g. ws://192.168.1.20:3536
+  --host-signaling           Run a local signaling server when hosting a game
+  --signaling-server <ADDR>  Only run a signaling server (e.g. 0.0.0.0:3536), no game
+  --rules <PATH>             Match rules (RON) to play by when hosting, see rules.ron
+  --sim-latency <MS>         Delay every GGRS packet, each way
+  --sim-jitter <MS>          Add up to this much random delay on top
+  --sim-loss <PERCENT>       Drop this share of GGRS packets
+  --sim-duplicate <PERCENT>  Send this share of GGRS packets twice
+  --sim-reorder <PERCENT>    Hold back this share of GGRS packets so others overtake
+  --sim-seed <SEED>          Seed for the simulated network's dice
+  -h, --help                 Print this help";
+
+// Command Line
+// ============
+//
+// Dev switches for launching the game in a specific mode without clicking
+// through the menu. Parsed once in main and inserted as a resource, so any
+// plugin can pick up what it cares about.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct CliArgs {
+    pub synctest: bool,
+    pub check_distance: Option<usize>,
+    pub signaling_url: Option<String>,
+    pub host_signaling: bool,
+    pub signaling_server: Option<SocketAddr>,
+    pub rules: Option<PathBuf>,
+    pub network_conditions: NetworkConditions,
+    pub simulator_seed: Option<u64>,
+}
+
+impl CliArgs {
+    // Parses the process arguments, exiting with usage on anything we don't understand
+    pub fn from_env() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(args) => args,
+            Err(message) => {
+                eprintln!("{message}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--synctest" => parsed.synctest = true,
+                "--check-distance" => {
+                    let value = args.next().ok_or("--check-distance needs a value")?;
+                    let frames = value
+                        .parse()
+                        .map_err(|_| format!("invalid check distance: {value}"))?;
+                    parsed.check_distance = Some(frames);
+                }
+                "--signaling-url" => {
+                    let url = args.next().ok_or("--signaling-url needs a value")?;
+                    parsed.signaling_url = Some(url);
+                }
+                "--host-signaling" => parsed.host_signaling = true,
+                "--signaling-server" => {
+                    let value = args.next().ok_or("--signaling-server needs an address")?;
+                    let addr = value
+                        .parse()
+                        .map_err(|_| format!("invalid signaling server address: {value}"))?;
+                    parsed.signaling_server = Some(addr);
+                }
+                "--rules" => {
+                    let path = args.next().ok_or("--rules needs a path")?;
+                    parsed.rules = Some(PathBuf::from(path));
+                }
+                "--sim-latency" => {
+                    parsed.network_conditions.latency = millis(&arg, args.next())?
+                }
+                "--sim-jitter" => parsed.network_conditions.jitter = millis(&arg, args.next())?,
+                "--sim-loss" => parsed.network_conditions.loss = percent(&arg, args.next())?,
+                "--sim-duplicate" => {
+                    parsed.network_conditions.duplicate = percent(&arg, args.next())?
+                }
+                "--sim-reorder" => {
+                    parsed.network_conditions.reorder = percent(&arg, args.next())?
+                }
+                "--sim-seed" => {
+                    let value = args.next().ok_or("--sim-seed needs a value")?;
+                    let seed = value
+                        .parse()
+                        .map_err(|_| format!("invalid seed: {value}"))?;
+                    parsed.simulator_seed = Some(seed);
+                }
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                other => return Err(format!("unknown argument: {other}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn millis(option: &str, value: Option<String>) -> Result<Duration, String> {
+    let value = value.ok_or(format!("{option} needs a value in milliseconds"))?;
+    let millis = value
+        .parse()
+        .map_err(|_| format!("invalid {option} value: {value}"))?;
+    Ok(Duration::from_millis(millis))
+}
+
+// A percentage on the command line, a chance from 0 to 1 in the game
+fn percent(option: &str, value: Option<String>) -> Result<f32, String> {
+    let value = value.ok_or(format!("{option} needs a percentage"))?;
+    match value.parse::<f32>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err(format!("invalid {option} value: {value}, expected 0 to 100")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_means_defaults() {
+        assert_eq!(parse(&[]), Ok(CliArgs::default()));
+    }
+
+    #[test]
+    fn synctest_with_check_distance() {
+        let args = parse(&["--synctest", "--check-distance", "4"]).unwrap();
+        assert!(args.synctest);
+        assert_eq!(args.check_distance, Some(4));
+    }
+
+    #[test]
+    fn signaling_options() {
+        let args = parse(&["--signaling-url", "ws://10.0.0.2:3536", "--host-signaling"]).unwrap();
+        assert_eq!(args.signaling_url.as_deref(), Some("ws://10.0.0.2:3536"));
+        assert!(args.host_signaling);
+
+        let args = parse(&["--signaling-server", "0.0.0.0:3536"]).unwrap();
+        assert_eq!(args.signaling_server, Some(SocketAddr::from(([0, 0, 0, 0], 3536))));
+        assert!(parse(&["--signaling-server", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn rules_file() {
+        let args = parse(&["--rules", "tournament.ron"]).unwrap();
+        assert_eq!(args.rules, Some(PathBuf::from("tournament.ron")));
+        assert!(parse(&["--rules"]).is_err());
+    }
+
+    #[test]
+    fn network_simulation_options() {
+        let args = parse(&[
+            "--sim-latency", "80", "--sim-jitter", "20", "--sim-loss", "5", "--sim-reorder", "2.5",
+            "--sim-seed", "7",
+        ])
+        .unwrap();
+        let conditions = args.network_conditions;
+        assert_eq!(conditions.latency, Duration::from_millis(80));
+        assert_eq!(conditions.jitter, Duration::from_millis(20));
+        assert_eq!((conditions.loss, conditions.duplicate, conditions.reorder), (0.05, 0.0, 0.025));
+        assert_eq!(args.simulator_seed, Some(7));
+
+        assert!(parse(&["--sim-loss", "150"]).is_err());
+        assert!(parse(&["--sim-latency", "-5"]).is_err());
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        assert!(parse(&["--check-distance"]).is_err());
+        assert!(parse(&["--check-distance", "lots"]).is_err());
+        assert!(parse(&["--fly"]).is_err());
+    }
+}
@@ -0,0 +1,724 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_ggrs::{AddRollbackCommandExtension, GgrsSchedule, PlayerInputs};
+use crate::advancement::MatchState;
+use crate::components::*;
+use crate::networking::{player_input, GGRSConfig, RollbackSet, FPS};
+use crate::physics::PlayerFell;
+use crate::resources::*;
+use crate::rules::MatchRules;
+use crate::GameState;
+
+// Fixed simulation step for everything running in `GgrsSchedule`
+pub const FIXED_DT: f32 = 1.0 / FPS as f32;
+
+// Height of the bow above the shooter's origin; aim rays are measured from here
+pub const BOW_HEIGHT: f32 = 0.6;
+// How far along the aim the arrow appears, clear of the shooter's capsule
+const ARROW_SPAWN_DISTANCE: f32 = 0.6;
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<MatchRules>()
+            .init_resource::<GameStats>()
+            .add_event::<ShotFired>()
+            .add_event::<PlayerKilled>()
+            .add_systems(
+                GgrsSchedule,
+                (
+                    update_dodges,
+                    update_bows,
+                    spawn_arrows,
+                    integrate_arrows,
+                    resolve_arrow_hits,
+                    despawn_spent_arrows,
+                    record_game_stats,
+                )
+                    .chain()
+                    .in_set(RollbackSet::Combat),
+            )
+            .add_systems(OnEnter(GameState::InGame), reset_game_stats)
+            .add_systems(Update, attach_arrow_visuals);
+    }
+}
+
+// Sent on the frame a fully drawn (or partially drawn) bow is released
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ShotFired {
+    pub shooter: Entity,
+    pub shooter_id: u32,
+    pub charge: f32,
+    pub direction: Vec3,
+}
+
+// Sent when an arrow takes a player's health to zero
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlayerKilled {
+    pub victim: Entity,
+    pub victim_id: u32,
+    pub shooter_id: u32,
+}
+
+// Dash on a fresh dodge press: one impulse along the movement input (or
+// forward when standing still), a second of i-frames, then a long cooldown.
+// Works in the air; the cooldown outlasts the i-frames so dashes can't chain.
+pub fn update_dodges(
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    rules: Res<MatchRules>,
+    mut dodge_query: Query<(&Player, &mut DodgeAbility, &mut ExternalImpulse), Without<Respawning>>,
+) {
+    for (player, mut dodge, mut impulse) in dodge_query.iter_mut() {
+        let Some(input) = player_input(&inputs, player.network_id) else {
+            continue;
+        };
+
+        if step_dodge(&mut dodge, input.dodge, FIXED_DT, &rules.dodge) {
+            let direction = Vec3::new(input.movement.x, 0.0, -input.movement.y)
+                .try_normalize()
+                .unwrap_or(Vec3::NEG_Z);
+            impulse.apply_impulse(direction * rules.dodge.dash_impulse);
+        }
+    }
+}
+
+// Advances a single dodge by one tick. Returns true when a dash starts.
+pub fn step_dodge(dodge: &mut DodgeAbility, dodge_pressed: bool, dt: f32, config: &DodgeConfig) -> bool {
+    if dodge.is_invincible {
+        dodge.invincible_timer -= dt;
+        if dodge.invincible_timer <= 0.0 {
+            dodge.invincible_timer = 0.0;
+            dodge.is_invincible = false;
+        }
+    }
+
+    if !dodge.can_dodge {
+        dodge.cooldown_timer -= dt;
+        if dodge.cooldown_timer <= 0.0 {
+            dodge.cooldown_timer = 0.0;
+            dodge.can_dodge = true;
+        }
+    }
+
+    // Only a fresh press dashes; holding Shift through the cooldown does nothing
+    let just_pressed = dodge_pressed && !dodge.dodge_held;
+    dodge.dodge_held = dodge_pressed;
+    if !just_pressed || !dodge.can_dodge {
+        return false;
+    }
+
+    dodge.can_dodge = false;
+    dodge.cooldown_timer = config.cooldown;
+    dodge.is_invincible = true;
+    dodge.invincible_timer = config.invincibility_time;
+    true
+}
+
+// Drives every bow from its owner's shoot input: holding draws and charges,
+// releasing fires, then the bow is locked until the reload finishes. The
+// dead don't shoot.
+pub fn update_bows(
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    rules: Res<MatchRules>,
+    mut bow_query: Query<(Entity, &Player, &mut Bow), Without<Respawning>>,
+    mut shot_writer: EventWriter<ShotFired>,
+) {
+    for (entity, player, mut bow) in bow_query.iter_mut() {
+        let Some(input) = player_input(&inputs, player.network_id) else {
+            continue;
+        };
+
+        if let Some(charge) = step_bow(&mut bow, input.shoot, FIXED_DT, &rules.bow) {
+            shot_writer.write(ShotFired {
+                shooter: entity,
+                shooter_id: player.network_id,
+                charge,
+                direction: input.aim,
+            });
+        }
+    }
+}
+
+// Advances a single bow by one tick. Returns the charge of the arrow to fire
+// when the shoot input is released after a draw.
+pub fn step_bow(bow: &mut Bow, shoot_held: bool, dt: f32, config: &BowConfig) -> Option<f32> {
+    if !bow.can_shoot {
+        bow.reload_timer -= dt;
+        if bow.reload_timer <= 0.0 {
+            bow.reload_timer = 0.0;
+            bow.can_shoot = true;
+        }
+        return None;
+    }
+
+    if shoot_held {
+        bow.is_drawing = true;
+        bow.draw_time += dt;
+        bow.power_charge = config.charge_for(bow.draw_time);
+        return None;
+    }
+
+    if !bow.is_drawing {
+        return None;
+    }
+
+    // Released: fire with whatever charge we built up and start reloading
+    let charge = bow.power_charge;
+    bow.is_drawing = false;
+    bow.draw_time = 0.0;
+    bow.power_charge = 0.0;
+    bow.can_shoot = false;
+    bow.reload_timer = config.reload_time;
+    Some(charge)
+}
+
+// Turns every shot fired this frame into a rollback-tracked arrow. The arrow
+// carries its own velocity, so resimulation can recreate it from the snapshot.
+pub fn spawn_arrows(
+    mut commands: Commands,
+    mut shot_reader: EventReader<ShotFired>,
+    rules: Res<MatchRules>,
+    shooter_query: Query<&Transform, With<Player>>,
+) {
+    for shot in shot_reader.read() {
+        let Ok(shooter_transform) = shooter_query.get(shot.shooter) else {
+            continue;
+        };
+
+        let velocity = shot.direction * rules.bow.arrow_speed(shot.charge);
+        let origin = shooter_transform.translation
+            + Vec3::Y * BOW_HEIGHT
+            + shot.direction * ARROW_SPAWN_DISTANCE;
+
+        commands
+            .spawn((
+                Arrow {
+                    damage: 1, // Instant death system
+                    lifetime: rules.bow.arrow_lifetime,
+                    shooter_id: shot.shooter_id,
+                    velocity,
+                    last_position: origin,
+                },
+                Transform::from_translation(origin).looking_to(velocity, Vec3::Y),
+            ))
+            .add_rollback();
+    }
+}
+
+// Fixed-step gravity integrator. Semi-implicit Euler on plain f32 math gives
+// the same arc on every peer as long as they see the same inputs.
+pub fn integrate_arrows(
+    rules: Res<MatchRules>,
+    mut arrow_query: Query<(&mut Arrow, &mut Transform)>,
+) {
+    for (mut arrow, mut transform) in arrow_query.iter_mut() {
+        step_arrow(&mut arrow, &mut transform, rules.bow.arrow_gravity, FIXED_DT);
+    }
+}
+
+pub fn step_arrow(arrow: &mut Arrow, transform: &mut Transform, gravity: f32, dt: f32) {
+    arrow.last_position = transform.translation;
+    arrow.velocity.y -= gravity * dt;
+    transform.translation += arrow.velocity * dt;
+    transform.look_to(arrow.velocity, Vec3::Y);
+    arrow.lifetime -= dt;
+}
+
+// Sweeps each arrow's flight segment for this tick against every living
+// player's capsule, so fast arrows can't tunnel through between ticks.
+//
+// design.md gives arrow hits to the arrow's owner. Every peer resimulates the
+// owner's arrows from the same inputs, so that authority comes down to two
+// rules here: an arrow never hits the player who shot it, and the kill is
+// always credited to `Arrow.shooter_id`.
+pub fn resolve_arrow_hits(
+    mut commands: Commands,
+    arrow_query: Query<(Entity, &Arrow, &Transform)>,
+    mut player_query: Query<(Entity, &mut Player, &Transform, Option<&DodgeAbility>), Without<Arrow>>,
+    mut kill_writer: EventWriter<PlayerKilled>,
+) {
+    for (arrow_entity, arrow, arrow_transform) in arrow_query.iter() {
+        for (player_entity, mut player, player_transform, dodge) in player_query.iter_mut() {
+            if player.network_id == arrow.shooter_id || player.health <= 0 {
+                continue;
+            }
+            // Dodging players are untouchable; the arrow keeps flying
+            if dodge.is_some_and(|dodge| dodge.is_invincible) {
+                continue;
+            }
+            if !segment_hits_capsule(
+                arrow.last_position,
+                arrow_transform.translation,
+                player_transform.translation,
+            ) {
+                continue;
+            }
+
+            player.health -= arrow.damage;
+            if player.health <= 0 {
+                info!("Player {} killed by player {}", player.network_id, arrow.shooter_id);
+                kill_writer.write(PlayerKilled {
+                    victim: player_entity,
+                    victim_id: player.network_id,
+                    shooter_id: arrow.shooter_id,
+                });
+            }
+            commands.entity(arrow_entity).despawn();
+            break;
+        }
+    }
+}
+
+// Arrows are spent once they reach the ground or run out of time
+pub fn despawn_spent_arrows(
+    mut commands: Commands,
+    arrow_query: Query<(Entity, &Arrow, &Transform)>,
+) {
+    for (entity, arrow, transform) in arrow_query.iter() {
+        if arrow.lifetime <= 0.0 || transform.translation.y <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn reset_game_stats(mut stats: ResMut<GameStats>) {
+    *stats = GameStats::default();
+}
+
+// Tallies this frame's shots and deaths. Players have one hit point, so
+// every arrow that lands is a kill.
+//
+// Nothing counts after the frame someone won on. The results are read once
+// that frame is confirmed, and by then a peer may have predicted further;
+// frozen stats are the same on all of them however far ahead they got.
+pub fn record_game_stats(
+    mut stats: ResMut<GameStats>,
+    match_state: Res<MatchState>,
+    mut shot_reader: EventReader<ShotFired>,
+    mut kill_reader: EventReader<PlayerKilled>,
+    mut fell_reader: EventReader<PlayerFell>,
+) {
+    if match_state.winner.is_some() {
+        // Drained all the same, so nothing from after the win is counted later
+        shot_reader.clear();
+        kill_reader.clear();
+        fell_reader.clear();
+        return;
+    }
+    stats.game_time += FIXED_DT;
+    for shot in shot_reader.read() {
+        *stats.shots_fired.entry(shot.shooter_id).or_default() += 1;
+    }
+    for kill in kill_reader.read() {
+        *stats.shots_hit.entry(kill.shooter_id).or_default() += 1;
+        *stats.kills.entry(kill.shooter_id).or_default() += 1;
+        *stats.deaths.entry(kill.victim_id).or_default() += 1;
+    }
+    for fall in fell_reader.read() {
+        *stats.deaths.entry(fall.player_id).or_default() += 1;
+    }
+}
+
+// Does the segment `start..end` pass within a player's capsule centred on
+// `capsule_center`?
+pub fn segment_hits_capsule(start: Vec3, end: Vec3, capsule_center: Vec3) -> bool {
+    let half_axis = Vec3::Y * (PLAYER_HEIGHT * 0.5);
+    let distance_squared = segment_distance_squared(
+        start,
+        end,
+        capsule_center - half_axis,
+        capsule_center + half_axis,
+    );
+    distance_squared <= PLAYER_RADIUS * PLAYER_RADIUS
+}
+
+// Squared distance between the closest points of segments p1..q1 and p2..q2
+// (Ericson, Real-Time Collision Detection, 5.1.9)
+fn segment_distance_squared(p1: Vec3, q1: Vec3, p2: Vec3, q2: Vec3) -> f32 {
+    const EPSILON: f32 = 1e-8;
+
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = d1.dot(d1);
+    let e = d2.dot(d2);
+    let f = d2.dot(r);
+
+    let (s, t) = if a <= EPSILON && e <= EPSILON {
+        (0.0, 0.0)
+    } else if a <= EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            // Parallel segments: any s works, start from p1
+            let s = if denom > EPSILON {
+                ((b * f - c * e) / denom).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).clamp(0.0, 1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+
+    let closest1 = p1 + d1 * s;
+    let closest2 = p2 + d2 * t;
+    (closest1 - closest2).length_squared()
+}
+
+// Rollback only restores the simulated components, so meshes are attached
+// outside the rollback schedule to any arrow that doesn't have one yet.
+pub fn attach_arrow_visuals(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut arrow_assets: Local<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>,
+    arrow_query: Query<Entity, (With<Arrow>, Without<Mesh3d>)>,
+) {
+    let (mesh, material) = arrow_assets.get_or_insert_with(|| {
+        (
+            meshes.add(Cuboid::new(0.04, 0.04, 0.8)),
+            materials.add(Color::srgb(0.55, 0.35, 0.2)),
+        )
+    });
+
+    for entity in arrow_query.iter() {
+        commands
+            .entity(entity)
+            .insert((Mesh3d(mesh.clone()), MeshMaterial3d(material.clone())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    // Fastest arrow we fire, covering its distance for one 30 Hz tick
+    fn fast_arrow_step() -> f32 {
+        BowConfig::default().max_arrow_speed * FIXED_DT
+    }
+
+    #[test]
+    fn fast_arrow_does_not_tunnel_through_capsule() {
+        let target = Vec3::new(0.0, 1.0, 0.0);
+        let step = fast_arrow_step();
+        // Both ends of the segment are well outside the capsule
+        let start = target + Vec3::new(0.0, 0.2, step * 0.5);
+        let end = target + Vec3::new(0.0, 0.2, -step * 0.5);
+
+        assert!(step > PLAYER_RADIUS * 2.0);
+        assert!((start - target).length() > PLAYER_RADIUS);
+        assert!((end - target).length() > PLAYER_RADIUS);
+        assert!(segment_hits_capsule(start, end, target));
+    }
+
+    #[test]
+    fn segment_that_stops_short_misses() {
+        let target = Vec3::ZERO;
+        let start = Vec3::new(0.0, 0.0, 3.0);
+        let end = Vec3::new(0.0, 0.0, PLAYER_RADIUS + 0.05);
+        assert!(!segment_hits_capsule(start, end, target));
+    }
+
+    #[test]
+    fn segment_over_the_head_misses() {
+        let target = Vec3::ZERO;
+        let over = PLAYER_HEIGHT * 0.5 + PLAYER_RADIUS + 0.05;
+        let start = Vec3::new(0.0, over, 2.0);
+        let end = Vec3::new(0.0, over, -2.0);
+        assert!(!segment_hits_capsule(start, end, target));
+    }
+
+    #[test]
+    fn segment_clips_the_rounded_cap() {
+        let target = Vec3::ZERO;
+        let over = PLAYER_HEIGHT * 0.5 + PLAYER_RADIUS - 0.05;
+        let start = Vec3::new(0.0, over, 2.0);
+        let end = Vec3::new(0.0, over, -2.0);
+        assert!(segment_hits_capsule(start, end, target));
+    }
+
+    #[test]
+    fn falling_arrow_parallel_to_axis_hits() {
+        let target = Vec3::ZERO;
+        let start = Vec3::new(PLAYER_RADIUS * 0.5, 3.0, 0.0);
+        let end = Vec3::new(PLAYER_RADIUS * 0.5, 0.5, 0.0);
+        assert!(segment_hits_capsule(start, end, target));
+    }
+
+    fn spawn_target(world: &mut World, network_id: u32, invincible: bool) -> Entity {
+        world
+            .spawn((
+                Player {
+                    network_id,
+                    ..default()
+                },
+                Transform::from_xyz(0.0, 1.0, 0.0),
+                DodgeAbility {
+                    is_invincible: invincible,
+                    ..default()
+                },
+            ))
+            .id()
+    }
+
+    fn spawn_fast_arrow(world: &mut World, shooter_id: u32) {
+        let step = fast_arrow_step();
+        world.spawn((
+            Arrow {
+                damage: 1,
+                lifetime: 1.0,
+                shooter_id,
+                velocity: Vec3::NEG_Z * BowConfig::default().max_arrow_speed,
+                last_position: Vec3::new(0.0, 1.0, step * 0.5),
+            },
+            Transform::from_xyz(0.0, 1.0, -step * 0.5),
+        ));
+    }
+
+    fn kills(world: &World) -> Vec<PlayerKilled> {
+        let events = world.resource::<Events<PlayerKilled>>();
+        events.iter_current_update_events().copied().collect()
+    }
+
+    #[test]
+    fn hit_kills_instantly_and_credits_the_shooter() {
+        let mut world = World::new();
+        world.init_resource::<Events<PlayerKilled>>();
+        let victim = spawn_target(&mut world, 1, false);
+        spawn_fast_arrow(&mut world, 0);
+
+        world.run_system_once(resolve_arrow_hits).unwrap();
+
+        assert_eq!(world.get::<Player>(victim).unwrap().health, 0);
+        let kills = kills(&world);
+        assert_eq!(kills.len(), 1);
+        assert_eq!(kills[0].victim_id, 1);
+        assert_eq!(kills[0].shooter_id, 0);
+        assert_eq!(world.query::<&Arrow>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn invincible_player_is_not_hit() {
+        let mut world = World::new();
+        world.init_resource::<Events<PlayerKilled>>();
+        let victim = spawn_target(&mut world, 1, true);
+        spawn_fast_arrow(&mut world, 0);
+
+        world.run_system_once(resolve_arrow_hits).unwrap();
+
+        assert_eq!(world.get::<Player>(victim).unwrap().health, 1);
+        assert!(kills(&world).is_empty());
+        assert_eq!(world.query::<&Arrow>().iter(&world).count(), 1);
+    }
+
+    #[test]
+    fn arrow_never_hits_its_owner() {
+        let mut world = World::new();
+        world.init_resource::<Events<PlayerKilled>>();
+        let shooter = spawn_target(&mut world, 0, false);
+        spawn_fast_arrow(&mut world, 0);
+
+        world.run_system_once(resolve_arrow_hits).unwrap();
+
+        assert_eq!(world.get::<Player>(shooter).unwrap().health, 1);
+        assert!(kills(&world).is_empty());
+    }
+
+    #[test]
+    fn stats_stop_counting_once_the_match_is_won() {
+        let mut world = World::new();
+        world.init_resource::<GameStats>();
+        world.init_resource::<MatchState>();
+        world.init_resource::<Events<ShotFired>>();
+        world.init_resource::<Events<PlayerKilled>>();
+        world.init_resource::<Events<PlayerFell>>();
+        let victim = world.spawn_empty().id();
+        let kill = PlayerKilled {
+            victim,
+            victim_id: 1,
+            shooter_id: 0,
+        };
+
+        world.send_event(kill);
+        world.run_system_once(record_game_stats).unwrap();
+        let counted = world.resource::<GameStats>().clone();
+        assert_eq!(counted.kills[&0], 1);
+        assert_eq!(counted.deaths[&1], 1);
+        assert_eq!(counted.game_time, FIXED_DT);
+
+        world.resource_mut::<MatchState>().winner = Some(0);
+        world.send_event(kill);
+        world.run_system_once(record_game_stats).unwrap();
+        let frozen = world.resource::<GameStats>();
+        assert_eq!(frozen.kills, counted.kills);
+        assert_eq!(frozen.deaths, counted.deaths);
+        assert_eq!(frozen.game_time, counted.game_time);
+    }
+
+    // Ticks `step_dodge` with `pressed` until `done`, and says how many it took
+    fn dodge_ticks_until(
+        dodge: &mut DodgeAbility,
+        pressed: bool,
+        done: impl Fn(&DodgeAbility) -> bool,
+    ) -> u32 {
+        let config = DodgeConfig::default();
+        for tick in 1..=1000 {
+            assert!(!step_dodge(dodge, pressed, FIXED_DT, &config), "dashed again");
+            if done(dodge) {
+                return tick;
+            }
+        }
+        panic!("never got there: {dodge:?}");
+    }
+
+    // Ticks a span of `seconds` takes, give or take one for float drift
+    fn about_ticks(ticks: u32, seconds: f32) -> bool {
+        ticks.abs_diff((seconds / FIXED_DT).round() as u32) <= 1
+    }
+
+    #[test]
+    fn a_dodge_press_during_the_cooldown_does_nothing() {
+        let config = DodgeConfig::default();
+        let mut dodge = DodgeAbility::default();
+        assert!(step_dodge(&mut dodge, true, FIXED_DT, &config));
+
+        // A fresh press every two seconds, all inside the seven of cooldown
+        for _ in 0..3 {
+            for _ in 0..60 {
+                step_dodge(&mut dodge, false, FIXED_DT, &config);
+            }
+            assert!(!step_dodge(&mut dodge, true, FIXED_DT, &config));
+            assert!(!dodge.can_dodge && !dodge.is_invincible);
+        }
+    }
+
+    #[test]
+    fn holding_dodge_does_not_dash_again() {
+        let config = DodgeConfig::default();
+        let mut dodge = DodgeAbility::default();
+        assert!(step_dodge(&mut dodge, true, FIXED_DT, &config));
+
+        // Still held once the cooldown is over, and for a while after
+        dodge_ticks_until(&mut dodge, true, |dodge| dodge.can_dodge);
+        for _ in 0..30 {
+            assert!(!step_dodge(&mut dodge, true, FIXED_DT, &config));
+        }
+
+        assert!(!step_dodge(&mut dodge, false, FIXED_DT, &config));
+        assert!(step_dodge(&mut dodge, true, FIXED_DT, &config));
+    }
+
+    #[test]
+    fn i_frames_end_after_the_invincibility_time() {
+        let config = DodgeConfig::default();
+        let mut dodge = DodgeAbility::default();
+        step_dodge(&mut dodge, true, FIXED_DT, &config);
+        assert!(dodge.is_invincible);
+
+        let ticks = dodge_ticks_until(&mut dodge, false, |dodge| !dodge.is_invincible);
+        assert!(about_ticks(ticks, config.invincibility_time), "{ticks} ticks");
+        assert_eq!(dodge.invincible_timer, 0.0);
+        assert!(!dodge.can_dodge, "the cooldown outlasts the i-frames");
+    }
+
+    #[test]
+    fn the_dodge_cooldown_recovers() {
+        let config = DodgeConfig::default();
+        let mut dodge = DodgeAbility::default();
+        step_dodge(&mut dodge, true, FIXED_DT, &config);
+
+        let ticks = dodge_ticks_until(&mut dodge, false, |dodge| dodge.can_dodge);
+        assert!(about_ticks(ticks, config.cooldown), "{ticks} ticks");
+        assert_eq!(dodge.cooldown_timer, 0.0);
+        assert!(step_dodge(&mut dodge, true, FIXED_DT, &config));
+    }
+
+    #[test]
+    fn every_charge_curve_runs_from_min_charge_to_full() {
+        for charge_curve in [ChargeCurve::Linear, ChargeCurve::EaseOut, ChargeCurve::EaseIn] {
+            let config = BowConfig {
+                charge_curve,
+                ..default()
+            };
+            assert_eq!(config.charge_for(0.0), config.min_charge);
+            assert_eq!(config.charge_for(config.full_draw_time), 1.0);
+            assert_eq!(config.charge_for(config.full_draw_time * 2.0), 1.0);
+
+            let steps = (0..=24).map(|step| config.charge_for(step as f32 * 0.05));
+            let charges = steps.collect::<Vec<_>>();
+            assert!(charges.windows(2).all(|pair| pair[0] <= pair[1]), "{charge_curve:?} dips");
+        }
+    }
+
+    #[test]
+    fn charge_curves_differ_halfway_through_the_draw() {
+        let halfway = |charge_curve| {
+            let config = BowConfig {
+                charge_curve,
+                ..default()
+            };
+            let shaped = config.charge_for(config.full_draw_time / 2.0) - config.min_charge;
+            shaped / (1.0 - config.min_charge)
+        };
+        assert!((halfway(ChargeCurve::Linear) - 0.5).abs() < 1e-6);
+        assert!((halfway(ChargeCurve::EaseOut) - 0.75).abs() < 1e-6);
+        assert!((halfway(ChargeCurve::EaseIn) - 0.25).abs() < 1e-6);
+    }
+
+    // Holds shoot for `ticks`, then lets go
+    fn draw_and_release(bow: &mut Bow, ticks: u32, config: &BowConfig) -> Option<f32> {
+        for _ in 0..ticks {
+            assert_eq!(step_bow(bow, true, FIXED_DT, config), None);
+        }
+        step_bow(bow, false, FIXED_DT, config)
+    }
+
+    #[test]
+    fn releasing_fires_with_the_charge_of_the_draw() {
+        let config = BowConfig::default();
+        let mut bow = Bow::default();
+        assert_eq!(step_bow(&mut bow, false, FIXED_DT, &config), None, "nothing drawn");
+        assert!(bow.can_shoot);
+
+        let charge = draw_and_release(&mut bow, 15, &config).unwrap();
+        assert_eq!(charge, config.charge_for(15.0 * FIXED_DT));
+        assert!(!bow.can_shoot && !bow.is_drawing);
+        assert_eq!((bow.draw_time, bow.power_charge), (0.0, 0.0));
+    }
+
+    #[test]
+    fn the_bow_is_locked_for_the_reload_time() {
+        let config = BowConfig::default();
+        let mut bow = Bow::default();
+        let full_draw = (config.full_draw_time / FIXED_DT).ceil() as u32;
+        assert_eq!(draw_and_release(&mut bow, full_draw, &config), Some(1.0));
+
+        // Holding shoot through the reload doesn't start a draw
+        let mut ticks = 0;
+        while !bow.can_shoot {
+            assert_eq!(step_bow(&mut bow, true, FIXED_DT, &config), None);
+            assert!(!bow.is_drawing);
+            ticks += 1;
+            assert!(ticks < 1000, "never reloaded");
+        }
+        assert!(about_ticks(ticks, config.reload_time), "{ticks} ticks");
+        assert_eq!(bow.reload_timer, 0.0);
+
+        assert!(draw_and_release(&mut bow, 5, &config).is_some());
+    }
+}
@@ -1,7 +1,13 @@
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Component, Debug, Clone, Serialize, Deserialize, Copy)]
+use crate::rules::MatchRules;
+
+// Player capsule shared by the mesh, the collider and arrow hit tests
+pub const PLAYER_RADIUS: f32 = 0.4;
+pub const PLAYER_HEIGHT: f32 = 1.8; // Length of the cylindrical section
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize, Copy, PartialEq)]
 pub struct Player {
     pub id: u32,
     pub network_id: u32, // Simplified for now
@@ -13,14 +19,16 @@ pub struct Player {
     pub is_local: bool,
 }
 
+// Tuned by the match rules; `MatchRules::player` is what a match spawns
 impl Default for Player {
     fn default() -> Self {
+        let rules = MatchRules::default();
         Self {
             id: 0,
             network_id: 0,
-            movement_speed: 5.0, // 5 meter per second base movement
+            movement_speed: rules.movement_speed,
             is_grounded: false,
-            jump_force: 8.0, // Hop-style jump force
+            jump_force: rules.jump_force,
             can_jump: true,
             health: 1, // Instant death system
             is_local: true,
@@ -47,12 +55,16 @@ pub struct LocalPlayer;
 #[derive(Component)]
 pub struct RemotePlayer;
 
-// Network input component for GGRS
+// Network input component for GGRS. This is the gameplay-side view; what
+// actually goes over the wire is `networking::PackedInput`.
 #[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct NetworkInput {
     pub movement: Vec2,
     pub jump: bool,
     pub shoot: bool,
+    pub dodge: bool,
+    pub aim: Vec3, // Unit direction from the bow, forward is -Z
+    pub pause: bool, // We're paused by vote, not a control
 }
 
 impl Default for NetworkInput {
@@ -61,16 +73,21 @@ impl Default for NetworkInput {
             movement: Vec2::ZERO,
             jump: false,
             shoot: false,
+            dodge: false,
+            aim: Vec3::NEG_Z,
+            pause: false,
         }
     }
 }
 
 // Bow and combat components
-#[derive(Component)]
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct Bow {
     pub reload_timer: f32,
     pub can_shoot: bool,
     pub power_charge: f32,
+    pub draw_time: f32, // Seconds the shoot input has been held this draw
+    pub is_drawing: bool,
 }
 
 impl Default for Bow {
@@ -79,23 +96,35 @@ impl Default for Bow {
             reload_timer: 0.0,
             can_shoot: true,
             power_charge: 0.0,
+            draw_time: 0.0,
+            is_drawing: false,
         }
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct Arrow {
     pub damage: i32,
     pub lifetime: f32,
     pub shooter_id: u32,
+    pub velocity: Vec3, // Integrated by hand so it never touches Avian state
+    pub last_position: Vec3, // Start of this tick's flight segment
 }
 
-#[derive(Component)]
+// A dead player waiting to come back, see `respawn.rs`
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Respawning {
+    pub died_at: Vec3,
+    pub timer: f32, // Seconds left if nobody is advancing
+}
+
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct DodgeAbility {
     pub cooldown_timer: f32,
     pub can_dodge: bool,
     pub invincible_timer: f32,
     pub is_invincible: bool,
+    pub dodge_held: bool, // Dodge input last tick, so holding Shift can't chain dashes
 }
 
 impl Default for DodgeAbility {
@@ -105,6 +134,7 @@ impl Default for DodgeAbility {
             can_dodge: true,
             invincible_timer: 0.0,
             is_invincible: false,
+            dodge_held: false,
         }
     }
 } 
\ No newline at end of file
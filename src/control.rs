@@ -0,0 +1,716 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::lobby::LobbyMessage;
+use crate::networking::{PeerSocket, CONTROL_CHANNEL};
+use crate::rejoin::{RejoinMessage, RejoinReceived, Rejoining};
+use crate::resources::LobbyState;
+use crate::GameState;
+
+// Bump whenever a message changes shape. Peers on another version are
+// ignored rather than half-understood.
+pub const CONTROL_PROTOCOL_VERSION: u16 = 5;
+
+// Control Channel
+// ===============
+//
+// GGRS gets the unreliable channel and is welcome to lose packets; it
+// resends inputs until they're acknowledged. Everything else that peers
+// tell each other goes over the reliable control channel: the lobby
+// before the match, chat, pause votes and forfeits during it, and the
+// snapshot that brings a dropped player back in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ControlMessage {
+    Lobby(LobbyMessage),
+    Match(MatchMessage),
+    Rejoin(RejoinMessage),
+}
+
+// In-match messages go straight from each peer to every other peer. The
+// host has no special say once the match is running.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MatchMessage {
+    Chat { text: String },
+    PauseVote { pause: bool },
+    Forfeit,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlPacket {
+    version: u16,
+    message: ControlMessage,
+}
+
+// Just enough of a packet to check the version before trusting the rest
+#[derive(Deserialize)]
+struct PacketHeader {
+    version: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    VersionMismatch(u16),
+    Malformed(String),
+}
+
+pub fn encode(message: ControlMessage) -> Box<[u8]> {
+    let packet = ControlPacket {
+        version: CONTROL_PROTOCOL_VERSION,
+        message,
+    };
+    serde_json::to_vec(&packet)
+        .expect("control messages always serialize")
+        .into_boxed_slice()
+}
+
+pub fn decode(bytes: &[u8]) -> Result<ControlMessage, DecodeError> {
+    let header: PacketHeader =
+        serde_json::from_slice(bytes).map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    if header.version != CONTROL_PROTOCOL_VERSION {
+        return Err(DecodeError::VersionMismatch(header.version));
+    }
+    let packet: ControlPacket =
+        serde_json::from_slice(bytes).map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    Ok(packet.message)
+}
+
+pub fn send(socket: &mut PeerSocket, peer: PeerId, message: ControlMessage) {
+    socket.send(CONTROL_CHANNEL, encode(message), peer);
+}
+
+pub fn broadcast(socket: &mut PeerSocket, message: ControlMessage) {
+    let packet = encode(message);
+    for peer in socket.connected_peers() {
+        socket.send(CONTROL_CHANNEL, packet.clone(), peer);
+    }
+}
+
+// Reads everything waiting on the control channel, dropping (and logging)
+// anything we can't understand
+pub fn receive(socket: &mut PeerSocket) -> Vec<(PeerId, ControlMessage)> {
+    receive_or_mismatch(socket).messages
+}
+
+// What `receive_or_mismatch` read: the messages, and the peers that speak
+// another protocol version (and which one)
+#[derive(Default)]
+pub struct Received {
+    pub messages: Vec<(PeerId, ControlMessage)>,
+    pub mismatches: Vec<(PeerId, u16)>,
+}
+
+// Like `receive`, but also hands back the version mismatches, for the lobby
+// to tell the player about
+pub fn receive_or_mismatch(socket: &mut PeerSocket) -> Received {
+    let mut received = Received::default();
+    for (peer, packet) in socket.receive(CONTROL_CHANNEL) {
+        match decode(&packet) {
+            Ok(message) => received.messages.push((peer, message)),
+            Err(DecodeError::VersionMismatch(version)) => {
+                warn!("Ignoring control message from {peer}: protocol v{version}, we speak v{CONTROL_PROTOCOL_VERSION}");
+                received.mismatches.push((peer, version));
+            }
+            Err(DecodeError::Malformed(e)) => {
+                warn!("Ignoring malformed control message from {peer}: {e}");
+            }
+        }
+    }
+    received
+}
+
+// What the in-game UI asks for
+#[derive(Event, Debug, Clone, PartialEq)]
+pub enum MatchAction {
+    Chat(String),
+    VotePause(bool),
+    Forfeit,
+}
+
+// Match Control
+// =============
+//
+// Who's in the match, who wants it paused and who has given up. Inserted
+// when a P2P match starts and gone again back at the menu.
+//
+// Rollback can't stop the clock on one peer without the others timing it
+// out, so a passed pause vote doesn't stop the session. It freezes every
+// player's input and marks it `PAUSE`, and the simulation stands still on
+// any frame where all the connected players' inputs say so (see
+// `networking::agree_on_pause`). The inputs are what every peer agrees on,
+// so they all pause on the same frame. Forfeited players are frozen for
+// the rest of the match.
+//
+// A disconnected player keeps their slot for a while in case they rejoin
+// (see `rejoin.rs`). Only once that runs out are they abandoned, which
+// counts as forfeiting. To take the slot back they show the rejoin token the
+// host handed them with the start. Players know each other's tokens, so any
+// of them can check it; spectators and anyone else in the room never see one.
+//
+// Spectators can chat but have no say in anything else. The broadcaster,
+// the lobby host, is the one player whose session sends them the inputs.
+#[derive(Resource, Debug, Clone)]
+pub struct MatchControl {
+    pub local: PeerId,
+    pub players: BTreeSet<PeerId>,
+    pub pause_votes: BTreeSet<PeerId>,
+    pub forfeited: BTreeSet<PeerId>,
+    pub disconnected: BTreeSet<PeerId>,
+    // Who is behind each GGRS handle of the running session
+    pub handles: Vec<PeerId>,
+    pub spectators: BTreeSet<PeerId>,
+    pub broadcaster: Option<PeerId>,
+    pub rejoin_tokens: BTreeMap<PeerId, u64>,
+}
+
+// The part of `MatchControl` every peer has to agree on after a rejoin
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchRoster {
+    pub players: Vec<PeerId>,
+    pub forfeited: Vec<PeerId>,
+    pub disconnected: Vec<PeerId>,
+}
+
+impl MatchControl {
+    pub fn new(local: PeerId, players: &[PeerId]) -> Self {
+        let players = players.iter().copied().collect::<BTreeSet<_>>();
+        Self {
+            local,
+            // Sorted, same as `networking::assign_player_handles`
+            handles: players.iter().copied().collect(),
+            players,
+            pause_votes: BTreeSet::new(),
+            forfeited: BTreeSet::new(),
+            disconnected: BTreeSet::new(),
+            spectators: BTreeSet::new(),
+            broadcaster: None,
+            rejoin_tokens: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_rejoin_tokens(mut self, tokens: &[(PeerId, u64)]) -> Self {
+        self.rejoin_tokens = tokens.iter().copied().collect();
+        self
+    }
+
+    pub fn with_spectators(mut self, broadcaster: PeerId, spectators: &[PeerId]) -> Self {
+        self.broadcaster = Some(broadcaster);
+        self.spectators = spectators.iter().copied().collect();
+        self
+    }
+
+    pub fn is_spectating(&self) -> bool {
+        self.spectators.contains(&self.local)
+    }
+
+    // Spectators our own P2P session has to feed, if we're the broadcaster
+    pub fn fed_spectators(&self) -> Vec<PeerId> {
+        if self.broadcaster == Some(self.local) && !self.is_spectating() {
+            self.spectators.iter().copied().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // A spectator left. Nobody holds a seat for them.
+    pub fn drop_spectator(&mut self, peer: PeerId) -> bool {
+        self.spectators.remove(&peer)
+    }
+
+    // Players who still get a say: not forfeited, not gone
+    pub fn active_players(&self) -> impl Iterator<Item = &PeerId> {
+        self.players
+            .iter()
+            .filter(|peer| !self.forfeited.contains(peer))
+    }
+
+    // Active players who are actually connected right now
+    pub fn present_players(&self) -> impl Iterator<Item = &PeerId> {
+        self.active_players()
+            .filter(|peer| !self.disconnected.contains(peer))
+    }
+
+    // Paused once every present player has voted for it
+    pub fn is_paused(&self) -> bool {
+        let mut present = self.present_players().peekable();
+        present.peek().is_some() && present.all(|peer| self.pause_votes.contains(peer))
+    }
+
+    pub fn has_forfeited(&self, peer: PeerId) -> bool {
+        self.forfeited.contains(&peer)
+    }
+
+    // Whether what we send this frame should be an idle input
+    pub fn freezes_local_input(&self) -> bool {
+        self.is_paused() || self.has_forfeited(self.local)
+    }
+
+    pub fn handle_of(&self, peer: PeerId) -> Option<usize> {
+        self.handles.iter().position(|&handle| handle == peer)
+    }
+
+    // Applies a message from `from` (which may be us). Returns what changed,
+    // if it's worth telling the players about.
+    pub fn apply(&mut self, from: PeerId, message: &MatchMessage) -> Option<MatchEvent> {
+        if let (true, MatchMessage::Chat { text }) = (self.spectators.contains(&from), message) {
+            return Some(MatchEvent::Chat(text.clone()));
+        }
+        if !self.players.contains(&from) {
+            return None; // Not in this match, e.g. someone who joined the room late
+        }
+
+        match message {
+            MatchMessage::Chat { text } => Some(MatchEvent::Chat(text.clone())),
+            MatchMessage::PauseVote { pause } => {
+                let was_paused = self.is_paused();
+                let changed = if *pause {
+                    self.pause_votes.insert(from)
+                } else {
+                    self.pause_votes.remove(&from)
+                };
+                match (was_paused, self.is_paused()) {
+                    (false, true) => Some(MatchEvent::Paused),
+                    (true, false) => Some(MatchEvent::Resumed),
+                    _ if changed => Some(MatchEvent::PauseVote(*pause)),
+                    _ => None,
+                }
+            }
+            MatchMessage::Forfeit => {
+                if !self.forfeited.insert(from) {
+                    return None;
+                }
+                self.pause_votes.remove(&from);
+                Some(MatchEvent::Forfeited)
+            }
+        }
+    }
+
+    // GGRS gave up on this peer. Their slot stays open until they rejoin
+    // or `abandon` is called.
+    pub fn disconnect(&mut self, peer: PeerId) -> bool {
+        if !self.players.contains(&peer) || !self.disconnected.insert(peer) {
+            return false;
+        }
+        self.pause_votes.remove(&peer);
+        true
+    }
+
+    // Their grace period ran out. For the result that's the same as
+    // forfeiting.
+    pub fn abandon(&mut self, peer: PeerId) -> bool {
+        self.disconnected.contains(&peer) && self.forfeited.insert(peer)
+    }
+
+    // Every other player we were still playing with is gone, which is more
+    // likely our connection than all of theirs
+    pub fn lost_everyone(&self) -> bool {
+        if self.is_spectating() {
+            return false; // Nothing to rejoin, there's no seat to take back
+        }
+        let mut others = self
+            .active_players()
+            .filter(|&&peer| peer != self.local)
+            .peekable();
+        others.peek().is_some() && others.all(|peer| self.disconnected.contains(peer))
+    }
+
+    // What we show to take our own slot back
+    pub fn rejoin_token(&self) -> Option<u64> {
+        self.rejoin_tokens.get(&self.local).copied()
+    }
+
+    // Only a slot whose player has dropped, and only for whoever holds its
+    // token. A player still connected keeps their slot whatever anyone says.
+    pub fn can_rejoin(&self, previous: PeerId, token: u64) -> bool {
+        self.players.contains(&previous)
+            && self.disconnected.contains(&previous)
+            && !self.forfeited.contains(&previous)
+            && self.rejoin_tokens.get(&previous) == Some(&token)
+    }
+
+    // The peer that answers a rejoin: the lowest one still present. Every
+    // survivor works this out the same way, so exactly one of them answers.
+    pub fn resync_authority(&self, previous: PeerId) -> Option<PeerId> {
+        self.players
+            .iter()
+            .copied()
+            .filter(|&peer| peer != previous && !self.disconnected.contains(&peer))
+            .min()
+    }
+
+    // The roster once `previous` is back as `rejoined`. Anyone else still
+    // disconnected can't be part of the new session, so they're abandoned.
+    pub fn rejoined_roster(&self, previous: PeerId, rejoined: PeerId) -> MatchRoster {
+        let swap = |peer: &PeerId| if *peer == previous { rejoined } else { *peer };
+        let players = self.players.iter().map(swap).collect::<Vec<_>>();
+        let disconnected = self
+            .disconnected
+            .iter()
+            .filter(|&&peer| peer != previous)
+            .copied()
+            .collect::<Vec<_>>();
+        let forfeited = self
+            .forfeited
+            .iter()
+            .chain(&disconnected)
+            .map(swap)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        MatchRoster {
+            players,
+            forfeited,
+            disconnected,
+        }
+    }
+
+    // The rejoined peer holds the old one's token from now on, so they can
+    // come back again if they drop again
+    pub fn pass_on_rejoin_token(&mut self, previous: PeerId, rejoined: PeerId) {
+        if let Some(token) = self.rejoin_tokens.remove(&previous) {
+            self.rejoin_tokens.insert(rejoined, token);
+        }
+    }
+
+    // Takes on a roster from a resync. Pause votes start over, and the new
+    // session's handles go to everyone still connected.
+    pub fn adopt(&mut self, roster: &MatchRoster) {
+        self.players = roster.players.iter().copied().collect();
+        self.forfeited = roster.forfeited.iter().copied().collect();
+        self.disconnected = roster.disconnected.iter().copied().collect();
+        self.pause_votes.clear();
+        self.handles = self
+            .players
+            .iter()
+            .filter(|peer| !self.disconnected.contains(peer))
+            .copied()
+            .collect();
+    }
+
+    // Over once at most one player is still playing
+    pub fn is_decided(&self) -> bool {
+        self.players.len() > 1 && self.active_players().count() <= 1
+    }
+}
+
+// What changed, for the chat log
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchEvent {
+    Chat(String),
+    PauseVote(bool),
+    Paused,
+    Resumed,
+    Forfeited,
+}
+
+pub struct ControlPlugin;
+
+impl Plugin for ControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MatchAction>()
+            .add_systems(OnEnter(GameState::MainMenu), clear_match_control)
+            .add_systems(
+                Update,
+                run_match_control.run_if(
+                    in_state(GameState::InGame)
+                        .and(resource_exists::<PeerSocket>)
+                        .and(resource_exists::<MatchControl>)
+                        // The rejoin reads the channel itself until we're back
+                        .and(not(resource_exists::<Rejoining>)),
+                ),
+            );
+    }
+}
+
+fn clear_match_control(mut commands: Commands) {
+    commands.remove_resource::<MatchControl>();
+}
+
+// Pumps the control channel during a match
+fn run_match_control(
+    mut socket: ResMut<PeerSocket>,
+    mut control: ResMut<MatchControl>,
+    mut lobby_state: ResMut<LobbyState>,
+    mut actions: EventReader<MatchAction>,
+    mut rejoins: EventWriter<RejoinReceived>,
+) {
+    if !socket.has_channel(CONTROL_CHANNEL) {
+        return;
+    }
+
+    // GGRS decides when a peer is gone for good, see `session_events.rs`
+    for (peer, state) in socket.update_peers() {
+        if state == PeerState::Disconnected {
+            warn!("Lost connection to {peer}");
+        }
+    }
+
+    let mut messages = receive(&mut socket)
+        .into_iter()
+        .filter_map(|(peer, message)| match message {
+            ControlMessage::Match(message) => Some((peer, message)),
+            // Someone still in the lobby; nothing to do with us now
+            ControlMessage::Lobby(_) => None,
+            // Comes from a peer id we don't know yet, see `rejoin.rs`
+            ControlMessage::Rejoin(message) => {
+                rejoins.write(RejoinReceived {
+                    from: peer,
+                    message,
+                });
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for action in actions.read() {
+        let message = match action {
+            MatchAction::Chat(text) => MatchMessage::Chat { text: text.clone() },
+            MatchAction::VotePause(pause) => MatchMessage::PauseVote { pause: *pause },
+            MatchAction::Forfeit => MatchMessage::Forfeit,
+        };
+        broadcast(&mut socket, ControlMessage::Match(message.clone()));
+        messages.push((control.local, message));
+    }
+
+    for (peer, message) in messages {
+        let Some(event) = control.apply(peer, &message) else {
+            continue;
+        };
+        let name = lobby_state.player_name(peer.0);
+        match event {
+            MatchEvent::Chat(text) => lobby_state.push_chat_message(&name, &text),
+            MatchEvent::PauseVote(true) => {
+                lobby_state.push_system_message(&format!("{name} voted to pause"))
+            }
+            MatchEvent::PauseVote(false) => {
+                lobby_state.push_system_message(&format!("{name} withdrew their pause vote"))
+            }
+            MatchEvent::Paused => lobby_state.push_system_message("Game paused by vote"),
+            MatchEvent::Resumed => {
+                lobby_state.push_system_message(&format!("{name} resumed the game"))
+            }
+            MatchEvent::Forfeited => lobby_state.push_system_message(&format!("{name} forfeited")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn peer(n: u128) -> PeerId {
+        PeerId(Uuid::from_u128(n))
+    }
+
+    fn three_player_match() -> MatchControl {
+        MatchControl::new(peer(1), &[peer(1), peer(2), peer(3)])
+    }
+
+    fn vote(control: &mut MatchControl, from: u128, pause: bool) -> Option<MatchEvent> {
+        control.apply(peer(from), &MatchMessage::PauseVote { pause })
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let message = ControlMessage::Match(MatchMessage::Chat {
+            text: "gl hf".to_string(),
+        });
+        assert_eq!(decode(&encode(message.clone())), Ok(message));
+    }
+
+    #[test]
+    fn other_protocol_versions_are_rejected() {
+        let packet = ControlPacket {
+            version: CONTROL_PROTOCOL_VERSION + 1,
+            message: ControlMessage::Match(MatchMessage::Forfeit),
+        };
+        let bytes = serde_json::to_vec(&packet).unwrap();
+        assert_eq!(
+            decode(&bytes),
+            Err(DecodeError::VersionMismatch(CONTROL_PROTOCOL_VERSION + 1))
+        );
+        assert!(matches!(
+            decode(b"not json"),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn pause_needs_every_vote() {
+        let mut control = three_player_match();
+        assert_eq!(
+            vote(&mut control, 1, true),
+            Some(MatchEvent::PauseVote(true))
+        );
+        assert_eq!(
+            vote(&mut control, 2, true),
+            Some(MatchEvent::PauseVote(true))
+        );
+        assert!(!control.is_paused());
+
+        assert_eq!(vote(&mut control, 3, true), Some(MatchEvent::Paused));
+        assert!(control.is_paused() && control.freezes_local_input());
+
+        assert_eq!(vote(&mut control, 2, false), Some(MatchEvent::Resumed));
+        assert!(!control.freezes_local_input());
+    }
+
+    #[test]
+    fn repeated_votes_are_quiet() {
+        let mut control = three_player_match();
+        vote(&mut control, 2, true);
+        assert_eq!(vote(&mut control, 2, true), None);
+        assert_eq!(vote(&mut control, 3, false), None);
+    }
+
+    #[test]
+    fn forfeited_and_departed_players_dont_hold_up_a_pause() {
+        let mut control = three_player_match();
+        vote(&mut control, 1, true);
+        vote(&mut control, 2, true);
+        assert_eq!(
+            control.apply(peer(3), &MatchMessage::Forfeit),
+            Some(MatchEvent::Forfeited)
+        );
+        assert!(control.is_paused());
+
+        let mut control = three_player_match();
+        vote(&mut control, 1, true);
+        vote(&mut control, 3, true);
+        assert!(control.disconnect(peer(2)));
+        assert!(control.is_paused());
+    }
+
+    #[test]
+    fn match_is_decided_when_one_player_is_left() {
+        let mut control = three_player_match();
+        control.apply(peer(2), &MatchMessage::Forfeit);
+        assert!(!control.is_decided());
+
+        assert!(control.disconnect(peer(3)));
+        assert!(!control.disconnect(peer(3)));
+        assert!(!control.is_decided(), "their slot is held for a rejoin");
+
+        assert!(control.abandon(peer(3)));
+        assert!(control.is_decided());
+        assert_eq!(control.active_players().collect::<Vec<_>>(), [&peer(1)]);
+        assert_eq!(control.forfeited.len(), 2);
+    }
+
+    #[test]
+    fn forfeiting_freezes_our_own_input() {
+        let mut control = three_player_match();
+        control.apply(peer(2), &MatchMessage::Forfeit);
+        assert!(!control.freezes_local_input());
+        control.apply(peer(1), &MatchMessage::Forfeit);
+        assert!(control.freezes_local_input());
+        assert_eq!(control.apply(peer(1), &MatchMessage::Forfeit), None);
+    }
+
+    #[test]
+    fn outsiders_are_ignored() {
+        let mut control = three_player_match();
+        assert_eq!(vote(&mut control, 9, true), None);
+        let chat = MatchMessage::Chat { text: "hi".into() };
+        assert_eq!(control.apply(peer(9), &chat), None);
+    }
+
+    #[test]
+    fn spectators_only_chat() {
+        let players = [peer(1), peer(2)];
+        let mut control = MatchControl::new(peer(1), &players).with_spectators(peer(1), &[peer(7)]);
+        assert_eq!(control.fed_spectators(), vec![peer(7)]);
+        assert_eq!(vote(&mut control, 7, true), None);
+        assert_eq!(control.apply(peer(7), &MatchMessage::Forfeit), None);
+        let chat = MatchMessage::Chat { text: "gg".into() };
+        assert_eq!(control.apply(peer(7), &chat), Some(MatchEvent::Chat("gg".into())));
+
+        // Only the broadcaster feeds them, and they have no seat to lose
+        let watcher = MatchControl::new(peer(7), &players).with_spectators(peer(1), &[peer(7)]);
+        assert!(watcher.is_spectating() && watcher.fed_spectators().is_empty());
+        let mut guest = MatchControl::new(peer(2), &players).with_spectators(peer(1), &[peer(7)]);
+        assert!(guest.fed_spectators().is_empty());
+        guest.disconnect(peer(1));
+        assert!(guest.lost_everyone() && !watcher.lost_everyone());
+    }
+
+    #[test]
+    fn abandoning_needs_a_disconnect_first() {
+        let mut control = three_player_match().with_rejoin_tokens(&[(peer(2), 22)]);
+        assert!(!control.abandon(peer(2)));
+        control.disconnect(peer(2));
+        assert!(control.can_rejoin(peer(2), 22));
+        assert!(control.abandon(peer(2)));
+        assert!(!control.abandon(peer(2)));
+        assert!(!control.can_rejoin(peer(2), 22));
+    }
+
+    #[test]
+    fn only_a_dropped_player_with_their_token_gets_their_slot() {
+        let tokens = [(peer(1), 11), (peer(2), 22), (peer(3), 33)];
+        let mut control = three_player_match().with_rejoin_tokens(&tokens);
+        assert!(!control.can_rejoin(peer(2), 22), "still connected");
+
+        control.disconnect(peer(2));
+        assert!(!control.can_rejoin(peer(2), 33));
+        assert!(!control.can_rejoin(peer(9), 22));
+        assert!(control.can_rejoin(peer(2), 22));
+
+        // A spectator's match has no tokens to check against
+        let watcher = MatchControl::new(peer(7), &[peer(1), peer(2)]);
+        assert_eq!(watcher.rejoin_token(), None);
+    }
+
+    #[test]
+    fn losing_everyone_else_looks_like_our_connection() {
+        let mut control = three_player_match();
+        control.disconnect(peer(2));
+        assert!(!control.lost_everyone());
+        control.disconnect(peer(3));
+        assert!(control.lost_everyone());
+
+        // A forfeited player leaving doesn't count either way
+        let mut control = three_player_match();
+        control.apply(peer(3), &MatchMessage::Forfeit);
+        control.disconnect(peer(2));
+        assert!(control.lost_everyone());
+    }
+
+    #[test]
+    fn lowest_present_peer_answers_a_rejoin() {
+        let mut control = MatchControl::new(peer(2), &[peer(1), peer(2), peer(3)]);
+        assert_eq!(control.resync_authority(peer(1)), Some(peer(2)));
+        assert_eq!(control.resync_authority(peer(3)), Some(peer(1)));
+        control.disconnect(peer(1));
+        assert_eq!(control.resync_authority(peer(3)), Some(peer(2)));
+    }
+
+    #[test]
+    fn rejoining_swaps_the_peer_and_reassigns_handles() {
+        let mut control = three_player_match();
+        control.disconnect(peer(1));
+        control.disconnect(peer(2));
+        control.apply(peer(3), &MatchMessage::PauseVote { pause: true });
+
+        let roster = control.rejoined_roster(peer(1), peer(9));
+        assert_eq!(roster.players, vec![peer(9), peer(2), peer(3)]);
+        // Nobody's waiting on 2 once the new session starts without them
+        assert_eq!(roster.disconnected, vec![peer(2)]);
+        assert_eq!(roster.forfeited, vec![peer(2)]);
+
+        control.adopt(&roster);
+        assert_eq!(control.handles, vec![peer(3), peer(9)]);
+        assert_eq!(control.handle_of(peer(9)), Some(1));
+        assert_eq!(control.handle_of(peer(1)), None);
+        assert!(control.pause_votes.is_empty());
+        assert!(!control.is_decided());
+
+        let mut control = three_player_match().with_rejoin_tokens(&[(peer(1), 11)]);
+        assert_eq!(control.rejoin_token(), Some(11));
+        control.pass_on_rejoin_token(peer(1), peer(9));
+        assert_eq!(control.rejoin_tokens.get(&peer(9)), Some(&11));
+        assert!(!control.rejoin_tokens.contains_key(&peer(1)));
+    }
+}
@@ -0,0 +1,735 @@
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+use bevy_matchbox::prelude::{PeerId, PeerState};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::networking::{CONTROL_CHANNEL, GGRS_CHANNEL};
+
+// Where a host listens unless told otherwise. GGRS takes the port after it.
+pub const DEFAULT_DIRECT_PORT: u16 = 7000;
+
+// How often everyone shows they're still there, how soon an unacknowledged
+// control message goes out again, and how long a silent peer lasts
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The most a UDP datagram carries
+const MAX_PACKET_SIZE: usize = 65_507;
+
+// Direct Connect
+// ==============
+//
+// No signaling server and no WebRTC, for LAN parties and local testing.
+// The host listens on a UDP port and everyone else is told its address.
+// A client says hello to the host until the host answers, and from then on
+// the host sends every client the directory several times a second: the id
+// the host gave them, and where every peer in the room can be reached.
+// After that everyone talks to everyone directly, as they would over WebRTC.
+//
+// Each peer has two sockets. GGRS gets a UDP socket of its own (the host's
+// is on the port after the listening port), and `DirectGgrsSocket` lets it
+// use the same peer ids as everything else. The other socket carries
+// the control and probe channels. Control messages are numbered, resent
+// until acknowledged and handed over in order, like the WebRTC reliable
+// channel. Probes take their chances, like the unreliable ones.
+//
+// Peer ids are made from the address the host sees a peer on, so a player
+// who reconnects (from a new port) comes back as a new peer, just as they
+// would through signaling.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum Packet {
+    // Client -> host, until the host answers
+    Hello {
+        ggrs_port: u16,
+    },
+    // Host -> each client, every heartbeat
+    Directory {
+        you: PeerId,
+        peers: Vec<DirectoryEntry>,
+    },
+    // Everyone -> everyone they know
+    Heartbeat,
+    Bye,
+    Control {
+        seq: u64,
+        payload: Vec<u8>,
+    },
+    Ack {
+        next: u64,
+    },
+    Datagram {
+        channel: usize,
+        payload: Vec<u8>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct DirectoryEntry {
+    id: PeerId,
+    control: SocketAddr,
+    ggrs: SocketAddr,
+}
+
+// Where each peer's GGRS socket is, shared with the GGRS session so peers
+// who arrive mid-match (a rejoin) are reachable too
+type GgrsAddresses = Arc<Mutex<HashMap<PeerId, SocketAddr>>>;
+
+// Packets waiting to be read off one channel, with who sent them
+type Inbox = Vec<(PeerId, Box<[u8]>)>;
+
+// Made up from the address the host sees a peer on. An IPv4 peer's id ends
+// in its address and port, so 192.168.1.5:7000 shows up as ...-c0a8-01051b58.
+pub fn peer_id_for(addr: SocketAddr) -> PeerId {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => u128::from(u32::from(ip)),
+        IpAddr::V6(ip) => u128::from(ip),
+    };
+    PeerId(Uuid::from_u128((ip << 16) ^ u128::from(addr.port())))
+}
+
+// The host as typed into the menu: `host:port`, or just `host` for the
+// default port. GGRS's socket only speaks IPv4.
+pub fn resolve_host(address: &str) -> Result<SocketAddr, String> {
+    let address = address.trim();
+    let with_port = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{address}:{DEFAULT_DIRECT_PORT}")
+    };
+    with_port
+        .to_socket_addrs()
+        .map_err(|e| format!("Couldn't find {address}: {e}"))?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| format!("{address} has no IPv4 address"))
+}
+
+// Our address on the LAN, as far as the routing table knows. Connecting a
+// UDP socket sends nothing; it only picks the interface.
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(8, 8, 8, 8), 80)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+enum Role {
+    Host,
+    Client { host: SocketAddr },
+}
+
+struct DirectPeer {
+    control: SocketAddr,
+    last_heard: Instant,
+    reliable: ReliableChannel,
+}
+
+pub struct DirectSocket {
+    socket: UdpSocket,
+    role: Role,
+    id: Option<PeerId>,
+    ggrs: Option<UdpSocket>,
+    ggrs_port: u16,
+    ggrs_addresses: GgrsAddresses,
+    peers: BTreeMap<PeerId, DirectPeer>,
+    // Gone for good; a directory sent before they left doesn't bring them back
+    departed: HashSet<PeerId>,
+    changes: Vec<(PeerId, PeerState)>,
+    inbox: HashMap<usize, Inbox>,
+    last_heartbeat: Option<Instant>,
+    last_resend: Instant,
+    buffer: Vec<u8>,
+}
+
+impl DirectSocket {
+    // Listens for players on `port` and for GGRS on `port + 1`
+    pub fn host(port: u16) -> io::Result<Self> {
+        let ggrs_port = port.checked_add(1).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "no port left after it for GGRS")
+        })?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let ggrs = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, ggrs_port))?;
+        let mut direct = Self::new(socket, Role::Host, ggrs)?;
+        direct.id = Some(peer_id_for(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))));
+        Ok(direct)
+    }
+
+    // Any free ports will do for a client; the host tells everyone which
+    pub fn join(host: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let ggrs = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        Self::new(socket, Role::Client { host }, ggrs)
+    }
+
+    // Both sockets stay bound from here on, so the GGRS port we tell
+    // everyone is the one we're actually listening on
+    fn new(socket: UdpSocket, role: Role, ggrs: UdpSocket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        ggrs.set_nonblocking(true)?;
+        let ggrs_port = ggrs.local_addr()?.port();
+        Ok(Self {
+            socket,
+            role,
+            id: None,
+            ggrs: Some(ggrs),
+            ggrs_port,
+            ggrs_addresses: default(),
+            peers: BTreeMap::new(),
+            departed: HashSet::new(),
+            changes: Vec::new(),
+            inbox: HashMap::new(),
+            last_heartbeat: None,
+            last_resend: Instant::now(),
+            buffer: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+
+    // None until the host has answered us
+    pub fn id(&self) -> Option<PeerId> {
+        self.id
+    }
+
+    pub fn connected_peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.peers.keys().copied()
+    }
+
+    // Peers who arrived or left since the last call
+    pub fn update_peers(&mut self) -> Vec<(PeerId, PeerState)> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn has_channel(&self, channel: usize) -> bool {
+        channel != GGRS_CHANNEL || self.ggrs.is_some()
+    }
+
+    // Control messages are resent until they arrive, anything else is sent
+    // once. A peer who has already left gets nothing, as over WebRTC.
+    pub fn send(&mut self, channel: usize, packet: Box<[u8]>, peer: PeerId) {
+        let Some(target) = self.peers.get_mut(&peer) else {
+            return;
+        };
+        let payload = packet.into_vec();
+        let packet = if channel == CONTROL_CHANNEL {
+            let seq = target.reliable.push(payload.clone());
+            Packet::Control { seq, payload }
+        } else {
+            Packet::Datagram { channel, payload }
+        };
+        let addr = target.control;
+        self.send_packet(&packet, addr);
+    }
+
+    pub fn receive(&mut self, channel: usize) -> Inbox {
+        self.inbox.remove(&channel).unwrap_or_default()
+    }
+
+    // The GGRS socket, for the session. There's only the one.
+    pub fn take_ggrs(&mut self) -> Option<DirectGgrsSocket> {
+        Some(DirectGgrsSocket {
+            socket: self.ggrs.take()?,
+            addresses: self.ggrs_addresses.clone(),
+            buffer: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+
+    // Reads everything that arrived and sends whatever is due. Called every
+    // frame, see `pump_peer_socket`.
+    pub fn poll(&mut self) {
+        let now = Instant::now();
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows' way of saying an earlier send went nowhere
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    error!("Direct Connect socket failed: {e}");
+                    break;
+                }
+            };
+            match bincode::serde::decode_from_slice(
+                &self.buffer[..len],
+                bincode::config::standard(),
+            ) {
+                Ok((packet, _)) => self.handle(now, from, packet),
+                Err(e) => warn!("Dropping malformed Direct Connect packet from {from}: {e}"),
+            }
+        }
+
+        if self
+            .last_heartbeat
+            .is_none_or(|last| now - last >= HEARTBEAT_INTERVAL)
+        {
+            self.heartbeat();
+            self.last_heartbeat = Some(now);
+        }
+        if now - self.last_resend >= RESEND_INTERVAL {
+            self.resend();
+            self.last_resend = now;
+        }
+
+        let silent = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| now - peer.last_heard > PEER_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in silent {
+            warn!("Lost direct connection to {id}");
+            self.remove_peer(id);
+        }
+    }
+
+    fn handle(&mut self, now: Instant, from: SocketAddr, packet: Packet) {
+        match packet {
+            Packet::Hello { ggrs_port } => self.welcome(now, from, ggrs_port),
+            Packet::Directory { you, peers } => self.read_directory(now, from, you, &peers),
+            packet => self.handle_peer_packet(now, from, packet),
+        }
+    }
+
+    // Host: a client is saying hello, maybe not for the first time
+    fn welcome(&mut self, now: Instant, from: SocketAddr, ggrs_port: u16) {
+        if !matches!(self.role, Role::Host) {
+            return;
+        }
+        let id = peer_id_for(from);
+        if self.departed.contains(&id) {
+            return;
+        }
+        if !self.peers.contains_key(&id) {
+            info!("{from} connected directly as {id}");
+            self.add_peer(now, id, from, SocketAddr::new(from.ip(), ggrs_port));
+        }
+        // Answers right away rather than on the next heartbeat
+        self.send_directories();
+    }
+
+    // Client: who we are, and who else is here
+    fn read_directory(
+        &mut self,
+        now: Instant,
+        from: SocketAddr,
+        you: PeerId,
+        entries: &[DirectoryEntry],
+    ) {
+        let Role::Client { host } = self.role else {
+            return;
+        };
+        if from != host {
+            return;
+        }
+        if self.id.is_none() {
+            info!("Connected directly to {host} as {you}");
+            self.id = Some(you);
+        }
+
+        // The host knows its own machine as 0.0.0.0 or loopback; to us it's
+        // wherever we reach the host
+        let locate = |addr: SocketAddr| {
+            if addr.ip().is_unspecified() || addr.ip().is_loopback() {
+                SocketAddr::new(host.ip(), addr.port())
+            } else {
+                addr
+            }
+        };
+        for entry in entries.iter().filter(|entry| entry.id != you) {
+            if !self.peers.contains_key(&entry.id) && !self.departed.contains(&entry.id) {
+                self.add_peer(now, entry.id, locate(entry.control), locate(entry.ggrs));
+            }
+        }
+        if let Some(host) = self.peers.values_mut().find(|peer| peer.control == host) {
+            host.last_heard = now;
+        }
+
+        let gone = self
+            .peers
+            .keys()
+            .filter(|&&id| !entries.iter().any(|entry| entry.id == id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in gone {
+            self.remove_peer(id);
+        }
+    }
+
+    fn handle_peer_packet(&mut self, now: Instant, from: SocketAddr, packet: Packet) {
+        let Some((&id, peer)) = self.peers.iter_mut().find(|(_, peer)| peer.control == from) else {
+            return; // A stranger, or a peer the directory hasn't told us about yet
+        };
+        peer.last_heard = now;
+
+        match packet {
+            Packet::Control { seq, payload } => {
+                let delivered = peer.reliable.receive(seq, payload);
+                let ack = Packet::Ack {
+                    next: peer.reliable.next_expected,
+                };
+                self.inbox
+                    .entry(CONTROL_CHANNEL)
+                    .or_default()
+                    .extend(delivered.into_iter().map(|payload| (id, payload.into())));
+                self.send_packet(&ack, from);
+            }
+            Packet::Ack { next } => peer.reliable.acknowledge(next),
+            Packet::Datagram { channel, payload } => {
+                self.inbox
+                    .entry(channel)
+                    .or_default()
+                    .push((id, payload.into()));
+            }
+            Packet::Bye => {
+                info!("{id} closed the direct connection");
+                self.remove_peer(id);
+            }
+            Packet::Heartbeat | Packet::Hello { .. } | Packet::Directory { .. } => {}
+        }
+    }
+
+    fn add_peer(&mut self, now: Instant, id: PeerId, control: SocketAddr, ggrs: SocketAddr) {
+        self.peers.insert(
+            id,
+            DirectPeer {
+                control,
+                last_heard: now,
+                reliable: ReliableChannel::default(),
+            },
+        );
+        self.ggrs_addresses.lock().unwrap().insert(id, ggrs);
+        self.changes.push((id, PeerState::Connected));
+    }
+
+    fn remove_peer(&mut self, id: PeerId) {
+        if self.peers.remove(&id).is_some() {
+            self.departed.insert(id);
+            self.ggrs_addresses.lock().unwrap().remove(&id);
+            self.changes.push((id, PeerState::Disconnected));
+        }
+    }
+
+    fn heartbeat(&mut self) {
+        match self.role {
+            Role::Host => self.send_directories(),
+            Role::Client { host } if self.id.is_none() => {
+                let hello = Packet::Hello {
+                    ggrs_port: self.ggrs_port,
+                };
+                self.send_packet(&hello, host);
+            }
+            Role::Client { .. } => self.send_to_everyone(&Packet::Heartbeat),
+        }
+    }
+
+    // Host: every client gets everyone, themselves included so they learn
+    // their id
+    fn send_directories(&self) {
+        let (Some(id), Ok(local)) = (self.id, self.socket.local_addr()) else {
+            return;
+        };
+        let mut entries = vec![DirectoryEntry {
+            id,
+            control: local,
+            ggrs: SocketAddr::new(local.ip(), self.ggrs_port),
+        }];
+        let ggrs_addresses = self.ggrs_addresses.lock().unwrap();
+        for (&id, peer) in &self.peers {
+            if let Some(&ggrs) = ggrs_addresses.get(&id) {
+                entries.push(DirectoryEntry {
+                    id,
+                    control: peer.control,
+                    ggrs,
+                });
+            }
+        }
+        drop(ggrs_addresses);
+
+        for (&you, peer) in &self.peers {
+            let directory = Packet::Directory {
+                you,
+                peers: entries.clone(),
+            };
+            self.send_packet(&directory, peer.control);
+        }
+    }
+
+    fn resend(&self) {
+        for peer in self.peers.values() {
+            for (&seq, payload) in &peer.reliable.unacked {
+                let packet = Packet::Control {
+                    seq,
+                    payload: payload.clone(),
+                };
+                self.send_packet(&packet, peer.control);
+            }
+        }
+    }
+
+    fn send_to_everyone(&self, packet: &Packet) {
+        for peer in self.peers.values() {
+            self.send_packet(packet, peer.control);
+        }
+    }
+
+    fn send_packet(&self, packet: &Packet, addr: SocketAddr) {
+        let bytes = bincode::serde::encode_to_vec(packet, bincode::config::standard())
+            .expect("Direct Connect packets always serialize");
+        if bytes.len() > MAX_PACKET_SIZE {
+            error!("Can't send {} bytes to {addr} in one datagram", bytes.len());
+            return;
+        }
+        match self.socket.send_to(&bytes, addr) {
+            Ok(_) => {}
+            // Control messages go again, anything else wasn't going to be missed
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => warn!("Couldn't send to {addr}: {e}"),
+        }
+    }
+}
+
+// Everyone hears we've gone now instead of after the timeout
+impl Drop for DirectSocket {
+    fn drop(&mut self) {
+        self.send_to_everyone(&Packet::Bye);
+    }
+}
+
+// Reliable Channel
+// ================
+//
+// Both directions of the control channel with one peer. Outgoing messages
+// are kept until the peer acknowledges them; incoming ones that overtook an
+// earlier message wait until it turns up, so they're handed over in order.
+// Acks are cumulative: "everything before `next` arrived".
+#[derive(Debug, Default)]
+struct ReliableChannel {
+    unacked: BTreeMap<u64, Vec<u8>>,
+    next_seq: u64,
+    early: BTreeMap<u64, Vec<u8>>,
+    next_expected: u64,
+}
+
+impl ReliableChannel {
+    // Numbers a message for sending and keeps it until it's acknowledged
+    fn push(&mut self, payload: Vec<u8>) -> u64 {
+        let seq = self.next_seq;
+        self.unacked.insert(seq, payload);
+        self.next_seq += 1;
+        seq
+    }
+
+    // Everything that can be handed over now `seq` has arrived, in order.
+    // Repeats of messages already handed over are dropped.
+    fn receive(&mut self, seq: u64, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if seq >= self.next_expected {
+            self.early.insert(seq, payload);
+        }
+        let mut delivered = Vec::new();
+        while let Some(payload) = self.early.remove(&self.next_expected) {
+            delivered.push(payload);
+            self.next_expected += 1;
+        }
+        delivered
+    }
+
+    fn acknowledge(&mut self, next: u64) {
+        self.unacked = self.unacked.split_off(&next);
+    }
+}
+
+// GGRS's own UDP socket, speaking in peer ids like every other GGRS socket
+// here. It only moves bytes: `GgrsChannel` encodes the messages, the same
+// way it does over WebRTC. Packets to or from an address no peer has are
+// dropped.
+pub struct DirectGgrsSocket {
+    socket: UdpSocket,
+    addresses: GgrsAddresses,
+    buffer: Vec<u8>,
+}
+
+impl DirectGgrsSocket {
+    pub fn send(&self, packet: &[u8], peer: PeerId) {
+        let Some(target) = self.addresses.lock().unwrap().get(&peer).copied() else {
+            return;
+        };
+        match self.socket.send_to(packet, target) {
+            Ok(_) => {}
+            // GGRS sends again whatever goes missing
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => warn!("Couldn't send a GGRS packet to {target}: {e}"),
+        }
+    }
+
+    pub fn receive(&mut self) -> Inbox {
+        let mut packets = Vec::new();
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows' way of saying an earlier send went nowhere
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    error!("Direct Connect GGRS socket failed: {e}");
+                    break;
+                }
+            };
+            let addresses = self.addresses.lock().unwrap();
+            if let Some((&peer, _)) = addresses.iter().find(|(_, &addr)| addr == from) {
+                packets.push((peer, self.buffer[..len].into()));
+            }
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_ids_spell_out_the_address() {
+        let addr = SocketAddr::from((Ipv4Addr::new(192, 168, 1, 5), 7000));
+        assert_eq!(
+            peer_id_for(addr).0.to_string(),
+            "00000000-0000-0000-0000-c0a801051b58"
+        );
+        assert_ne!(
+            peer_id_for(addr),
+            peer_id_for(SocketAddr::from((Ipv4Addr::new(192, 168, 1, 5), 7001)))
+        );
+    }
+
+    #[test]
+    fn host_addresses_get_the_default_port() {
+        assert_eq!(
+            resolve_host(" 127.0.0.1 "),
+            Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_DIRECT_PORT)))
+        );
+        assert_eq!(
+            resolve_host("127.0.0.1:7100"),
+            Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 7100)))
+        );
+        assert!(resolve_host("127.0.0.1:port").is_err());
+    }
+
+    #[test]
+    fn control_messages_are_handed_over_once_and_in_order() {
+        let mut sender = ReliableChannel::default();
+        let mut receiver = ReliableChannel::default();
+        let sent = (0..3u8)
+            .map(|n| (sender.push(vec![n]), vec![n]))
+            .collect::<Vec<_>>();
+
+        // The last one overtakes, the first one turns up twice
+        assert!(receiver.receive(sent[2].0, sent[2].1.clone()).is_empty());
+        assert_eq!(receiver.receive(sent[0].0, sent[0].1.clone()), [vec![0]]);
+        assert!(receiver.receive(sent[0].0, sent[0].1.clone()).is_empty());
+        assert_eq!(
+            receiver.receive(sent[1].0, sent[1].1.clone()),
+            [vec![1], vec![2]]
+        );
+
+        sender.acknowledge(2);
+        assert_eq!(sender.unacked.keys().collect::<Vec<_>>(), [&2]);
+        sender.acknowledge(receiver.next_expected);
+        assert!(sender.unacked.is_empty());
+    }
+
+    // Polls both sockets until `done`, or gives up after a second
+    fn pump(sockets: &mut [&mut DirectSocket], done: impl Fn(&[&mut DirectSocket]) -> bool) {
+        for _ in 0..200 {
+            for socket in sockets.iter_mut() {
+                socket.poll();
+            }
+            if done(sockets) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("sockets never got there");
+    }
+
+    fn free_port() -> u16 {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn clients_meet_through_the_host() {
+        let port = free_port();
+        let host_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let mut host = DirectSocket::host(port).unwrap();
+        let mut first = DirectSocket::join(host_addr).unwrap();
+        let mut second = DirectSocket::join(host_addr).unwrap();
+
+        pump(&mut [&mut host, &mut first, &mut second], |sockets| {
+            sockets
+                .iter()
+                .all(|socket| socket.connected_peers().count() == 2)
+        });
+        let host_id = host.id().unwrap();
+        let (first_id, second_id) = (first.id().unwrap(), second.id().unwrap());
+        let mut others = vec![host_id, second_id];
+        others.sort();
+        assert_eq!(first.connected_peers().collect::<Vec<_>>(), others);
+        assert_eq!(
+            host.update_peers(),
+            [
+                (first_id, PeerState::Connected),
+                (second_id, PeerState::Connected)
+            ]
+        );
+
+        // Client to client, without the host in between
+        for n in 0..3u8 {
+            first.send(CONTROL_CHANNEL, Box::new([n]), second_id);
+        }
+        pump(&mut [&mut host, &mut first, &mut second], |sockets| {
+            sockets[2]
+                .inbox
+                .get(&CONTROL_CHANNEL)
+                .is_some_and(|inbox| inbox.len() == 3)
+        });
+        let received = second.receive(CONTROL_CHANNEL);
+        assert!(received.iter().all(|(from, _)| *from == first_id));
+        assert_eq!(
+            received
+                .into_iter()
+                .map(|(_, packet)| packet[0])
+                .collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+
+        // GGRS packets go straight between the two GGRS sockets
+        let first_ggrs = first.take_ggrs().unwrap();
+        let mut second_ggrs = second.take_ggrs().unwrap();
+        assert_eq!(first_ggrs.socket.local_addr().unwrap().port(), first.ggrs_port);
+        first_ggrs.send(&[7], second_id);
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            received.extend(second_ggrs.receive());
+            if !received.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(received, [(first_id, Box::from([7]))]);
+
+        // Leaving tells everyone straight away
+        drop(first);
+        second.update_peers();
+        pump(&mut [&mut host, &mut second], |sockets| {
+            sockets
+                .iter()
+                .all(|socket| socket.connected_peers().count() == 1)
+        });
+        assert_eq!(second.update_peers(), [(first_id, PeerState::Disconnected)]);
+    }
+}
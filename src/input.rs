@@ -1,19 +1,30 @@
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
 use avian3d::prelude::*;
+use bevy_egui::input::EguiWantsInput;
+use bevy_ggrs::{ggrs::PlayerHandle, LocalInputs, LocalPlayers};
+use crate::combat::BOW_HEIGHT;
+use crate::control::MatchControl;
 use crate::components::*;
+use crate::networking::{GGRSConfig, PackedInput};
 use crate::{GameState};
-// Temporarily commented out GGRS imports
-// use bevy_ggrs::{ggrs::PlayerHandle, LocalInputs, LocalPlayers};
+
+// Aim point used when the cursor ray never meets the ground (pointing at the sky)
+const MAX_AIM_DISTANCE: f32 = 100.0;
 
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<JumpLatch>()
             .add_systems(Update, (
+                latch_jump,
                 gather_input.run_if(in_state(GameState::SinglePlayer)),
                 process_local_input.run_if(in_state(GameState::SinglePlayer)),
-            ));
+            ).chain());
     }
 }
 
@@ -21,60 +32,189 @@ impl Plugin for InputPlugin {
 // in a single-player context. It's not used for networked games.
 pub fn gather_input(
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut local_input_query: Query<&mut NetworkInput, With<LocalPlayer>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    cursor_aim: CursorAim,
+    mut jump_latch: ResMut<JumpLatch>,
+    mut local_input_query: Query<(&mut NetworkInput, &Transform), With<LocalPlayer>>,
 ) {
-    for mut input in local_input_query.iter_mut() {
-        // Gather movement input
-        let mut movement = Vec2::ZERO;
-        
-        if keyboard_input.pressed(KeyCode::KeyW) {
-            movement.y += 1.0;
-        }
-        if keyboard_input.pressed(KeyCode::KeyS) {
-            movement.y -= 1.0;
-        }
-        if keyboard_input.pressed(KeyCode::KeyA) {
-            movement.x -= 1.0;
-        }
-        if keyboard_input.pressed(KeyCode::KeyD) {
-            movement.x += 1.0;
-        }
-
-        // Normalize movement to prevent faster diagonal movement
-        if movement.length() > 0.0 {
-            movement = movement.normalize();
-        }
-
-        input.movement = movement;
-        input.jump = keyboard_input.just_pressed(KeyCode::Space);
-        input.shoot = keyboard_input.just_pressed(KeyCode::ArrowUp) || 
-                     keyboard_input.pressed(KeyCode::ArrowUp);
+    for (mut input, transform) in local_input_query.iter_mut() {
+        let aim = cursor_aim.from_player(transform.translation);
+        map_controls(&mut input, &keyboard_input, &mouse_input, aim, &mut jump_latch);
     }
 }
 
 // This is for GGRS. It reads the local player's input and returns it
 // for GGRS to handle. This is the real deal for multiplayer.
-// TODO: Implement proper GGRS input system
-/*
 pub fn read_local_inputs(
     mut commands: Commands,
-    keys: Res<ButtonInput<KeyCode>>,
-    local_players: Res<bevy_ggrs::LocalPlayers>,
+    gate: InputGate,
+    (mouse_input, mut jump_latch): (Res<ButtonInput<MouseButton>>, ResMut<JumpLatch>),
+    cursor_aim: CursorAim,
+    local_players: Res<LocalPlayers>,
+    player_query: Query<(&Player, &Transform), With<LocalPlayer>>,
+    mut last_inputs: Local<HashMap<PlayerHandle, NetworkInput>>,
+) {
+    let frozen = gate.frozen();
+    let paused = gate.paused();
+    let keyboard_input = gate.keyboard();
+
+    let mut local_inputs = HashMap::default();
+
+    for handle in &local_players.0 {
+        // Start from the last input we sent so the aim sticks when the cursor leaves the window
+        let mut input = last_inputs.get(handle).copied().unwrap_or_default();
+        let aim = player_query
+            .iter()
+            .find(|(player, _)| player.network_id as usize == *handle)
+            .and_then(|(_, transform)| cursor_aim.from_player(transform.translation));
+        if frozen {
+            // Paused or forfeited: stand still. A drawn bow stays drawn rather
+            // than firing the moment the pause starts.
+            input = NetworkInput {
+                aim: input.aim,
+                shoot: input.shoot,
+                ..default()
+            };
+            // Nor does a press from before the pause jump the moment it ends
+            jump_latch.take();
+        } else {
+            map_controls(&mut input, keyboard_input, &mouse_input, aim, &mut jump_latch);
+        }
+        input.pause = paused;
+
+        last_inputs.insert(*handle, input);
+        local_inputs.insert(*handle, PackedInput::from(input));
+    }
+
+    commands.insert_resource(LocalInputs::<GGRSConfig>(local_inputs));
+}
+
+// Things that stop local controls from reaching the simulation
+#[derive(SystemParam)]
+pub struct InputGate<'w, 's> {
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    no_keys: Local<'s, ButtonInput<KeyCode>>,
+    control: Option<Res<'w, MatchControl>>,
+    egui_input: Option<Res<'w, EguiWantsInput>>,
+}
+
+impl InputGate<'_, '_> {
+    // Paused by vote or forfeited, see `control.rs`
+    pub fn frozen(&self) -> bool {
+        self.control.as_ref().is_some_and(|control| control.freezes_local_input())
+    }
+
+    // Our side of the vote as the simulation sees it, see `networking::agree_on_pause`
+    pub fn paused(&self) -> bool {
+        self.control.as_ref().is_some_and(|control| control.is_paused())
+    }
+
+    // Typing in the chat box shouldn't walk us around
+    pub fn keyboard(&self) -> &ButtonInput<KeyCode> {
+        let typing = self.egui_input.as_ref().is_some_and(|egui_input| egui_input.wants_keyboard_input());
+        if typing { &self.no_keys } else { &self.keyboard_input }
+    }
+}
+
+// A jump press, kept until the next input read picks it up. Inputs are read
+// once per rollback tick, 30 times a second, so a tap that starts and ends
+// between two reads would otherwise never be seen. Only the press counts:
+// keeping Space down doesn't jump again on landing.
+#[derive(Resource, Debug, Default)]
+pub struct JumpLatch(bool);
+
+impl JumpLatch {
+    pub fn press(&mut self) {
+        self.0 = true;
+    }
+
+    // Whether Space went down since the last call
+    pub fn take(&mut self) -> bool {
+        std::mem::take(&mut self.0)
+    }
+}
+
+// Every frame, however often inputs are read
+pub fn latch_jump(gate: InputGate, mut jump_latch: ResMut<JumpLatch>) {
+    if gate.keyboard().just_pressed(KeyCode::Space) {
+        jump_latch.press();
+    }
+}
+
+// The one place keys and buttons are mapped to actions. Both the single-player
+// and the GGRS input paths go through here so they can never drift apart.
+pub fn map_controls(
+    input: &mut NetworkInput,
+    keyboard_input: &ButtonInput<KeyCode>,
+    mouse_input: &ButtonInput<MouseButton>,
+    aim: Option<Vec3>,
+    jump_latch: &mut JumpLatch,
 ) {
-    // Implementation will be added later when GGRS input system is fixed
+    // Gather movement input
+    let mut movement = Vec2::ZERO;
+
+    if keyboard_input.pressed(KeyCode::KeyW) {
+        movement.y += 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyS) {
+        movement.y -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyA) {
+        movement.x -= 1.0;
+    }
+    if keyboard_input.pressed(KeyCode::KeyD) {
+        movement.x += 1.0;
+    }
+
+    // Normalize movement to prevent faster diagonal movement
+    if movement.length() > 0.0 {
+        movement = movement.normalize();
+    }
+
+    input.movement = movement;
+    input.jump = jump_latch.take();
+    input.shoot = mouse_input.pressed(MouseButton::Left);
+    input.dodge = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    // Keep the previous aim if the cursor left the window
+    if let Some(aim) = aim {
+        input.aim = aim;
+    }
+}
+
+// Casts a ray from the main camera through the cursor and returns the
+// direction from the player's bow to where it meets the ground, so arrows
+// fly toward whatever the player is pointing at.
+#[derive(SystemParam)]
+pub struct CursorAim<'w, 's> {
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    camera_query: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<MainCamera>>,
+}
+
+impl CursorAim<'_, '_> {
+    pub fn from_player(&self, player_position: Vec3) -> Option<Vec3> {
+        let window = self.window_query.single().ok()?;
+        let (camera, camera_transform) = self.camera_query.single().ok()?;
+        let cursor = window.cursor_position()?;
+        let ray = camera.viewport_to_world(camera_transform, cursor).ok()?;
+
+        let distance = ray
+            .intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))
+            .unwrap_or(MAX_AIM_DISTANCE);
+        let bow_position = player_position + Vec3::Y * BOW_HEIGHT;
+        (ray.get_point(distance) - bow_position).try_normalize()
+    }
 }
-*/
 
 // This processes the local input for the single-player mode. It directly
 // applies forces to the player character. In multiplayer, the server-side
 // logic (or in our case, GGRS) would handle this.
 pub fn process_local_input(
     input_query: Query<&NetworkInput, With<LocalPlayer>>,
-    mut player_query: Query<(&mut Player, &mut ExternalImpulse, &Transform), With<LocalPlayer>>,
-    time: Res<Time>,
+    mut player_query: Query<(&mut Player, &mut ExternalImpulse), With<LocalPlayer>>,
 ) {
     for input in input_query.iter() {
-        for (mut player, mut impulse, transform) in player_query.iter_mut() {
+        for (mut player, mut impulse) in player_query.iter_mut() {
             let speed = player.movement_speed;
 
             // Apply horizontal movement using impulses (improved version)
@@ -97,4 +237,60 @@ pub fn process_local_input(
             }
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<JumpLatch>();
+        world
+    }
+
+    // One rendered frame: the keyboard as it is now, then latched
+    fn frame(world: &mut World, change: impl FnOnce(&mut ButtonInput<KeyCode>)) {
+        let mut keyboard = world.resource_mut::<ButtonInput<KeyCode>>();
+        keyboard.clear();
+        change(&mut keyboard);
+        world.run_system_once(latch_jump).unwrap();
+    }
+
+    // What the next input read sends
+    fn read(world: &mut World) -> bool {
+        let mut input = NetworkInput::default();
+        let keyboard = world.resource::<ButtonInput<KeyCode>>().clone();
+        let mut jump_latch = world.resource_mut::<JumpLatch>();
+        map_controls(&mut input, &keyboard, &ButtonInput::default(), None, &mut jump_latch);
+        input.jump
+    }
+
+    #[test]
+    fn a_tap_between_two_reads_still_jumps() {
+        let mut world = world();
+        frame(&mut world, |keyboard| keyboard.press(KeyCode::Space));
+        frame(&mut world, |keyboard| keyboard.release(KeyCode::Space));
+        frame(&mut world, |_| {});
+
+        assert!(read(&mut world));
+        assert!(!read(&mut world), "one tap, one jump");
+    }
+
+    #[test]
+    fn holding_space_jumps_once() {
+        let mut world = world();
+        frame(&mut world, |keyboard| keyboard.press(KeyCode::Space));
+        assert!(read(&mut world));
+
+        for _ in 0..3 {
+            frame(&mut world, |_| {});
+            assert!(!read(&mut world));
+        }
+
+        frame(&mut world, |keyboard| keyboard.release(KeyCode::Space));
+        frame(&mut world, |keyboard| keyboard.press(KeyCode::Space));
+        assert!(read(&mut world));
+    }
+}
@@ -5,10 +5,26 @@ pub mod networking;
 pub mod ui;
 pub mod physics;
 pub mod input;
+pub mod combat;
+pub mod cli;
+pub mod synctest;
+pub mod signaling;
+pub mod lobby;
+pub mod control;
+pub mod network_stats;
+pub mod session_events;
+pub mod rejoin;
+pub mod spectator;
+pub mod netsim;
+pub mod direct;
+pub mod advancement;
+pub mod respawn;
+pub mod rules;
 
 // Re-export commonly used items
 pub use components::*;
 pub use resources::*;
+pub use rules::{HouseRules, MatchRules};
 
 // Game state management
 use bevy::prelude::*;
@@ -30,11 +46,26 @@ impl Plugin for RibbonGamePlugin {
         app
             .init_state::<GameState>()
             .add_plugins((
+                rules::RulesPlugin,
                 physics::PhysicsPlugin,
                 input::InputPlugin,
                 networking::NetworkingPlugin,
                 ui::UIPlugin,
                 systems::GameSystemsPlugin,
+                combat::CombatPlugin,
+                advancement::AdvancementPlugin,
+                respawn::RespawnPlugin,
+            ))
+            .add_plugins((
+                synctest::SyncTestPlugin,
+                signaling::SignalingPlugin,
+                lobby::LobbyPlugin,
+                control::ControlPlugin,
+                network_stats::NetworkStatsPlugin,
+                session_events::SessionEventsPlugin,
+                rejoin::RejoinPlugin,
+                spectator::SpectatorPlugin,
+                netsim::NetworkSimulatorPlugin,
             ));
     }
 } 
\ No newline at end of file
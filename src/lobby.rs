@@ -0,0 +1,1154 @@
+use bevy::prelude::*;
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::control::{self, ControlMessage, MatchControl, CONTROL_PROTOCOL_VERSION};
+use crate::netsim::NetworkSimulator;
+use crate::networking::{start_p2p_session, start_spectator_session, PeerSocket, CONTROL_CHANNEL};
+use crate::resources::*;
+use crate::rules::{HouseRules, MatchRules};
+use crate::GameState;
+
+// Latecomers beyond this, once every seat is taken, are turned away
+pub const MAX_SPECTATORS: usize = 4;
+
+// Lobby Protocol
+// ==============
+//
+// The host owns the lobby. Everyone else tells the host what they want
+// (their name, their ready flag, a chat line) and the host tells everyone
+// what actually happened by broadcasting the roster and relaying chat.
+// Clients don't know who the host is until a roster arrives, so their
+// requests go to every peer and only the host acts on them.
+//
+// Anyone joining once every seat is taken becomes a spectator. They're in
+// the roster and the chat, but never ready and never given a player
+// handle. When the match starts, the host's P2P session feeds each of them
+// through a GGRS spectator session.
+//
+// Everyone plays by the host's rules. The host sends them to each peer that
+// joins, and every ready flag and the start carry a fingerprint of the rules
+// the sender has. The host won't take a ready from a peer with other rules
+// (it sends its rules again instead), and a client won't start a match the
+// host started with rules it doesn't have.
+//
+// Each player's start also carries everyone's rejoin tokens, so a player
+// who drops can prove who they were when they come back (see `control.rs`).
+// Spectators get a start without them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LobbyMessage {
+    // Any peer -> host
+    Join {
+        name: String,
+    },
+    SetReady {
+        ready: bool,
+        rules: u64, // Fingerprint of the rules we'd play by
+    },
+    Chat {
+        text: String,
+    },
+    // Host -> everyone
+    Roster {
+        max_players: usize,
+        players: Vec<RosterEntry>,
+    },
+    ChatRelay {
+        sender: String,
+        text: String,
+    },
+    Kick {
+        peer: PeerId,
+        reason: String,
+    },
+    Rules {
+        rules: MatchRules,
+    },
+    Start {
+        players: Vec<PeerId>,
+        spectators: Vec<PeerId>,
+        rules: u64,
+        rejoin_tokens: Vec<(PeerId, u64)>, // Players only
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RosterEntry {
+    pub peer: PeerId,
+    pub name: String,
+    pub ready: bool,
+    pub spectator: bool,
+}
+
+// What the lobby UI asks for. Handled by `run_lobby`, which decides whether
+// that means changing the lobby directly (host) or asking the host (client).
+#[derive(Event, Debug, Clone, PartialEq)]
+pub enum LobbyAction {
+    ToggleReady,
+    Chat(String),
+    Kick(PeerId),
+    SetMaxPlayers(usize),
+    Start,
+}
+
+// Who we are and who runs the lobby, once we know, and the rules it plays
+// by: ours when hosting, the host's once they arrive
+#[derive(Resource, Debug, Default)]
+pub struct LobbyPeers {
+    pub local: Option<PeerId>,
+    pub host: Option<PeerId>,
+    pub rules: MatchRules,
+    kicked: HashSet<PeerId>,
+    // Peers we've told the player speak another protocol version
+    mismatched: HashSet<PeerId>,
+}
+
+// Side effects of handling a message or action, applied by `run_lobby`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyEffect {
+    Broadcast(LobbyMessage),
+    // To every peer but these
+    BroadcastExcept(Vec<PeerId>, LobbyMessage),
+    Send(PeerId, LobbyMessage),
+    StartGame {
+        players: Vec<PeerId>,
+        spectators: Vec<PeerId>,
+        rejoin_tokens: Vec<(PeerId, u64)>,
+    },
+    // Watch the match through the host's session
+    Spectate {
+        host: PeerId,
+        players: Vec<PeerId>,
+        spectators: Vec<PeerId>,
+    },
+    Leave(String),
+}
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LobbyPeers>()
+            .init_resource::<HouseRules>()
+            .add_event::<LobbyAction>()
+            .add_systems(OnEnter(GameState::Lobby), reset_lobby_peers)
+            .add_systems(
+                Update,
+                run_lobby.run_if(in_state(GameState::Lobby).and(resource_exists::<PeerSocket>)),
+            );
+    }
+}
+
+fn reset_lobby_peers(mut peers: ResMut<LobbyPeers>, house_rules: Res<HouseRules>) {
+    *peers = LobbyPeers {
+        rules: house_rules.0.clone(),
+        ..default()
+    };
+}
+
+// Pumps the lobby channel: peer arrivals and departures, incoming messages
+// and UI actions all turn into effects, which are then sent or acted on.
+fn run_lobby(
+    mut commands: Commands,
+    mut socket: ResMut<PeerSocket>,
+    mut lobby_state: ResMut<LobbyState>,
+    mut peers: ResMut<LobbyPeers>,
+    mut actions: EventReader<LobbyAction>,
+    (mut connection_info, simulator): (ResMut<ConnectionInfo>, Res<NetworkSimulator>),
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if peers.local.is_none() {
+        let Some(local_id) = socket.id() else {
+            return; // Signaling (or the Direct Connect host) hasn't given us our id yet
+        };
+        claim_local_id(&mut lobby_state, &mut peers, local_id);
+    }
+    if !socket.has_channel(CONTROL_CHANNEL) {
+        return;
+    }
+
+    let mut effects = Vec::new();
+
+    for (peer, state) in socket.update_peers() {
+        effects.extend(match state {
+            PeerState::Connected => on_peer_connected(&lobby_state, peer),
+            PeerState::Disconnected => on_peer_left(&mut lobby_state, &peers, peer),
+        });
+    }
+
+    let received = control::receive_or_mismatch(&mut socket);
+    for (peer, version) in received.mismatches {
+        on_version_mismatch(&mut lobby_state, &mut peers, peer, version);
+    }
+    for (peer, message) in received.messages {
+        // Match messages from a previous match's stragglers are of no use here
+        if let ControlMessage::Lobby(message) = message {
+            effects.extend(handle_message(&mut lobby_state, &mut peers, peer, message));
+        }
+    }
+
+    for action in actions.read() {
+        effects.extend(handle_action(&mut lobby_state, &mut peers, action.clone()));
+    }
+
+    for effect in effects {
+        match effect {
+            LobbyEffect::Broadcast(message) => {
+                control::broadcast(&mut socket, ControlMessage::Lobby(message))
+            }
+            LobbyEffect::BroadcastExcept(except, message) => {
+                for peer in socket.connected_peers() {
+                    if !except.contains(&peer) {
+                        let message = ControlMessage::Lobby(message.clone());
+                        control::send(&mut socket, peer, message);
+                    }
+                }
+            }
+            LobbyEffect::Send(peer, message) => {
+                control::send(&mut socket, peer, ControlMessage::Lobby(message))
+            }
+            LobbyEffect::StartGame {
+                players,
+                spectators,
+                rejoin_tokens,
+            } => {
+                let (Some(local), Some(host)) = (peers.local, peers.host) else {
+                    continue;
+                };
+                let control = MatchControl::new(local, &players)
+                    .with_spectators(host, &spectators)
+                    .with_rejoin_tokens(&rejoin_tokens);
+                let spectators_fed = control.fed_spectators();
+                match start_p2p_session(
+                    &mut socket,
+                    &players,
+                    &spectators_fed,
+                    &simulator,
+                    &peers.rules,
+                ) {
+                    Ok((session, channel)) => {
+                        info!(
+                            "Starting match with {} players and {} spectators",
+                            players.len(),
+                            spectators.len()
+                        );
+                        lobby_state.game_started = true;
+                        lobby_state.max_players = players.len();
+                        commands.insert_resource(peers.rules.clone());
+                        commands.insert_resource(control);
+                        commands.insert_resource(session);
+                        commands.insert_resource(channel);
+                        game_state.set(GameState::InGame);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to start GGRS session: {e}");
+                        lobby_state.push_system_message(&format!("Failed to start the game: {e}"));
+                    }
+                }
+            }
+            LobbyEffect::Spectate {
+                host,
+                players,
+                spectators,
+            } => match start_spectator_session(
+                &mut socket,
+                host,
+                players.len(),
+                &simulator,
+                &peers.rules,
+            ) {
+                Ok((session, channel)) => {
+                    info!("Spectating {} players through {host}", players.len());
+                    lobby_state.game_started = true;
+                    commands.insert_resource(peers.rules.clone());
+                    if let Some(local) = peers.local {
+                        commands.insert_resource(
+                            MatchControl::new(local, &players).with_spectators(host, &spectators),
+                        );
+                    }
+                    commands.insert_resource(session);
+                    commands.insert_resource(channel);
+                    game_state.set(GameState::InGame);
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to start GGRS spectator session: {e}");
+                    lobby_state.push_system_message(&format!("Failed to start spectating: {e}"));
+                }
+            },
+            LobbyEffect::Leave(reason) => {
+                connection_info.connection_error = Some(reason);
+                lobby_state.room_id = None;
+                lobby_state.players.clear();
+                lobby_state.player_count = 0;
+                lobby_state.is_host = false;
+                game_state.set(GameState::MainMenu);
+                return;
+            }
+        }
+    }
+}
+
+// The lobby UI put us in the player list before we had a peer id
+fn claim_local_id(lobby_state: &mut LobbyState, peers: &mut LobbyPeers, local_id: PeerId) {
+    peers.local = Some(local_id);
+    if lobby_state.is_host {
+        peers.host = Some(local_id);
+    }
+    for player in lobby_state
+        .players
+        .iter_mut()
+        .filter(|player| player.is_local)
+    {
+        player.id = local_id.0;
+    }
+}
+
+fn local_name(lobby_state: &LobbyState) -> String {
+    lobby_state
+        .players
+        .iter()
+        .find(|player| player.is_local)
+        .map(|player| player.name.clone())
+        .unwrap_or_else(|| lobby_state.local_player_name.clone())
+}
+
+// Everyone with a seat, as opposed to spectators
+fn seated(lobby_state: &LobbyState) -> impl Iterator<Item = &LobbyPlayer> {
+    lobby_state
+        .players
+        .iter()
+        .filter(|player| !player.is_spectator)
+}
+
+fn all_ready(lobby_state: &LobbyState) -> bool {
+    seated(lobby_state).count() >= 2 && seated(lobby_state).all(|player| player.is_ready)
+}
+
+fn roster(lobby_state: &LobbyState) -> LobbyMessage {
+    LobbyMessage::Roster {
+        max_players: lobby_state.max_players,
+        players: lobby_state
+            .players
+            .iter()
+            .map(|player| RosterEntry {
+                peer: PeerId(player.id),
+                name: player.name.clone(),
+                ready: player.is_ready,
+                spectator: player.is_spectator,
+            })
+            .collect(),
+    }
+}
+
+fn find_player(lobby_state: &mut LobbyState, peer: PeerId) -> Option<&mut LobbyPlayer> {
+    lobby_state
+        .players
+        .iter_mut()
+        .find(|player| player.id == peer.0)
+}
+
+fn remove_player(lobby_state: &mut LobbyState, peer: PeerId) -> Option<LobbyPlayer> {
+    let index = lobby_state
+        .players
+        .iter()
+        .position(|player| player.id == peer.0)?;
+    let player = lobby_state.players.remove(index);
+    fill_seats(lobby_state);
+    Some(player)
+}
+
+// Spectators take any free seats, longest waiting first
+fn fill_seats(lobby_state: &mut LobbyState) {
+    let mut free = lobby_state
+        .max_players
+        .saturating_sub(seated(lobby_state).count());
+    let mut seated_now = Vec::new();
+    for player in lobby_state
+        .players
+        .iter_mut()
+        .filter(|player| player.is_spectator)
+    {
+        if free == 0 {
+            break;
+        }
+        player.is_spectator = false;
+        seated_now.push(player.name.clone());
+        free -= 1;
+    }
+    for name in seated_now {
+        lobby_state.push_system_message(&format!("{name} took a free seat"));
+    }
+    renumber(lobby_state);
+}
+
+fn renumber(lobby_state: &mut LobbyState) {
+    let mut handle = 0;
+    for player in lobby_state.players.iter_mut() {
+        player.network_handle = handle;
+        if !player.is_spectator {
+            handle += 1;
+        }
+    }
+    lobby_state.player_count = handle as usize;
+}
+
+// Introduce ourselves to every new peer; one of them may be the host
+pub fn on_peer_connected(lobby_state: &LobbyState, peer: PeerId) -> Vec<LobbyEffect> {
+    vec![LobbyEffect::Send(
+        peer,
+        LobbyMessage::Join {
+            name: local_name(lobby_state),
+        },
+    )]
+}
+
+pub fn on_peer_left(
+    lobby_state: &mut LobbyState,
+    peers: &LobbyPeers,
+    peer: PeerId,
+) -> Vec<LobbyEffect> {
+    if peers.host == Some(peer) && !lobby_state.is_host {
+        return vec![LobbyEffect::Leave("The host left the lobby".to_string())];
+    }
+    if !lobby_state.is_host {
+        return Vec::new(); // The host will send a fresh roster
+    }
+
+    match remove_player(lobby_state, peer) {
+        Some(player) => {
+            lobby_state.push_system_message(&format!("{} left the lobby", player.name));
+            vec![LobbyEffect::Broadcast(roster(lobby_state))]
+        }
+        None => Vec::new(),
+    }
+}
+
+// Nothing a peer on another protocol version sends can be understood, so
+// the lobby would just never fill. Say so once, naming both versions.
+pub fn on_version_mismatch(
+    lobby_state: &mut LobbyState,
+    peers: &mut LobbyPeers,
+    peer: PeerId,
+    version: u16,
+) {
+    if !peers.mismatched.insert(peer) {
+        return;
+    }
+    // Their Join never got through, so all we know them by is their id
+    lobby_state.push_system_message(&format!(
+        "Peer {peer} speaks lobby protocol v{version}, we speak v{CONTROL_PROTOCOL_VERSION}. \
+         One of you needs to update."
+    ));
+}
+
+pub fn handle_message(
+    lobby_state: &mut LobbyState,
+    peers: &mut LobbyPeers,
+    from: PeerId,
+    message: LobbyMessage,
+) -> Vec<LobbyEffect> {
+    if lobby_state.is_host {
+        host_handle_message(lobby_state, peers, from, message)
+    } else {
+        client_handle_message(lobby_state, peers, from, message)
+    }
+}
+
+fn host_handle_message(
+    lobby_state: &mut LobbyState,
+    peers: &mut LobbyPeers,
+    from: PeerId,
+    message: LobbyMessage,
+) -> Vec<LobbyEffect> {
+    if peers.kicked.contains(&from) {
+        return vec![LobbyEffect::Send(
+            from,
+            LobbyMessage::Kick {
+                peer: from,
+                reason: "You were kicked from the lobby".to_string(),
+            },
+        )];
+    }
+
+    match message {
+        LobbyMessage::Join { name } => {
+            let spectators = lobby_state
+                .players
+                .iter()
+                .filter(|player| player.is_spectator)
+                .count();
+            if let Some(player) = find_player(lobby_state, from) {
+                player.name = name;
+            } else if lobby_state.game_started || spectators >= MAX_SPECTATORS {
+                return vec![LobbyEffect::Send(
+                    from,
+                    LobbyMessage::Kick {
+                        peer: from,
+                        reason: "The lobby is full".to_string(),
+                    },
+                )];
+            } else {
+                let is_spectator = lobby_state.player_count >= lobby_state.max_players;
+                lobby_state.players.push(LobbyPlayer {
+                    id: from.0,
+                    name: name.clone(),
+                    is_ready: false,
+                    network_handle: 0,
+                    is_local: false,
+                    is_spectator,
+                });
+                renumber(lobby_state);
+                let joined = if is_spectator {
+                    "joined as a spectator"
+                } else {
+                    "joined the lobby"
+                };
+                lobby_state.push_system_message(&format!("{name} {joined}"));
+            }
+            vec![
+                LobbyEffect::Broadcast(roster(lobby_state)),
+                LobbyEffect::Send(
+                    from,
+                    LobbyMessage::Rules {
+                        rules: peers.rules.clone(),
+                    },
+                ),
+            ]
+        }
+        LobbyMessage::SetReady { ready, rules } => match find_player(lobby_state, from) {
+            Some(player) if !player.is_spectator && ready && rules != peers.rules.fingerprint() => {
+                let name = player.name.clone();
+                lobby_state.push_system_message(&format!(
+                    "{name} can't ready up with different rules, sending ours again"
+                ));
+                vec![LobbyEffect::Send(
+                    from,
+                    LobbyMessage::Rules {
+                        rules: peers.rules.clone(),
+                    },
+                )]
+            }
+            Some(player) if !player.is_spectator && player.is_ready != ready => {
+                player.is_ready = ready;
+                let name = player.name.clone();
+                let status = if ready { "ready" } else { "not ready" };
+                lobby_state.push_system_message(&format!("{name} is now {status}"));
+                vec![LobbyEffect::Broadcast(roster(lobby_state))]
+            }
+            _ => Vec::new(),
+        },
+        LobbyMessage::Chat { text } => match find_player(lobby_state, from) {
+            Some(player) => {
+                let sender = player.name.clone();
+                lobby_state.push_chat_message(&sender, &text);
+                vec![LobbyEffect::Broadcast(LobbyMessage::ChatRelay {
+                    sender,
+                    text,
+                })]
+            }
+            None => Vec::new(),
+        },
+        // Only a host sends these, and there's only one of us
+        other => {
+            warn!("Host ignoring unexpected lobby message from {from}: {other:?}");
+            Vec::new()
+        }
+    }
+}
+
+fn client_handle_message(
+    lobby_state: &mut LobbyState,
+    peers: &mut LobbyPeers,
+    from: PeerId,
+    message: LobbyMessage,
+) -> Vec<LobbyEffect> {
+    match message {
+        // Requests meant for the host; we aren't it
+        LobbyMessage::Join { .. } | LobbyMessage::SetReady { .. } | LobbyMessage::Chat { .. } => {
+            Vec::new()
+        }
+        // Whoever sends the first roster is the host from then on
+        _ if peers.host.is_some_and(|host| host != from) => {
+            warn!("Ignoring host message from {from}, who isn't the host");
+            Vec::new()
+        }
+        LobbyMessage::Roster {
+            max_players,
+            players,
+        } => {
+            peers.host = Some(from);
+            lobby_state.max_players = max_players;
+            lobby_state.players = players
+                .into_iter()
+                .map(|entry| LobbyPlayer {
+                    id: entry.peer.0,
+                    name: entry.name,
+                    is_ready: entry.ready,
+                    network_handle: 0,
+                    is_local: Some(entry.peer) == peers.local,
+                    is_spectator: entry.spectator,
+                })
+                .collect();
+            renumber(lobby_state);
+            Vec::new()
+        }
+        LobbyMessage::ChatRelay { sender, text } => {
+            lobby_state.push_chat_message(&sender, &text);
+            Vec::new()
+        }
+        LobbyMessage::Rules { rules } => {
+            peers.rules = rules;
+            Vec::new()
+        }
+        LobbyMessage::Kick { peer, reason } => {
+            if Some(peer) == peers.local {
+                vec![LobbyEffect::Leave(reason)]
+            } else {
+                if let Some(player) = lobby_state
+                    .players
+                    .iter()
+                    .find(|player| player.id == peer.0)
+                {
+                    let name = player.name.clone();
+                    lobby_state.push_system_message(&format!("{name} was kicked from the lobby"));
+                }
+                Vec::new()
+            }
+        }
+        LobbyMessage::Start {
+            players,
+            spectators,
+            rules,
+            rejoin_tokens,
+        } => {
+            if rules != peers.rules.fingerprint() {
+                vec![LobbyEffect::Leave(
+                    "The host started the game with different rules".to_string(),
+                )]
+            } else if peers.local.is_some_and(|local| players.contains(&local)) {
+                vec![LobbyEffect::StartGame {
+                    players,
+                    spectators,
+                    rejoin_tokens,
+                }]
+            } else if peers.local.is_some_and(|local| spectators.contains(&local)) {
+                vec![LobbyEffect::Spectate {
+                    host: from,
+                    players,
+                    spectators,
+                }]
+            } else {
+                vec![LobbyEffect::Leave(
+                    "The game started without you".to_string(),
+                )]
+            }
+        }
+    }
+}
+
+pub fn handle_action(
+    lobby_state: &mut LobbyState,
+    peers: &mut LobbyPeers,
+    action: LobbyAction,
+) -> Vec<LobbyEffect> {
+    let Some(local_id) = peers.local else {
+        return Vec::new();
+    };
+
+    if !lobby_state.is_host {
+        // Clients only ask; the roster coming back is the answer
+        return match action {
+            LobbyAction::ToggleReady => {
+                let ready = lobby_state
+                    .players
+                    .iter()
+                    .any(|player| player.is_local && player.is_ready);
+                vec![LobbyEffect::Broadcast(LobbyMessage::SetReady {
+                    ready: !ready,
+                    rules: peers.rules.fingerprint(),
+                })]
+            }
+            LobbyAction::Chat(text) => vec![LobbyEffect::Broadcast(LobbyMessage::Chat { text })],
+            LobbyAction::Kick(_) | LobbyAction::SetMaxPlayers(_) | LobbyAction::Start => Vec::new(),
+        };
+    }
+
+    match action {
+        LobbyAction::ToggleReady => {
+            let ready = !lobby_state
+                .players
+                .iter()
+                .any(|player| player.is_local && player.is_ready);
+            let rules = peers.rules.fingerprint();
+            host_handle_message(
+                lobby_state,
+                peers,
+                local_id,
+                LobbyMessage::SetReady { ready, rules },
+            )
+        }
+        LobbyAction::Chat(text) => {
+            host_handle_message(lobby_state, peers, local_id, LobbyMessage::Chat { text })
+        }
+        LobbyAction::Kick(peer) if peer != local_id => {
+            let Some(player) = remove_player(lobby_state, peer) else {
+                return Vec::new();
+            };
+            peers.kicked.insert(peer);
+            lobby_state.push_system_message(&format!("{} was kicked from the lobby", player.name));
+            vec![
+                LobbyEffect::Broadcast(LobbyMessage::Kick {
+                    peer,
+                    reason: "You were kicked from the lobby".to_string(),
+                }),
+                LobbyEffect::Broadcast(roster(lobby_state)),
+            ]
+        }
+        LobbyAction::Kick(_) => Vec::new(),
+        LobbyAction::SetMaxPlayers(max_players) => {
+            // Never below the players already seated
+            lobby_state.max_players = max_players.max(lobby_state.player_count);
+            fill_seats(lobby_state);
+            vec![LobbyEffect::Broadcast(roster(lobby_state))]
+        }
+        LobbyAction::Start => {
+            if lobby_state.game_started || !all_ready(lobby_state) {
+                return Vec::new();
+            }
+            let (spectators, players): (Vec<_>, Vec<_>) = lobby_state
+                .players
+                .iter()
+                .partition(|player| player.is_spectator);
+            let ids = |players: Vec<&LobbyPlayer>| {
+                players
+                    .into_iter()
+                    .map(|player| PeerId(player.id))
+                    .collect::<Vec<_>>()
+            };
+            let (players, spectators) = (ids(players), ids(spectators));
+            let rejoin_tokens = players
+                .iter()
+                .map(|&peer| (peer, rand::random()))
+                .collect::<Vec<_>>();
+            lobby_state.push_system_message("Host is starting the game...");
+            let start = |rejoin_tokens: &[(PeerId, u64)]| LobbyMessage::Start {
+                players: players.clone(),
+                spectators: spectators.clone(),
+                rules: peers.rules.fingerprint(),
+                rejoin_tokens: rejoin_tokens.to_vec(),
+            };
+            let mut effects = players
+                .iter()
+                .filter(|&&peer| peer != local_id)
+                .map(|&peer| LobbyEffect::Send(peer, start(&rejoin_tokens)))
+                .collect::<Vec<_>>();
+            effects.push(LobbyEffect::BroadcastExcept(players.clone(), start(&[])));
+            effects.push(LobbyEffect::StartGame {
+                players,
+                spectators,
+                rejoin_tokens,
+            });
+            effects
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn peer(n: u128) -> PeerId {
+        PeerId(Uuid::from_u128(n))
+    }
+
+    // A lobby as the UI leaves it right after Create / Join, with our id claimed
+    fn lobby(is_host: bool, local: PeerId) -> (LobbyState, LobbyPeers) {
+        let mut lobby_state = LobbyState {
+            is_host,
+            max_players: 4,
+            ..default()
+        };
+        lobby_state.players.push(LobbyPlayer {
+            id: Uuid::new_v4(),
+            name: if is_host { "Host" } else { "Guest" }.to_string(),
+            is_ready: false,
+            network_handle: 0,
+            is_local: true,
+            is_spectator: false,
+        });
+        let mut peers = LobbyPeers::default();
+        claim_local_id(&mut lobby_state, &mut peers, local);
+        (lobby_state, peers)
+    }
+
+    fn broadcasts(effects: &[LobbyEffect]) -> Vec<&LobbyMessage> {
+        effects
+            .iter()
+            .filter_map(|effect| match effect {
+                LobbyEffect::Broadcast(message) => Some(message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Hands the host's messages for `to` over, as the wire would
+    fn deliver(
+        effects: &[LobbyEffect],
+        to: PeerId,
+        (state, peers): (&mut LobbyState, &mut LobbyPeers),
+    ) -> Vec<LobbyEffect> {
+        effects
+            .iter()
+            .filter_map(|effect| match effect {
+                LobbyEffect::Broadcast(message) => Some(message),
+                LobbyEffect::BroadcastExcept(except, message) if !except.contains(&to) => {
+                    Some(message)
+                }
+                LobbyEffect::Send(peer, message) if *peer == to => Some(message),
+                _ => None,
+            })
+            .flat_map(|message| handle_message(state, peers, peer(1), message.clone()))
+            .collect()
+    }
+
+    // A host with house rules of its own joins a guest
+    fn joined_pair() -> ((LobbyState, LobbyPeers), (LobbyState, LobbyPeers)) {
+        let (host_id, guest_id) = (peer(1), peer(2));
+        let (mut host, mut host_peers) = lobby(true, host_id);
+        host_peers.rules.advancements_to_win = 3;
+        let (mut guest, mut guest_peers) = lobby(false, guest_id);
+
+        let hello = on_peer_connected(&guest, host_id);
+        let LobbyEffect::Send(_, join) = &hello[0] else {
+            panic!()
+        };
+        let effects = handle_message(&mut host, &mut host_peers, guest_id, join.clone());
+        deliver(&effects, guest_id, (&mut guest, &mut guest_peers));
+
+        ((host, host_peers), (guest, guest_peers))
+    }
+
+    #[test]
+    fn joining_syncs_the_roster_to_the_guest() {
+        let ((host, _), (guest, guest_peers)) = joined_pair();
+
+        assert_eq!(guest_peers.host, Some(peer(1)));
+        let names = |state: &LobbyState| {
+            state
+                .players
+                .iter()
+                .map(|p| p.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&host), ["Host", "Guest"]);
+        assert_eq!(names(&guest), names(&host));
+        assert!(guest.players[1].is_local && !guest.players[0].is_local);
+    }
+
+    #[test]
+    fn guests_take_on_the_host_rules() {
+        let ((_, host_peers), (_, guest_peers)) = joined_pair();
+        assert_eq!(guest_peers.rules.advancements_to_win, 3);
+        assert_eq!(guest_peers.rules, host_peers.rules);
+    }
+
+    #[test]
+    fn ready_with_other_rules_is_refused() {
+        let ((mut host, mut host_peers), _) = joined_pair();
+        let ours = MatchRules {
+            advancements_to_win: 3,
+            ..default()
+        };
+        let effects = handle_message(
+            &mut host,
+            &mut host_peers,
+            peer(2),
+            LobbyMessage::SetReady {
+                ready: true,
+                rules: MatchRules::default().fingerprint(),
+            },
+        );
+        assert!(!host.players[1].is_ready);
+        assert_eq!(
+            effects,
+            [LobbyEffect::Send(
+                peer(2),
+                LobbyMessage::Rules {
+                    rules: ours.clone()
+                }
+            )]
+        );
+
+        handle_message(
+            &mut host,
+            &mut host_peers,
+            peer(2),
+            LobbyMessage::SetReady {
+                ready: true,
+                rules: ours.fingerprint(),
+            },
+        );
+        assert!(host.players[1].is_ready);
+    }
+
+    #[test]
+    fn a_start_with_other_rules_is_refused() {
+        let (_, (mut guest, mut guest_peers)) = joined_pair();
+        let start = LobbyMessage::Start {
+            players: vec![peer(1), peer(2)],
+            spectators: vec![],
+            rules: MatchRules::default().fingerprint(),
+            rejoin_tokens: vec![],
+        };
+        assert!(matches!(
+            handle_message(&mut guest, &mut guest_peers, peer(1), start)[..],
+            [LobbyEffect::Leave(_)]
+        ));
+    }
+
+    #[test]
+    fn guest_ready_goes_through_the_host() {
+        let ((mut host, mut host_peers), (mut guest, mut guest_peers)) = joined_pair();
+
+        let request = handle_action(&mut guest, &mut guest_peers, LobbyAction::ToggleReady);
+        assert!(
+            !guest.players[1].is_ready,
+            "clients wait for the host's roster"
+        );
+
+        let request = broadcasts(&request)[0].clone();
+        let effects = handle_message(&mut host, &mut host_peers, peer(2), request);
+        handle_message(
+            &mut guest,
+            &mut guest_peers,
+            peer(1),
+            broadcasts(&effects)[0].clone(),
+        );
+        assert!(host.players[1].is_ready);
+        assert!(guest.players[1].is_ready);
+    }
+
+    #[test]
+    fn chat_is_relayed_with_the_sender_name() {
+        let ((mut host, mut host_peers), _) = joined_pair();
+        let effects = handle_message(
+            &mut host,
+            &mut host_peers,
+            peer(2),
+            LobbyMessage::Chat { text: "hi".into() },
+        );
+        assert_eq!(
+            broadcasts(&effects),
+            [&LobbyMessage::ChatRelay {
+                sender: "Guest".into(),
+                text: "hi".into()
+            }]
+        );
+        assert_eq!(host.chat_messages.last().unwrap().message, "hi");
+    }
+
+    #[test]
+    fn start_needs_everyone_ready() {
+        let ((mut host, mut host_peers), (mut guest, mut guest_peers)) = joined_pair();
+        assert!(handle_action(&mut host, &mut host_peers, LobbyAction::Start).is_empty());
+
+        handle_action(&mut host, &mut host_peers, LobbyAction::ToggleReady);
+        let rules = host_peers.rules.fingerprint();
+        handle_message(
+            &mut host,
+            &mut host_peers,
+            peer(2),
+            LobbyMessage::SetReady { ready: true, rules },
+        );
+        let effects = handle_action(&mut host, &mut host_peers, LobbyAction::Start);
+        let tokens = start_game(&effects).unwrap();
+        assert_eq!(
+            tokens.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+            [peer(1), peer(2)]
+        );
+
+        let started = deliver(&effects, peer(2), (&mut guest, &mut guest_peers));
+        assert_eq!(start_game(&started), Some(tokens));
+    }
+
+    // Everyone's rejoin tokens, if these effects start a match we play in
+    fn start_game(effects: &[LobbyEffect]) -> Option<Vec<(PeerId, u64)>> {
+        effects.iter().find_map(|effect| match effect {
+            LobbyEffect::StartGame {
+                players,
+                rejoin_tokens,
+                ..
+            } => {
+                assert_eq!(players, &[peer(1), peer(2)]);
+                Some(rejoin_tokens.clone())
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn only_the_host_can_send_host_messages() {
+        let (_, (mut guest, mut guest_peers)) = joined_pair();
+        let fake = LobbyMessage::Start {
+            players: vec![peer(2), peer(3)],
+            spectators: vec![],
+            rules: guest_peers.rules.fingerprint(),
+            rejoin_tokens: vec![],
+        };
+        assert!(handle_message(&mut guest, &mut guest_peers, peer(3), fake).is_empty());
+    }
+
+    #[test]
+    fn kicked_guest_leaves_and_stays_out() {
+        let ((mut host, mut host_peers), (mut guest, mut guest_peers)) = joined_pair();
+        let effects = handle_action(&mut host, &mut host_peers, LobbyAction::Kick(peer(2)));
+        assert_eq!(host.players.len(), 1);
+
+        let kick = broadcasts(&effects)[0].clone();
+        assert!(matches!(
+            handle_message(&mut guest, &mut guest_peers, peer(1), kick)[..],
+            [LobbyEffect::Leave(_)]
+        ));
+
+        let rejoin = handle_message(
+            &mut host,
+            &mut host_peers,
+            peer(2),
+            LobbyMessage::Join {
+                name: "Guest".into(),
+            },
+        );
+        assert!(matches!(
+            rejoin[..],
+            [LobbyEffect::Send(_, LobbyMessage::Kick { .. })]
+        ));
+        assert_eq!(host.players.len(), 1);
+    }
+
+    fn join(host: &mut LobbyState, host_peers: &mut LobbyPeers, n: u128) -> Vec<LobbyEffect> {
+        handle_message(
+            host,
+            host_peers,
+            peer(n),
+            LobbyMessage::Join {
+                name: format!("Late {n}"),
+            },
+        )
+    }
+
+    #[test]
+    fn full_lobby_seats_latecomers_as_spectators() {
+        let ((mut host, mut host_peers), _) = joined_pair();
+        handle_action(&mut host, &mut host_peers, LobbyAction::SetMaxPlayers(2));
+
+        join(&mut host, &mut host_peers, 3);
+        assert_eq!(host.players.len(), 3);
+        assert!(host.players[2].is_spectator);
+        assert_eq!(host.player_count, 2);
+
+        // Spectators can't hold up the start
+        handle_action(&mut host, &mut host_peers, LobbyAction::ToggleReady);
+        let rules = host_peers.rules.fingerprint();
+        for n in [2, 3] {
+            handle_message(
+                &mut host,
+                &mut host_peers,
+                peer(n),
+                LobbyMessage::SetReady { ready: true, rules },
+            );
+        }
+        assert!(!host.players[2].is_ready);
+        let effects = handle_action(&mut host, &mut host_peers, LobbyAction::Start);
+        assert!(effects.iter().any(|effect| matches!(
+            effect,
+            LobbyEffect::StartGame { spectators, .. } if spectators == &[peer(3)]
+        )));
+
+        // The spectator's start doesn't give away anyone's rejoin token
+        let (mut watcher, mut watcher_peers) = lobby(false, peer(3));
+        watcher_peers.host = Some(peer(1));
+        watcher_peers.rules = host_peers.rules.clone();
+        let watching = deliver(&effects, peer(3), (&mut watcher, &mut watcher_peers));
+        assert!(matches!(watching[..], [LobbyEffect::Spectate { .. }]));
+        assert!(effects.iter().all(|effect| match effect {
+            LobbyEffect::Send(to, LobbyMessage::Start { .. }) => *to == peer(2),
+            LobbyEffect::BroadcastExcept(except, LobbyMessage::Start { rejoin_tokens, .. }) => {
+                except.contains(&peer(2)) && rejoin_tokens.is_empty()
+            }
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn spectators_watch_through_the_host() {
+        let ((mut host, mut host_peers), _) = joined_pair();
+        handle_action(&mut host, &mut host_peers, LobbyAction::SetMaxPlayers(2));
+        let effects = join(&mut host, &mut host_peers, 3);
+
+        let (mut watcher, mut watcher_peers) = lobby(false, peer(3));
+        deliver(&effects, peer(3), (&mut watcher, &mut watcher_peers));
+        assert!(watcher.players[2].is_local && watcher.players[2].is_spectator);
+
+        let start = LobbyMessage::Start {
+            players: vec![peer(1), peer(2)],
+            spectators: vec![peer(3)],
+            rules: host_peers.rules.fingerprint(),
+            rejoin_tokens: vec![],
+        };
+        assert_eq!(
+            handle_message(&mut watcher, &mut watcher_peers, peer(1), start),
+            [LobbyEffect::Spectate {
+                host: peer(1),
+                players: vec![peer(1), peer(2)],
+                spectators: vec![peer(3)],
+            }]
+        );
+    }
+
+    #[test]
+    fn free_seats_go_to_spectators_then_the_room_fills_up() {
+        let ((mut host, mut host_peers), _) = joined_pair();
+        handle_action(&mut host, &mut host_peers, LobbyAction::SetMaxPlayers(2));
+        for n in 3..3 + MAX_SPECTATORS as u128 {
+            join(&mut host, &mut host_peers, n);
+        }
+        assert!(matches!(
+            join(&mut host, &mut host_peers, 99)[..],
+            [LobbyEffect::Send(_, LobbyMessage::Kick { .. })]
+        ));
+
+        on_peer_left(&mut host, &host_peers, peer(2));
+        assert_eq!(host.player_count, 2);
+        assert!(!host.players[1].is_spectator);
+        assert_eq!(host.players[1].id, peer(3).0);
+    }
+
+    #[test]
+    fn host_leaving_closes_the_lobby_for_guests() {
+        let ((mut host, host_peers), (mut guest, guest_peers)) = joined_pair();
+        assert!(matches!(
+            on_peer_left(&mut guest, &guest_peers, peer(1))[..],
+            [LobbyEffect::Leave(_)]
+        ));
+
+        on_peer_left(&mut host, &host_peers, peer(2));
+        assert_eq!(host.players.len(), 1);
+    }
+
+    #[test]
+    fn other_protocol_versions_are_named_once() {
+        let (mut guest, mut guest_peers) = lobby(false, peer(2));
+        let version = CONTROL_PROTOCOL_VERSION + 1;
+        on_version_mismatch(&mut guest, &mut guest_peers, peer(1), version);
+        on_version_mismatch(&mut guest, &mut guest_peers, peer(1), version);
+
+        assert_eq!(guest.chat_messages.len(), 1);
+        let message = &guest.chat_messages[0].message;
+        assert!(message.contains(&format!("v{version}")));
+        assert!(message.contains(&format!("v{CONTROL_PROTOCOL_VERSION}")));
+    }
+}
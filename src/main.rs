@@ -1,8 +1,20 @@
 use bevy::prelude::*;
-use avian3d::prelude::*;
-use ribbon_game::RibbonGamePlugin;
+use ribbon_game::{cli::CliArgs, signaling::SignalingServer, RibbonGamePlugin};
 
 fn main() {
+    let args = CliArgs::from_env();
+
+    // Sidecar mode: just the signaling server, no window. The app is only
+    // here so Bevy's log setup is shared with the game.
+    if let Some(addr) = args.signaling_server {
+        App::new().add_plugins(bevy::log::LogPlugin::default());
+        if let Err(e) = SignalingServer::run_blocking(addr) {
+            error!("Signaling server failed: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
@@ -12,7 +24,7 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugins(PhysicsPlugins::default())
+        .insert_resource(args)
         .add_plugins(RibbonGamePlugin)
         .run();
 } 
\ No newline at end of file
@@ -0,0 +1,345 @@
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+use bevy_ggrs::ggrs::{Message, NonBlockingSocket};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cli::CliArgs;
+
+// Seed used unless the CLI picks another, so two runs drop the same packets
+pub const DEFAULT_SIMULATOR_SEED: u64 = 0x5EED;
+
+// How much longer a reordered packet is held back than the rest
+const REORDER_HOLD: Duration = Duration::from_millis(40);
+
+// Network Simulator
+// =================
+//
+// Bad networks on demand. Every GGRS socket is wrapped in a
+// `SimulatedSocket`, which passes packets straight through until some
+// conditions are set (CLI at startup, or the F4 panel at any time). Then
+// each packet, in each direction, may be dropped, duplicated, delayed by
+// the latency plus some jitter, or held back long enough for the packets
+// behind it to overtake it. A 50 ms latency therefore adds 100 ms to the
+// round trip, whatever the other peer simulates.
+//
+// The dice come from a seeded RNG, so the same seed and the same traffic
+// give the same losses run after run. Delayed packets go out when GGRS next
+// polls the socket, which it does every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkConditions {
+    pub latency: Duration,
+    pub jitter: Duration,
+    // Chances per packet, from 0 to 1
+    pub loss: f32,
+    pub duplicate: f32,
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    pub fn is_perfect(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub struct NetworkSimulatorPlugin;
+
+impl Plugin for NetworkSimulatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkSimulator>()
+            .init_resource::<NetworkSimulatorPanel>()
+            .add_systems(Startup, apply_simulator_overrides)
+            .add_systems(Update, toggle_network_simulator_panel);
+    }
+}
+
+// The conditions every wrapped socket reads. Shared, so changing them
+// reaches sessions that are already running.
+#[derive(Resource, Debug, Clone)]
+pub struct NetworkSimulator {
+    conditions: Arc<Mutex<NetworkConditions>>,
+    pub seed: u64,
+    pub clock: SimulatorClock,
+}
+
+impl Default for NetworkSimulator {
+    fn default() -> Self {
+        Self {
+            conditions: default(),
+            seed: DEFAULT_SIMULATOR_SEED,
+            clock: default(),
+        }
+    }
+}
+
+impl NetworkSimulator {
+    pub fn conditions(&self) -> NetworkConditions {
+        *self.conditions.lock().unwrap()
+    }
+
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        *self.conditions.lock().unwrap() = conditions;
+    }
+
+    pub fn wrap<S, A>(&self, socket: S) -> SimulatedSocket<S, A> {
+        SimulatedSocket {
+            inner: socket,
+            conditions: self.conditions.clone(),
+            rng: StdRng::seed_from_u64(self.seed),
+            clock: self.clock.clone(),
+            outgoing: Conditioner::default(),
+            incoming: Conditioner::default(),
+        }
+    }
+}
+
+// What simulated sockets time their delays by. The game goes by the wall
+// clock. A test can share a manual clock and move it on with its own
+// simulated time, so a 40 ms delay lasts the same number of frames however
+// fast the machine runs them.
+#[derive(Debug, Clone)]
+pub enum SimulatorClock {
+    Real(Instant),
+    Manual(Arc<Mutex<Duration>>),
+}
+
+impl Default for SimulatorClock {
+    fn default() -> Self {
+        Self::Real(Instant::now())
+    }
+}
+
+impl SimulatorClock {
+    pub fn manual() -> Self {
+        Self::Manual(default())
+    }
+
+    // Moves a manual clock on; the wall clock moves by itself
+    pub fn advance(&self, by: Duration) {
+        if let Self::Manual(now) = self {
+            *now.lock().unwrap() += by;
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        match self {
+            Self::Real(started) => started.elapsed(),
+            Self::Manual(now) => *now.lock().unwrap(),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct NetworkSimulatorPanel {
+    pub visible: bool,
+}
+
+fn apply_simulator_overrides(args: Option<Res<CliArgs>>, mut simulator: ResMut<NetworkSimulator>) {
+    let Some(args) = args else {
+        return;
+    };
+    if let Some(seed) = args.simulator_seed {
+        simulator.seed = seed;
+    }
+    if !args.network_conditions.is_perfect() {
+        info!(
+            "Simulating network conditions: {:?}",
+            args.network_conditions
+        );
+        simulator.set_conditions(args.network_conditions);
+    }
+}
+
+fn toggle_network_simulator_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut panel: ResMut<NetworkSimulatorPanel>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        panel.visible = !panel.visible;
+    }
+}
+
+pub struct SimulatedSocket<S, A> {
+    inner: S,
+    conditions: Arc<Mutex<NetworkConditions>>,
+    rng: StdRng,
+    clock: SimulatorClock,
+    outgoing: Conditioner<(A, Message)>,
+    incoming: Conditioner<(A, Message)>,
+}
+
+impl<A, S> NonBlockingSocket<A> for SimulatedSocket<S, A>
+where
+    A: Clone + PartialEq + Eq + Hash + Send + Sync,
+    S: NonBlockingSocket<A>,
+{
+    fn send_to(&mut self, msg: &Message, addr: &A) {
+        let conditions = *self.conditions.lock().unwrap();
+        let now = self.clock.now();
+        self.outgoing
+            .push(now, &conditions, &mut self.rng, (addr.clone(), msg.clone()));
+        for (addr, msg) in self.outgoing.due(now) {
+            self.inner.send_to(&msg, &addr);
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(A, Message)> {
+        let conditions = *self.conditions.lock().unwrap();
+        let now = self.clock.now();
+        for (addr, msg) in self.outgoing.due(now) {
+            self.inner.send_to(&msg, &addr);
+        }
+        for packet in self.inner.receive_all_messages() {
+            self.incoming.push(now, &conditions, &mut self.rng, packet);
+        }
+        self.incoming.due(now)
+    }
+}
+
+// Packets in one direction, ordered by when they're let through. The
+// sequence number keeps packets due at the same moment in arrival order.
+struct Conditioner<P> {
+    queue: BTreeMap<(Duration, u64), P>,
+    next: u64,
+}
+
+impl<P> Default for Conditioner<P> {
+    fn default() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            next: 0,
+        }
+    }
+}
+
+impl<P: Clone> Conditioner<P> {
+    fn push(&mut self, now: Duration, conditions: &NetworkConditions, rng: &mut StdRng, packet: P) {
+        if conditions.is_perfect() {
+            self.enqueue(now, packet);
+            return;
+        }
+        if rng.gen::<f32>() < conditions.loss {
+            return;
+        }
+        let copies = if rng.gen::<f32>() < conditions.duplicate {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut delay = conditions.latency + conditions.jitter.mul_f32(rng.gen());
+            if rng.gen::<f32>() < conditions.reorder {
+                delay += conditions.jitter + REORDER_HOLD;
+            }
+            self.enqueue(now + delay, packet.clone());
+        }
+    }
+
+    fn enqueue(&mut self, due: Duration, packet: P) {
+        self.queue.insert((due, self.next), packet);
+        self.next += 1;
+    }
+
+    // Everything whose time has come, in the order it's let through
+    fn due(&mut self, now: Duration) -> Vec<P> {
+        let later = self.queue.split_off(&(now, u64::MAX));
+        std::mem::replace(&mut self.queue, later)
+            .into_values()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // One packet a millisecond
+    fn send(conditions: NetworkConditions, packets: u32) -> Conditioner<u32> {
+        let mut conditioner = Conditioner::default();
+        let mut rng = StdRng::seed_from_u64(DEFAULT_SIMULATOR_SEED);
+        for packet in 0..packets {
+            conditioner.push(ms(packet as u64), &conditions, &mut rng, packet);
+        }
+        conditioner
+    }
+
+    #[test]
+    fn perfect_conditions_pass_straight_through() {
+        let mut conditioner = send(NetworkConditions::default(), 3);
+        assert_eq!(conditioner.due(ms(2)), [0, 1, 2]);
+        assert!(conditioner.due(ms(1000)).is_empty());
+    }
+
+    #[test]
+    fn latency_holds_packets_back() {
+        let conditions = NetworkConditions {
+            latency: ms(50),
+            ..default()
+        };
+        let mut conditioner = send(conditions, 3);
+        assert!(conditioner.due(ms(49)).is_empty());
+        assert_eq!(conditioner.due(ms(51)), [0, 1]);
+        assert_eq!(conditioner.due(ms(52)), [2]);
+    }
+
+    #[test]
+    fn loss_and_duplication_follow_the_odds() {
+        let lossy = NetworkConditions {
+            loss: 0.25,
+            ..default()
+        };
+        let delivered = send(lossy, 1000).due(ms(2000)).len();
+        assert!(
+            (700..800).contains(&delivered),
+            "{delivered} of 1000 got through"
+        );
+
+        let doubled = NetworkConditions {
+            duplicate: 1.0,
+            ..default()
+        };
+        assert_eq!(send(doubled, 2).due(ms(10)), [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn reordering_lets_later_packets_overtake() {
+        let conditions = NetworkConditions {
+            reorder: 0.5,
+            ..default()
+        };
+        let arrived = send(conditions, 100).due(ms(1000));
+        assert_eq!(arrived.len(), 100);
+        assert!(arrived.windows(2).any(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn a_manual_clock_only_moves_when_advanced() {
+        let clock = SimulatorClock::manual();
+        let shared = clock.clone();
+        assert_eq!(clock.now(), Duration::ZERO);
+        shared.advance(ms(33));
+        shared.advance(ms(33));
+        assert_eq!(clock.now(), ms(66));
+    }
+
+    #[test]
+    fn the_same_seed_loses_the_same_packets() {
+        let conditions = NetworkConditions {
+            latency: ms(20),
+            jitter: ms(30),
+            loss: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+        };
+        let mut first = send(conditions, 200);
+        let mut second = send(conditions, 200);
+        assert_eq!(first.due(ms(1000)), second.due(ms(1000)));
+    }
+}
@@ -0,0 +1,383 @@
+use bevy::prelude::*;
+use bevy_ggrs::{
+    ggrs::{NetworkStats, PlayerHandle},
+    AdvanceWorld, LoadWorld, RollbackFrameCount, Session,
+};
+use bevy_matchbox::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::networking::{GGRSConfig, PeerSocket, PROBE_CHANNEL};
+use crate::resources::{LobbyState, NetworkSession};
+use crate::GameState;
+
+// How often each peer gets a probe, and how long we wait before calling it lost
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Packet loss is over the last this-many probes, about ten seconds' worth
+const LOSS_WINDOW: usize = 20;
+
+// Weight of each new round trip in the smoothed RTT
+const RTT_SMOOTHING: f64 = 0.25;
+
+const PROBE_PING: u8 = 0;
+const PROBE_PONG: u8 = 1;
+
+// Network Statistics
+// ==================
+//
+// GGRS reports ping, send queue, frame advantage and bandwidth per remote
+// player, but only once a match is running, and it has no idea how many
+// packets go missing. So every peer also probes every other peer over
+// its own unreliable channel, lobby and match alike: a numbered ping,
+// answered by a pong. Round trips give the lobby a real ping, and
+// unanswered probes give us packet loss on the same kind of channel GGRS
+// uses. Rollbacks are counted locally as GGRS asks for them.
+pub struct NetworkStatsPlugin;
+
+impl Plugin for NetworkStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PeerLinks>()
+            .init_resource::<RollbackStats>()
+            .init_resource::<NetworkReport>()
+            .init_resource::<NetworkStatsOverlay>()
+            .add_systems(OnEnter(GameState::MainMenu), reset_peer_links)
+            .add_systems(OnEnter(GameState::InGame), reset_match_stats)
+            .add_systems(Update, probe_peers.run_if(resource_exists::<PeerSocket>))
+            .add_systems(
+                Update,
+                (
+                    toggle_network_stats_overlay,
+                    refresh_network_report.run_if(resource_exists::<Session<GGRSConfig>>),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            )
+            .add_systems(AdvanceWorld, track_newest_frame)
+            .add_systems(LoadWorld, count_rollback);
+    }
+}
+
+// Link Quality
+// ============
+//
+// One peer's probes in flight and how the recent ones went
+#[derive(Debug, Default, Clone)]
+pub struct LinkQuality {
+    next_sequence: u32,
+    in_flight: VecDeque<(u32, Duration)>,
+    answered: VecDeque<bool>,
+    rtt: Option<Duration>,
+}
+
+impl LinkQuality {
+    // Numbers the next probe and remembers when it went out
+    pub fn send_probe(&mut self, now: Duration) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.in_flight.push_back((sequence, now));
+        sequence
+    }
+
+    // A pong for a probe we gave up on (or never sent) is ignored
+    pub fn receive_pong(&mut self, sequence: u32, now: Duration) {
+        let Some(index) = self.in_flight.iter().position(|&(s, _)| s == sequence) else {
+            return;
+        };
+        let (_, sent_at) = self.in_flight.remove(index).unwrap();
+        let sample = now.saturating_sub(sent_at);
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt.mul_f64(1.0 - RTT_SMOOTHING) + sample.mul_f64(RTT_SMOOTHING),
+            None => sample,
+        });
+        self.record(true);
+    }
+
+    // Writes off probes that have been out longer than `PROBE_TIMEOUT`
+    pub fn expire(&mut self, now: Duration) {
+        while let Some(&(_, sent_at)) = self.in_flight.front() {
+            if now.saturating_sub(sent_at) < PROBE_TIMEOUT {
+                break;
+            }
+            self.in_flight.pop_front();
+            self.record(false);
+        }
+    }
+
+    fn record(&mut self, answered: bool) {
+        self.answered.push_back(answered);
+        if self.answered.len() > LOSS_WINDOW {
+            self.answered.pop_front();
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    // Fraction of recent probes that never came back, once we know anything
+    pub fn packet_loss(&self) -> Option<f32> {
+        if self.answered.is_empty() {
+            return None;
+        }
+        let lost = self.answered.iter().filter(|&&answered| !answered).count();
+        Some(lost as f32 / self.answered.len() as f32)
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct PeerLinks(pub HashMap<PeerId, LinkQuality>);
+
+impl PeerLinks {
+    pub fn get(&self, peer: PeerId) -> Option<&LinkQuality> {
+        self.0.get(&peer)
+    }
+}
+
+fn reset_peer_links(mut links: ResMut<PeerLinks>) {
+    links.0.clear();
+}
+
+fn probe_peers(
+    mut socket: ResMut<PeerSocket>,
+    mut links: ResMut<PeerLinks>,
+    time: Res<Time<Real>>,
+    mut last_probe: Local<Option<Duration>>,
+) {
+    if !socket.has_channel(PROBE_CHANNEL) {
+        return;
+    }
+    let now = time.elapsed();
+
+    for (peer, packet) in socket.receive(PROBE_CHANNEL) {
+        let Some((kind, sequence)) = decode_probe(&packet) else {
+            continue;
+        };
+        match kind {
+            PROBE_PING => socket.send(PROBE_CHANNEL, encode_probe(PROBE_PONG, sequence), peer),
+            PROBE_PONG => links.0.entry(peer).or_default().receive_pong(sequence, now),
+            _ => {}
+        }
+    }
+
+    for link in links.0.values_mut() {
+        link.expire(now);
+    }
+
+    if last_probe.is_some_and(|last| now.saturating_sub(last) < PROBE_INTERVAL) {
+        return;
+    }
+    *last_probe = Some(now);
+
+    for peer in socket.connected_peers() {
+        let sequence = links.0.entry(peer).or_default().send_probe(now);
+        socket.send(PROBE_CHANNEL, encode_probe(PROBE_PING, sequence), peer);
+    }
+}
+
+fn encode_probe(kind: u8, sequence: u32) -> Box<[u8]> {
+    let mut packet = vec![kind];
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.into_boxed_slice()
+}
+
+fn decode_probe(packet: &[u8]) -> Option<(u8, u32)> {
+    let (&kind, sequence) = packet.split_first()?;
+    Some((kind, u32::from_le_bytes(sequence.try_into().ok()?)))
+}
+
+// Rollback Counting
+// =================
+//
+// GGRS asks for a load whenever a remote input turns out different from
+// what we predicted. How far back it goes is how many frames get
+// simulated again, so that's worth watching too.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct RollbackStats {
+    pub rollbacks: u32,
+    pub frames_resimulated: u32,
+    pub deepest: i32,
+    newest_frame: i32,
+}
+
+impl RollbackStats {
+    // A resync starts a new session from frame 0; the counts carry on
+    pub fn restart_frames(&mut self) {
+        self.newest_frame = 0;
+    }
+}
+
+fn reset_match_stats(
+    mut rollbacks: ResMut<RollbackStats>,
+    mut report: ResMut<NetworkReport>,
+    mut network_session: ResMut<NetworkSession>,
+) {
+    *rollbacks = RollbackStats::default();
+    *report = NetworkReport::default();
+    network_session.resumed_from = 0;
+}
+
+fn track_newest_frame(frame: Res<RollbackFrameCount>, mut stats: ResMut<RollbackStats>) {
+    stats.newest_frame = stats.newest_frame.max(frame.0);
+}
+
+// By the time `LoadWorld` runs, the frame count is the frame being restored
+fn count_rollback(frame: Res<RollbackFrameCount>, mut stats: ResMut<RollbackStats>) {
+    let depth = (stats.newest_frame - frame.0).max(0);
+    stats.rollbacks += 1;
+    stats.frames_resimulated += depth as u32;
+    stats.deepest = stats.deepest.max(depth);
+}
+
+// Per-Match Report
+// ================
+//
+// Everything the overlay shows, gathered once per frame
+#[derive(Resource, Debug, Default, Clone)]
+pub struct NetworkReport {
+    pub current_frame: i32,
+    pub confirmed_frame: i32,
+    pub frames_ahead: i32,
+    pub remotes: Vec<RemoteReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteReport {
+    pub handle: PlayerHandle,
+    pub name: String,
+    // None until GGRS has synchronized with this peer
+    pub stats: Option<NetworkStats>,
+    pub packet_loss: Option<f32>,
+}
+
+fn refresh_network_report(
+    session: Res<Session<GGRSConfig>>,
+    links: Res<PeerLinks>,
+    lobby_state: Res<LobbyState>,
+    mut report: ResMut<NetworkReport>,
+    mut network_session: ResMut<NetworkSession>,
+) {
+    let Session::P2P(session) = session.as_ref() else {
+        return; // SyncTest has no network, and spectators have no one to measure
+    };
+
+    network_session.is_active = true;
+    network_session.session_id = lobby_state.room_id.clone();
+    network_session.frame_count =
+        network_session.resumed_from + session.current_frame().max(0) as u32;
+
+    // Which peer is behind each remote handle
+    let peers = links
+        .0
+        .keys()
+        .flat_map(|&peer| {
+            session
+                .handles_by_address(peer)
+                .into_iter()
+                .map(move |handle| (handle, peer))
+        })
+        .collect::<HashMap<_, _>>();
+
+    report.current_frame = session.current_frame();
+    report.confirmed_frame = session.confirmed_frame();
+    report.frames_ahead = session.frames_ahead();
+    report.remotes = session
+        .remote_player_handles()
+        .into_iter()
+        .map(|handle| {
+            let peer = peers.get(&handle).copied();
+            let name = peer
+                .and_then(|peer| lobby_state.players.iter().find(|p| p.id == peer.0))
+                .map(|player| player.name.clone())
+                .unwrap_or_else(|| format!("Player {handle}"));
+            RemoteReport {
+                handle,
+                name,
+                stats: session.network_stats(handle).ok(),
+                packet_loss: peer
+                    .and_then(|peer| links.get(peer))
+                    .and_then(LinkQuality::packet_loss),
+            }
+        })
+        .collect();
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct NetworkStatsOverlay {
+    pub visible: bool,
+}
+
+fn toggle_network_stats_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut overlay: ResMut<NetworkStatsOverlay>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        overlay.visible = !overlay.visible;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn probes_round_trip_on_the_wire() {
+        assert_eq!(
+            decode_probe(&encode_probe(PROBE_PONG, 70_000)),
+            Some((PROBE_PONG, 70_000))
+        );
+        assert_eq!(decode_probe(&[PROBE_PING, 1, 2]), None);
+    }
+
+    #[test]
+    fn rtt_is_smoothed_over_round_trips() {
+        let mut link = LinkQuality::default();
+        assert_eq!(link.rtt(), None);
+
+        let first = link.send_probe(ms(0));
+        link.receive_pong(first, ms(100));
+        assert_eq!(link.rtt(), Some(ms(100)));
+
+        let second = link.send_probe(ms(500));
+        link.receive_pong(second, ms(520));
+        assert_eq!(link.rtt(), Some(ms(80)));
+    }
+
+    #[test]
+    fn unanswered_probes_count_as_lost() {
+        let mut link = LinkQuality::default();
+        assert_eq!(link.packet_loss(), None);
+
+        let answered = link.send_probe(ms(0));
+        link.send_probe(ms(500));
+        link.receive_pong(answered, ms(50));
+        link.expire(ms(500) + PROBE_TIMEOUT);
+        assert_eq!(link.packet_loss(), Some(0.5));
+    }
+
+    #[test]
+    fn late_pongs_are_ignored() {
+        let mut link = LinkQuality::default();
+        let sequence = link.send_probe(ms(0));
+        link.expire(PROBE_TIMEOUT);
+        link.receive_pong(sequence, PROBE_TIMEOUT + ms(1));
+        assert_eq!(link.rtt(), None);
+        assert_eq!(link.packet_loss(), Some(1.0));
+    }
+
+    #[test]
+    fn loss_only_remembers_recent_probes() {
+        let mut link = LinkQuality::default();
+        link.send_probe(ms(0));
+        link.expire(PROBE_TIMEOUT);
+        for i in 0..LOSS_WINDOW as u64 {
+            let sequence = link.send_probe(ms(i));
+            link.receive_pong(sequence, ms(i + 10));
+        }
+        assert_eq!(link.packet_loss(), Some(0.0));
+    }
+}
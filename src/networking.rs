@@ -1,16 +1,28 @@
 use avian3d::prelude::*;
 use bevy::prelude::*;
 use bevy_ggrs::{
-    ggrs::{Config, SessionBuilder, PlayerType},
-    GgrsApp, GgrsPlugin, GgrsSchedule, LocalPlayers, PlayerInputs, RollbackApp, Session,
+    ggrs::{
+        Config, DesyncDetection, InputStatus, Message, NonBlockingSocket, PlayerHandle, PlayerType,
+        SessionBuilder,
+    },
+    AddRollbackCommandExtension, GgrsApp, GgrsPlugin, GgrsSchedule, PlayerInputs, RollbackApp,
+    Session,
 };
-use bevy_matchbox::prelude::*;
+use bevy_matchbox::{matchbox_socket::WebRtcChannel, prelude::*};
+use bytemuck::{Pod, Zeroable};
 use serde::{Deserialize, Serialize};
-use uuid;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crate::advancement::MatchState;
 use crate::components::*;
-// use crate::input::read_local_inputs;
+use crate::direct::{DirectGgrsSocket, DirectSocket};
+use crate::input::{read_local_inputs, JumpLatch};
+use crate::netsim::NetworkSimulator;
 use crate::resources::*;
+use crate::rules::MatchRules;
+use crate::signaling::{room_url, LocalSignalingServer};
 use crate::GameState;
 
 // GGRS Configuration
@@ -19,16 +31,177 @@ use crate::GameState;
 // A quick note from your friendly neighborhood Bespoke developer:
 // This is where we define the core of our networking. GGRS is a powerful rollback
 // networking library, but it needs to know what we're sending across the wire.
-// `NetworkInput` is our payload. Keep it lean, keep it mean.
+// `PackedInput` is our payload. Keep it lean, keep it mean.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct GGRSConfig;
 
 impl Config for GGRSConfig {
-    type Input = NetworkInput;
+    type Input = PackedInput;
     type State = u8;
     type Address = PeerId;
 }
 
+// Packed Input Wire Format
+// =========================
+//
+// Eight bytes per player per frame, no floats. Every field is quantized
+// on the sending side, so all peers unpack the exact same `NetworkInput`
+// and the simulation never sees a value that could round differently.
+// Bow charge isn't carried: the simulation derives it from how long
+// `SHOOT` has been held (see `combat::step_bow`), which every peer already
+// agrees on, so a charge byte would only repeat what the buttons say.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Pod, Zeroable, Serialize, Deserialize)]
+pub struct PackedInput {
+    pub aim_yaw: i16,   // [-PI, PI], 0 faces forward (-Z)
+    pub aim_pitch: i16, // [-PI/2, PI/2], 0 is level
+    pub move_x: i8,     // [-1, 1] scaled to +-127
+    pub move_y: i8,
+    pub buttons: u8,
+    pub reserved: u8, // Always zero; keeps the struct free of padding
+}
+
+impl PackedInput {
+    pub const JUMP: u8 = 1 << 0;
+    pub const SHOOT: u8 = 1 << 1;
+    pub const DODGE: u8 = 1 << 2;
+    pub const PAUSE: u8 = 1 << 3;
+
+    fn pressed(&self, button: u8) -> bool {
+        self.buttons & button != 0
+    }
+}
+
+impl From<NetworkInput> for PackedInput {
+    fn from(input: NetworkInput) -> Self {
+        let aim = input.aim.normalize_or(Vec3::NEG_Z);
+        let yaw = aim.x.atan2(-aim.z);
+        let pitch = aim.y.clamp(-1.0, 1.0).asin();
+
+        let mut buttons = 0;
+        if input.jump {
+            buttons |= Self::JUMP;
+        }
+        if input.shoot {
+            buttons |= Self::SHOOT;
+        }
+        if input.dodge {
+            buttons |= Self::DODGE;
+        }
+        if input.pause {
+            buttons |= Self::PAUSE;
+        }
+
+        Self {
+            aim_yaw: quantize_i16(yaw / std::f32::consts::PI),
+            aim_pitch: quantize_i16(pitch / std::f32::consts::FRAC_PI_2),
+            move_x: quantize_i8(input.movement.x),
+            move_y: quantize_i8(input.movement.y),
+            buttons,
+            reserved: 0,
+        }
+    }
+}
+
+impl From<PackedInput> for NetworkInput {
+    fn from(packed: PackedInput) -> Self {
+        // Every peer runs this, so the trig goes through libm rather than
+        // whatever the platform provides
+        let yaw = packed.aim_yaw as f32 / i16::MAX as f32 * std::f32::consts::PI;
+        let pitch = packed.aim_pitch as f32 / i16::MAX as f32 * std::f32::consts::FRAC_PI_2;
+        let (sin_yaw, cos_yaw) = (libm::sinf(yaw), libm::cosf(yaw));
+        let (sin_pitch, cos_pitch) = (libm::sinf(pitch), libm::cosf(pitch));
+
+        Self {
+            movement: Vec2::new(
+                packed.move_x as f32 / i8::MAX as f32,
+                packed.move_y as f32 / i8::MAX as f32,
+            ),
+            jump: packed.pressed(PackedInput::JUMP),
+            shoot: packed.pressed(PackedInput::SHOOT),
+            dodge: packed.pressed(PackedInput::DODGE),
+            pause: packed.pressed(PackedInput::PAUSE),
+            aim: Vec3::new(sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch),
+        }
+    }
+}
+
+fn quantize_i16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn quantize_i8(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * i8::MAX as f32).round() as i8
+}
+
+// Gameplay view of a player's input for the frame being simulated
+pub fn player_input(inputs: &PlayerInputs<GGRSConfig>, network_id: u32) -> Option<NetworkInput> {
+    inputs
+        .get(network_id as usize)
+        .map(|(packed, _)| NetworkInput::from(*packed))
+}
+
+// Pausing
+// =======
+//
+// A pause vote (see `control.rs`) only ever changes what we send: while it
+// holds, every input carries `PAUSE`. The frame itself pauses once every
+// connected player's input for it does, so all peers stop and resume on
+// the same frame, and a rollback replays the pause like anything else.
+// Nothing in the gameplay sets runs on a paused frame: no movement, no
+// physics, no arrows, no respawn timers and no game clock.
+pub fn agree_on_pause(inputs: Res<PlayerInputs<GGRSConfig>>, mut match_state: ResMut<MatchState>) {
+    let mut connected = inputs
+        .iter()
+        .filter(|(_, status)| *status != InputStatus::Disconnected)
+        .peekable();
+    let paused = connected.peek().is_some()
+        && connected.all(|(packed, _)| packed.pressed(PackedInput::PAUSE));
+    match_state.paused = paused;
+}
+
+pub fn match_running(match_state: Res<MatchState>) -> bool {
+    !match_state.paused
+}
+
+// Socket channels, in the order `start_matchbox_socket` adds them
+pub const GGRS_CHANNEL: usize = 0; // Unreliable, unordered
+pub const CONTROL_CHANNEL: usize = 1; // Reliable, see `control.rs`
+pub const PROBE_CHANNEL: usize = 2; // Unreliable, see `network_stats.rs`
+
+// Rollback ticks per second, straight from design.md
+pub const FPS: usize = 30;
+
+// How long a silent peer gets before GGRS drops them, and how long before
+// we start counting that down on screen
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DISCONNECT_NOTIFY_DELAY: Duration = Duration::from_secs(1);
+
+// Frames between checksum exchanges, so about three a second
+pub const DESYNC_CHECK_INTERVAL: u32 = 10;
+
+// A spectator this far behind the host plays several frames per tick to
+// catch up, so a hiccup costs them a few seconds of fast forward at most
+pub const SPECTATOR_MAX_FRAMES_BEHIND: usize = FPS / 2;
+pub const SPECTATOR_CATCHUP_SPEED: usize = 2;
+
+// Rollback Ordering
+// =================
+//
+// GGRS builds its schedule with ambiguity detection cranked up to "error", so
+// every rollback system has to live in one of these sets. Movement pushes
+// bodies around, physics steps them, then combat reacts to where everyone
+// ended up. The match rules go last, once the frame's kills are in. Ahead
+// of all that, the players' inputs decide whether the frame runs at all.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RollbackSet {
+    Pause,
+    Movement,
+    Physics,
+    Combat,
+    Advancement,
+}
+
 // Networking Plugin
 // =================
 //
@@ -44,31 +217,68 @@ impl Plugin for NetworkingPlugin {
             // GGRS, the star of the show
             .add_plugins(GgrsPlugin::<GGRSConfig>::default())
             // Define a schedule for GGRS to run on
-            .set_rollback_schedule_fps(30)
+            .set_rollback_schedule_fps(FPS)
+            .configure_sets(
+                GgrsSchedule,
+                (
+                    RollbackSet::Pause,
+                    (
+                        RollbackSet::Movement,
+                        RollbackSet::Physics,
+                        RollbackSet::Combat,
+                        RollbackSet::Advancement,
+                    )
+                        .chain()
+                        .run_if(match_running),
+                )
+                    .chain(),
+            )
             // These components will be rolled back by GGRS
             .rollback_component_with_copy::<Transform>()
             .rollback_component_with_copy::<ExternalImpulse>()
             .rollback_component_with_copy::<Player>()
+            .rollback_component_with_copy::<Bow>()
+            .rollback_component_with_copy::<Arrow>()
+            .rollback_component_with_copy::<DodgeAbility>()
+            .rollback_component_with_copy::<Respawning>()
+            // Everything Avian needs to replay a step exactly. The contact
+            // graph carries the solver's warm-start impulses between steps.
+            .rollback_component_with_copy::<Position>()
+            .rollback_component_with_copy::<Rotation>()
+            .rollback_component_with_copy::<LinearVelocity>()
+            .rollback_component_with_copy::<AngularVelocity>()
+            .rollback_resource_with_clone::<ContactGraph>()
+            // Who holds the right of way, and how far each player got
+            .rollback_resource_with_clone::<MatchState>()
+            .rollback_resource_with_clone::<GameStats>()
             // Resources to manage our sorry excuse for a lobby
             .init_resource::<LobbyState>()
             .init_resource::<ConnectionInfo>()
+            .init_resource::<DirectConnectSettings>()
             .init_resource::<NetworkSession>()
             // System to kick things off when we enter the lobby
-            .add_systems(OnEnter(GameState::Lobby), start_matchbox_socket)
-            // Systems that run in the lobby, waiting for players to join
-            .add_systems(Update, wait_for_players.run_if(in_state(GameState::Lobby)))
+            .add_systems(OnEnter(GameState::Lobby), start_peer_socket)
+            .add_systems(OnEnter(GameState::MainMenu), close_peer_socket)
+            .add_systems(
+                PreUpdate,
+                pump_peer_socket.run_if(resource_exists::<PeerSocket>),
+            )
+            // One entity per handle, as soon as the session exists
+            .add_systems(
+                OnEnter(GameState::InGame),
+                spawn_network_players.run_if(resource_exists::<Session<GGRSConfig>>),
+            )
             // GGRS will read player inputs from this system
-            // TODO: Fix input system - temporarily disabled for testing
-            // .add_systems(bevy_ggrs::ReadInputs, read_local_inputs)
+            .init_resource::<JumpLatch>()
+            .add_systems(bevy_ggrs::ReadInputs, read_local_inputs)
+            // Decided before anything moves, so a paused frame stands still
+            .add_systems(GgrsSchedule, agree_on_pause.in_set(RollbackSet::Pause))
             // These systems are the core of our networked gameplay
             .add_systems(
                 GgrsSchedule,
-                (
-                    spawn_network_players,
-                    network_player_movement,
-                    network_jump_system,
-                )
-                    .chain(),
+                (network_player_movement, network_jump_system)
+                    .chain()
+                    .in_set(RollbackSet::Movement),
             );
     }
 }
@@ -79,129 +289,451 @@ impl Plugin for NetworkingPlugin {
 // We need a way to connect to other players. Matchbox provides a WebRTC socket
 // that works in the browser. This function creates the socket and kicks off
 // the connection process. Simple, effective, and doesn't complain about
-// dependency lists.
-pub fn start_matchbox_socket(mut commands: Commands, lobby_state: Res<LobbyState>) {
-    let room_id = lobby_state.room_id.as_ref().unwrap();
-    let room_url = format!("ws://44.206.226.40:3536/{}", room_id);
-    info!("Connecting to Matchbox server: {}", room_url);
-    commands.insert_resource(MatchboxSocket::new_reliable(&room_url));
-}
-
-// Waiting for Players
-// ===================
-//
-// This system just waits for players to connect. Once everyone is here,
-// it will trigger the start of the GGRS session. No more, no less.
-pub fn wait_for_players(
+// dependency lists. Direct Connect lobbies skip all that and go straight to
+// UDP (see `direct.rs`).
+pub fn start_peer_socket(
     mut commands: Commands,
-    mut socket: ResMut<MatchboxSocket>,
+    lobby_state: Res<LobbyState>,
+    settings: Res<SignalingSettings>,
+    local_server: Option<Res<LocalSignalingServer>>,
+    mut connection_info: ResMut<ConnectionInfo>,
     mut game_state: ResMut<NextState<GameState>>,
-    mut lobby_state: ResMut<LobbyState>,
 ) {
-    if socket.get_channel(0).is_err() {
-        return; // We are not ready yet
-    }
-
-    // Check for new connections
-    socket.update_peers();
-    let players = socket.connected_peers().collect::<Vec<_>>();
-    
-    // Update lobby state with current player count
-    let current_player_count = players.len() + 1; // +1 for the local player
-    lobby_state.player_count = current_player_count;
-    lobby_state.connected_players = players.iter().map(|p| format!("Player_{}", p)).collect();
-    
-    // Update the players list that the UI displays
-    let is_host = lobby_state.is_host;
-    let local_player_name = lobby_state.local_player_name.clone();
-    
-    lobby_state.players.clear();
-    // Add the local/host player first
-    lobby_state.players.push(crate::resources::LobbyPlayer {
-        id: uuid::Uuid::new_v4(),
-        name: if is_host { "Host".to_string() } else { local_player_name },
-        is_ready: false,
-        network_handle: 0,
-        is_local: true,
-    });
-    
-    // Add remote players
-    for (i, _player) in players.iter().enumerate() {
-        lobby_state.players.push(crate::resources::LobbyPlayer {
-            id: uuid::Uuid::new_v4(),
-            name: format!("Player_{}", i + 2),
-            is_ready: false,
-            network_handle: (i + 1) as u32,
-            is_local: false,
-        });
+    let socket = match lobby_state.transport {
+        LobbyTransport::Signaling => {
+            let room_id = lobby_state.room_id.as_ref().unwrap();
+            let room_url = room_url(&settings, local_server.as_deref(), room_id);
+            info!("Connecting to Matchbox server: {}", room_url);
+            connection_info.server_url = room_url.clone();
+            Ok(PeerSocket::Matchbox(open_matchbox_socket(room_url)))
+        }
+        transport => open_direct_socket(transport),
+    };
+    match socket {
+        Ok(socket) => commands.insert_resource(socket),
+        Err(e) => {
+            error!("{e}");
+            connection_info.connection_error = Some(e);
+            game_state.set(GameState::MainMenu);
+        }
     }
+}
 
-    info!("Players connected: {}/{}", current_player_count, lobby_state.max_players);
+pub fn open_direct_socket(transport: LobbyTransport) -> Result<PeerSocket, String> {
+    let socket = match transport {
+        LobbyTransport::DirectHost { port } => {
+            info!("Listening for direct connections on port {port}");
+            DirectSocket::host(port).map_err(|e| {
+                format!(
+                    "Couldn't listen on ports {port} and {}: {e}",
+                    port.wrapping_add(1)
+                )
+            })?
+        }
+        LobbyTransport::DirectJoin { host } => {
+            info!("Connecting directly to {host}");
+            DirectSocket::join(host).map_err(|e| format!("Couldn't connect to {host}: {e}"))?
+        }
+        LobbyTransport::Signaling => return Err("not a Direct Connect lobby".to_string()),
+    };
+    Ok(PeerSocket::Direct(Box::new(socket)))
+}
+
+pub fn open_matchbox_socket(room_url: String) -> MatchboxSocket {
+    // GGRS resends inputs until they're acked, so a late packet is worse
+    // than a lost one. The control channel can't afford to lose anything.
+    // Probes measure the kind of channel GGRS is on, so they're unreliable too.
+    let socket = WebRtcSocketBuilder::new(room_url)
+        .add_unreliable_channel()
+        .add_reliable_channel()
+        .add_unreliable_channel();
+    MatchboxSocket::from(socket)
+}
 
-    if players.len() < lobby_state.max_players - 1 {
-        return; // not enough players yet (-1 because we count the local player)
+// Back at the menu nobody is connected to anyone
+fn close_peer_socket(mut commands: Commands) {
+    commands.remove_resource::<PeerSocket>();
+    commands.remove_resource::<GgrsChannel>();
+}
+
+// Matchbox runs its socket in the background; a direct one runs here
+fn pump_peer_socket(mut socket: ResMut<PeerSocket>) {
+    if let PeerSocket::Direct(socket) = socket.as_mut() {
+        socket.poll();
     }
-    
-    // Auto-start when we have enough players (simplified for now)
-    // TODO: Later we can add proper ready-state synchronization via GGRS
-    info!("All players connected, auto-starting GGRS session.");
+}
+
+// Peer Socket
+// ===========
+//
+// However we got to the other peers, the lobby, the control channel and the
+// probes all see the same thing: our id once we have one, peers arriving
+// and leaving, and numbered channels to send on. GGRS gets its own channel
+// through `GgrsChannel::take`.
+#[derive(Resource)]
+pub enum PeerSocket {
+    Matchbox(MatchboxSocket),
+    Direct(Box<DirectSocket>),
+}
+
+impl PeerSocket {
+    pub fn id(&mut self) -> Option<PeerId> {
+        match self {
+            Self::Matchbox(socket) => socket.id(),
+            Self::Direct(socket) => socket.id(),
+        }
+    }
+
+    pub fn update_peers(&mut self) -> Vec<(PeerId, PeerState)> {
+        match self {
+            Self::Matchbox(socket) => socket.update_peers(),
+            Self::Direct(socket) => socket.update_peers(),
+        }
+    }
+
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        match self {
+            Self::Matchbox(socket) => socket.connected_peers().collect(),
+            Self::Direct(socket) => socket.connected_peers().collect(),
+        }
+    }
+
+    // False once the channel has been taken, or before the socket is ready
+    pub fn has_channel(&self, channel: usize) -> bool {
+        match self {
+            Self::Matchbox(socket) => socket.get_channel(channel).is_ok(),
+            Self::Direct(socket) => socket.has_channel(channel),
+        }
+    }
+
+    pub fn send(&mut self, channel: usize, packet: Box<[u8]>, peer: PeerId) {
+        match self {
+            Self::Matchbox(socket) => socket.channel_mut(channel).send(packet, peer),
+            Self::Direct(socket) => socket.send(channel, packet, peer),
+        }
+    }
+
+    pub fn receive(&mut self, channel: usize) -> Vec<(PeerId, Box<[u8]>)> {
+        match self {
+            Self::Matchbox(socket) => socket.channel_mut(channel).receive(),
+            Self::Direct(socket) => socket.receive(channel),
+        }
+    }
+}
+
+// Session Start
+// =============
+//
+// The lobby decides when to start and with whom (see `lobby.rs`). Every
+// peer on the start list gets a handle; anyone still connected but not on
+// it isn't part of this match. GGRS takes over the unreliable channel, the
+// control channel stays with the socket.
+pub fn start_p2p_session(
+    socket: &mut PeerSocket,
+    players: &[PeerId],
+    spectators: &[PeerId],
+    simulator: &NetworkSimulator,
+    rules: &MatchRules,
+) -> Result<(Session<GGRSConfig>, GgrsChannel), String> {
+    let local_id = socket.id().ok_or("no peer id from signaling yet")?;
+    let channel = GgrsChannel::take(socket, rules)?;
+    let session = p2p_session(local_id, players, spectators, simulator.wrap(channel.clone()))?;
+    Ok((session, channel))
+}
+
+// A session over a channel we already hold, for the first start and for
+// every resync after a rejoin (see `rejoin.rs`). `spectators` are fed
+// confirmed inputs by this session; only the broadcaster passes any.
+pub fn p2p_session(
+    local_id: PeerId,
+    players: &[PeerId],
+    spectators: &[PeerId],
+    socket: impl NonBlockingSocket<PeerId> + 'static,
+) -> Result<Session<GGRSConfig>, String> {
+    let remote_ids = players
+        .iter()
+        .copied()
+        .filter(|&peer| peer != local_id)
+        .collect::<Vec<_>>();
 
     let mut session_builder = SessionBuilder::<GGRSConfig>::new()
-        .with_num_players(lobby_state.max_players)
-        .with_input_delay(2);
+        .with_num_players(remote_ids.len() + 1)
+        .with_input_delay(2)
+        .with_disconnect_timeout(DISCONNECT_TIMEOUT)
+        .with_disconnect_notify_delay(DISCONNECT_NOTIFY_DELAY)
+        .with_desync_detection_mode(DesyncDetection::On {
+            interval: DESYNC_CHECK_INTERVAL,
+        });
+
+    for (handle, player_type) in assign_player_handles(local_id, &remote_ids) {
+        session_builder = session_builder
+            .add_player(player_type, handle)
+            .map_err(|e| e.to_string())?;
+    }
 
-    for (i, _player) in players.into_iter().enumerate() {
+    // GGRS wants spectator handles after all the player handles
+    for (index, &spectator) in spectators.iter().enumerate() {
         session_builder = session_builder
-            .add_player(PlayerType::Local, i)
-            .expect("failed to add player");
+            .add_player(PlayerType::Spectator(spectator), players.len() + index)
+            .map_err(|e| e.to_string())?;
     }
 
-    // Create GGRS session and start the networked game
-    let channel = socket.take_channel(0).unwrap();
     let ggrs_session = session_builder
-        .start_p2p_session(channel)
-        .expect("failed to start session");
-    commands.insert_resource(Session::P2P(ggrs_session));
+        .start_p2p_session(socket)
+        .map_err(|e| e.to_string())?;
+    Ok(Session::P2P(ggrs_session))
+}
+
+// Spectators run no simulation of their own choosing: the host's session
+// sends them every confirmed input and they play them back
+pub fn start_spectator_session(
+    socket: &mut PeerSocket,
+    host: PeerId,
+    num_players: usize,
+    simulator: &NetworkSimulator,
+    rules: &MatchRules,
+) -> Result<(Session<GGRSConfig>, GgrsChannel), String> {
+    let channel = GgrsChannel::take(socket, rules)?;
+    let session = spectator_session(host, num_players, simulator.wrap(channel.clone()))?;
+    Ok((session, channel))
+}
 
-    game_state.set(GameState::InGame);
+pub fn spectator_session(
+    host: PeerId,
+    num_players: usize,
+    socket: impl NonBlockingSocket<PeerId> + 'static,
+) -> Result<Session<GGRSConfig>, String> {
+    let ggrs_session = SessionBuilder::<GGRSConfig>::new()
+        .with_num_players(num_players)
+        .with_disconnect_timeout(DISCONNECT_TIMEOUT)
+        .with_disconnect_notify_delay(DISCONNECT_NOTIFY_DELAY)
+        .with_max_frames_behind(SPECTATOR_MAX_FRAMES_BEHIND)
+        .map_err(|e| e.to_string())?
+        .with_catchup_speed(SPECTATOR_CATCHUP_SPEED)
+        .map_err(|e| e.to_string())?
+        .start_spectator_session(host, socket);
+    Ok(Session::Spectator(ggrs_session))
+}
+
+// GGRS Channel
+// ============
+//
+// A GGRS session owns its socket for good, but a rejoin needs a fresh
+// session on the same connections. So the unreliable channel is shared
+// rather than handed over, and every packet is stamped with the epoch of
+// the session that sent it. Whatever the previous session still had in
+// flight is dropped instead of being read as this session's inputs. Over
+// WebRTC and Direct Connect alike, since both only carry the bytes.
+//
+// Every packet also carries the fingerprint of the rules we play by (see
+// `rules.rs`). A packet with another fingerprint is dropped too, so GGRS
+// never synchronizes with a peer on other rules; they're noted as refused
+// and the match is called off (see `session_events.rs`).
+#[derive(Resource, Clone)]
+pub struct GgrsChannel {
+    link: GgrsLink,
+    epoch: u8,
+    rules: u64,
+    refused: Arc<Mutex<BTreeSet<PeerId>>>,
+}
+
+#[derive(Clone)]
+enum GgrsLink {
+    WebRtc(Arc<Mutex<WebRtcChannel>>),
+    Direct(Arc<Mutex<DirectGgrsSocket>>),
+}
+
+impl GgrsChannel {
+    pub fn take(socket: &mut PeerSocket, rules: &MatchRules) -> Result<Self, String> {
+        let link = match socket {
+            PeerSocket::Matchbox(socket) => {
+                let channel = socket
+                    .take_channel(GGRS_CHANNEL)
+                    .map_err(|e| e.to_string())?;
+                GgrsLink::WebRtc(Arc::new(Mutex::new(channel)))
+            }
+            PeerSocket::Direct(socket) => {
+                let socket = socket
+                    .take_ggrs()
+                    .ok_or("the GGRS socket is already taken")?;
+                GgrsLink::Direct(Arc::new(Mutex::new(socket)))
+            }
+        };
+        Ok(Self {
+            link,
+            epoch: 0,
+            rules: rules.fingerprint(),
+            refused: default(),
+        })
+    }
+
+    pub fn epoch(&self) -> u8 {
+        self.epoch
+    }
+
+    // The same channel, for a session that only talks to its own epoch
+    pub fn with_epoch(&self, epoch: u8) -> Self {
+        Self {
+            epoch,
+            ..self.clone()
+        }
+    }
+
+    // Peers whose packets came with rules other than ours
+    pub fn refused(&self) -> Vec<PeerId> {
+        self.refused.lock().unwrap().iter().copied().collect()
+    }
+}
+
+impl GgrsChannel {
+    fn send(&self, packet: &[u8], peer: PeerId) {
+        match &self.link {
+            GgrsLink::WebRtc(channel) => channel.lock().unwrap().send(packet.into(), peer),
+            GgrsLink::Direct(socket) => socket.lock().unwrap().send(packet, peer),
+        }
+    }
+
+    fn receive(&self) -> Vec<(PeerId, Box<[u8]>)> {
+        match &self.link {
+            GgrsLink::WebRtc(channel) => channel.lock().unwrap().receive(),
+            GgrsLink::Direct(socket) => socket.lock().unwrap().receive(),
+        }
+    }
+}
+
+impl NonBlockingSocket<PeerId> for GgrsChannel {
+    fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+        let mut packet = vec![self.epoch];
+        packet.extend_from_slice(&self.rules.to_le_bytes());
+        bincode::serde::encode_into_std_write(msg, &mut packet, bincode::config::standard())
+            .expect("GGRS messages always serialize");
+        self.send(&packet, *addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        self.receive()
+            .into_iter()
+            .filter_map(|(peer, packet)| {
+                let (&epoch, rest) = packet.split_first()?;
+                if epoch != self.epoch {
+                    return None; // Left over from an earlier session
+                }
+                let (rules, body) = rest.split_first_chunk::<8>()?;
+                if u64::from_le_bytes(*rules) != self.rules {
+                    if self.refused.lock().unwrap().insert(peer) {
+                        warn!("Refusing GGRS packets from {peer}: they play by other rules");
+                    }
+                    return None;
+                }
+                match bincode::serde::decode_from_slice(body, bincode::config::standard()) {
+                    Ok((message, _)) => Some((peer, message)),
+                    Err(e) => {
+                        warn!("Dropping malformed GGRS packet from {peer}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+// Handle Assignment
+// =================
+//
+// Every peer has to agree on who is handle 0, 1, 2... without talking about
+// it. Peer ids are unique and every peer sees the same set, so sorting them
+// gives everyone the same order. Our own id gets `Local`, the rest `Remote`.
+pub fn assign_player_handles(
+    local_id: PeerId,
+    remote_ids: &[PeerId],
+) -> Vec<(PlayerHandle, PlayerType<PeerId>)> {
+    let mut peer_ids = remote_ids.to_vec();
+    peer_ids.push(local_id);
+    peer_ids.sort();
+
+    peer_ids
+        .into_iter()
+        .enumerate()
+        .map(|(handle, peer_id)| {
+            let player_type = if peer_id == local_id {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(peer_id)
+            };
+            (handle, player_type)
+        })
+        .collect()
 }
 
 // Player Spawning
 // ===============
 //
 // GGRS is ready, so it's time to spawn our players. This system runs once
-// at the beginning of the GGRS session. It creates a player entity for each
-// player in the session, whether they're local or remote. No hiding players
-// like the last guys. We're here to play.
+// when we enter the game. It creates a player entity for every handle in
+// the session, whether they're local or remote. No hiding players like the
+// last guys. We're here to play.
 fn spawn_network_players(
     mut commands: Commands,
-    local_players: Res<LocalPlayers>,
+    session: Res<Session<GGRSConfig>>,
+    rules: Res<MatchRules>,
 ) {
-    for handle in &local_players.0 {
-        let is_local = true; // All players in LocalPlayers are local
+    let (num_players, local_handles) = match session.as_ref() {
+        Session::P2P(s) => (s.num_players(), s.local_player_handles()),
+        Session::SyncTest(s) => (s.num_players(), (0..s.num_players()).collect()),
+        Session::Spectator(s) => (s.num_players(), Vec::new()),
+    };
+
+    for handle in 0..num_players {
+        let is_local = local_handles.contains(&handle);
+        let transform = Transform::from_translation(spawn_position(handle, num_players));
+        spawn_network_player(&mut commands, &rules, handle, is_local, transform);
+    }
+}
 
-        let player_entity = commands
-            .spawn((
-                Player {
-                    network_id: *handle as u32,
-                    is_local,
-                    ..default()
-                },
-                // All the usual components for a player
-                RigidBody::Dynamic,
-                Collider::capsule(0.4, 0.4),
-                ExternalImpulse::default(),
-                LockedAxes::new().lock_rotation_x().lock_rotation_z(),
-                Transform::from_xyz(0.0, 1.0, 0.0),
-            ))
-            .id();
+// One rollback-tracked player for `handle`. Resyncs after a rejoin spawn
+// through here too, then overwrite the state from the snapshot.
+pub fn spawn_network_player(
+    commands: &mut Commands,
+    rules: &MatchRules,
+    handle: PlayerHandle,
+    is_local: bool,
+    transform: Transform,
+) -> Entity {
+    let player_entity = commands
+        .spawn((
+            Player {
+                id: handle as u32,
+                network_id: handle as u32,
+                is_local,
+                ..rules.player()
+            },
+            // All the usual components for a player
+            RigidBody::Dynamic,
+            Collider::capsule(PLAYER_RADIUS, PLAYER_HEIGHT),
+            ExternalImpulse::default(),
+            LockedAxes::new().lock_rotation_x().lock_rotation_z(),
+            // A body that falls asleep would wake up differently after a rollback
+            SleepingDisabled,
+            transform,
+            Bow::default(),
+            DodgeAbility::default(),
+        ))
+        .add_rollback()
+        .id();
 
-        if is_local {
-            commands.entity(player_entity).insert(LocalPlayer);
-        } else {
-            commands.entity(player_entity).insert(RemotePlayer);
-        }
+    if is_local {
+        commands.entity(player_entity).insert(LocalPlayer);
+    } else {
+        commands.entity(player_entity).insert(RemotePlayer);
     }
+    player_entity
+}
+
+// Spread players across the arena's width so nobody spawns inside anybody else
+fn spawn_position(handle: PlayerHandle, num_players: usize) -> Vec3 {
+    let spacing = 3.0;
+    let offset = handle as f32 - (num_players as f32 - 1.0) / 2.0;
+    Vec3::new(offset * spacing, 1.0, 0.0)
 }
 
 // Networked Movement & Jumping
@@ -210,14 +742,14 @@ fn spawn_network_players(
 // These systems are the bread and butter of our networked gameplay. They run on
 // the GGRS schedule, which means they're subject to rollback. This is where we
 // read the inputs from the GGRS session and apply them to our player entities.
-// No magic, just good, clean code.
+// Dead players sit out until they respawn. No magic, just good, clean code.
 
 fn network_player_movement(
     inputs: Res<PlayerInputs<GGRSConfig>>,
-    mut player_query: Query<(&Player, &mut ExternalImpulse)>,
+    mut player_query: Query<(&Player, &mut ExternalImpulse), Without<Respawning>>,
 ) {
     for (player, mut impulse) in player_query.iter_mut() {
-        if let Some((input, _)) = inputs.get(player.network_id as usize) {
+        if let Some(input) = player_input(&inputs, player.network_id) {
             let direction =
                 Vec3::new(input.movement.x, 0.0, -input.movement.y).normalize_or_zero();
             impulse.apply_impulse(direction * player.movement_speed);
@@ -227,14 +759,194 @@ fn network_player_movement(
 
 fn network_jump_system(
     inputs: Res<PlayerInputs<GGRSConfig>>,
-    mut player_query: Query<(&mut Player, &mut ExternalImpulse)>,
+    mut player_query: Query<(&mut Player, &mut ExternalImpulse), Without<Respawning>>,
 ) {
     for (mut player, mut impulse) in player_query.iter_mut() {
-        if let Some((input, _)) = inputs.get(player.network_id as usize) {
+        if let Some(input) = player_input(&inputs, player.network_id) {
             if input.jump && player.can_jump && player.is_grounded {
                 impulse.apply_impulse(Vec3::Y * player.jump_force);
                 player.can_jump = false;
             }
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(n: u128) -> PeerId {
+        PeerId(uuid::Uuid::from_u128(n))
+    }
+
+    #[test]
+    fn peers_agree_on_handle_order() {
+        let (a, b, c) = (peer(3), peer(1), peer(2));
+        let from_a = assign_player_handles(a, &[c, b]);
+        let from_b = assign_player_handles(b, &[a, c]);
+
+        let order = |handles: &[(PlayerHandle, PlayerType<PeerId>)], local| {
+            handles
+                .iter()
+                .map(|(handle, player_type)| match player_type {
+                    PlayerType::Local => (*handle, local),
+                    PlayerType::Remote(id) => (*handle, *id),
+                    PlayerType::Spectator(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(&from_a, a), vec![(0, b), (1, c), (2, a)]);
+        assert_eq!(order(&from_a, a), order(&from_b, b));
+    }
+
+    #[test]
+    fn only_our_own_peer_is_local() {
+        let handles = assign_player_handles(peer(7), &[peer(9), peer(2)]);
+        let locals = handles
+            .iter()
+            .filter(|(_, player_type)| matches!(player_type, PlayerType::Local))
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        assert_eq!(locals, vec![1]);
+        assert!(matches!(handles[0].1, PlayerType::Remote(id) if id == peer(2)));
+        assert!(matches!(handles[2].1, PlayerType::Remote(id) if id == peer(9)));
+    }
+
+    fn round_trip(input: NetworkInput) -> NetworkInput {
+        NetworkInput::from(PackedInput::from(input))
+    }
+
+    #[test]
+    fn packed_input_is_eight_bytes() {
+        assert_eq!(std::mem::size_of::<PackedInput>(), 8);
+        let packed = PackedInput::from(NetworkInput::default());
+        let bytes = bytemuck::bytes_of(&packed);
+        assert_eq!(*bytemuck::from_bytes::<PackedInput>(bytes), packed);
+    }
+
+    #[test]
+    fn default_input_packs_to_zero() {
+        assert_eq!(PackedInput::from(NetworkInput::default()), PackedInput::zeroed());
+        assert_eq!(round_trip(NetworkInput::default()), NetworkInput::default());
+    }
+
+    #[test]
+    fn buttons_round_trip() {
+        for bits in 0..8u8 {
+            let input = NetworkInput {
+                jump: bits & 1 != 0,
+                shoot: bits & 2 != 0,
+                dodge: bits & 4 != 0,
+                ..default()
+            };
+            let unpacked = round_trip(input);
+            assert_eq!(unpacked.jump, input.jump);
+            assert_eq!(unpacked.shoot, input.shoot);
+            assert_eq!(unpacked.dodge, input.dodge);
+        }
+    }
+
+    #[test]
+    fn movement_round_trips_within_one_step() {
+        let diagonal = Vec2::new(1.0, 1.0).normalize();
+        for movement in [Vec2::ZERO, Vec2::X, Vec2::NEG_Y, diagonal, -diagonal] {
+            let unpacked = round_trip(NetworkInput { movement, ..default() });
+            assert!((unpacked.movement - movement).abs().max_element() <= 1.0 / i8::MAX as f32);
+        }
+    }
+
+    #[test]
+    fn aim_round_trips_within_quantization_error() {
+        let aims = [
+            Vec3::NEG_Z,
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::new(0.3, 0.4, -0.8).normalize(),
+            Vec3::new(-0.5, -0.2, 0.7).normalize(),
+            Vec3::new(0.0, 0.99, -0.1).normalize(),
+        ];
+        for aim in aims {
+            let unpacked = round_trip(NetworkInput { aim, ..default() });
+            assert!(unpacked.aim.angle_between(aim) < 1e-3, "{aim} came back as {}", unpacked.aim);
+            assert!((unpacked.aim.length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn repacking_is_stable() {
+        // Peers only ever exchange packed inputs, so unpack -> pack must not drift
+        let packed = PackedInput::from(NetworkInput {
+            movement: Vec2::new(-0.6, 0.8),
+            jump: true,
+            shoot: false,
+            dodge: true,
+            aim: Vec3::new(0.2, 0.1, -0.97).normalize(),
+            pause: true,
+        });
+        assert_eq!(PackedInput::from(NetworkInput::from(packed)), packed);
+    }
+
+    // A Direct Connect host and guest on this machine, their GGRS channels
+    // taken with the rules each of them plays by
+    fn channels(host_rules: &MatchRules, guest_rules: &MatchRules) -> [(PeerId, GgrsChannel); 2] {
+        let port = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut host = DirectSocket::host(port).unwrap();
+        let mut guest = DirectSocket::join((std::net::Ipv4Addr::LOCALHOST, port).into()).unwrap();
+        for _ in 0..200 {
+            host.poll();
+            guest.poll();
+            if host.connected_peers().count() == 1 && guest.connected_peers().count() == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        [(host, host_rules), (guest, guest_rules)].map(|(socket, rules)| {
+            let mut socket = PeerSocket::Direct(Box::new(socket));
+            let id = socket.id().unwrap();
+            (id, GgrsChannel::take(&mut socket, rules).unwrap())
+        })
+    }
+
+    // Sends the guest a keep-alive and waits until it's read or refused
+    fn keep_alive(
+        [(_, host), (guest_id, guest)]: &mut [(PeerId, GgrsChannel); 2],
+    ) -> Vec<(PeerId, Message)> {
+        // The one GGRS message with nothing in it: a magic number and `KeepAlive`
+        let (message, _): (Message, _) =
+            bincode::serde::decode_from_slice(&[1, 7], bincode::config::standard()).unwrap();
+        host.send_to(&message, guest_id);
+        for _ in 0..200 {
+            let received = guest.receive_all_messages();
+            if !received.is_empty() || !guest.refused().is_empty() {
+                return received;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("the keep-alive never arrived");
+    }
+
+    #[test]
+    fn ggrs_only_talks_to_peers_with_the_same_rules() {
+        let mut same = channels(&MatchRules::default(), &MatchRules::default());
+        let host = same[0].0;
+        assert!(matches!(keep_alive(&mut same)[..], [(from, _)] if from == host));
+        assert!(same[1].1.refused().is_empty());
+
+        let faster_reload = MatchRules {
+            bow: BowConfig {
+                reload_time: 0.5,
+                ..default()
+            },
+            ..default()
+        };
+        let mut other = channels(&faster_reload, &MatchRules::default());
+        let host = other[0].0;
+        assert!(keep_alive(&mut other).is_empty());
+        assert_eq!(other[1].1.refused(), [host]);
+    }
+}
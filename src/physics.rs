@@ -1,128 +1,261 @@
-use bevy::prelude::*;
-use avian3d::prelude::*;
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use avian3d::{prelude::*, sync::SyncConfig};
+use bevy_ggrs::{GgrsSchedule, Session};
+use crate::advancement::{Edge, EdgeCrossed, MatchState};
 use crate::components::*;
+use crate::networking::{GGRSConfig, RollbackSet};
 use crate::resources::*;
+use crate::rules::MatchRules;
+
+// Avian runs its whole pipeline inside this schedule, and we decide when it
+// ticks. Online that's exactly once per GGRS frame, so a rollback replays
+// physics step for step. Offline it's Bevy's fixed timestep.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PhysicsStep;
+
+// How far below the capsule's bottom we still count as standing on something
+const GROUND_TOLERANCE: f32 = 0.1;
 
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Update, (
-                update_ground_detection,
-                handle_boundaries,
-            ));
+            .add_plugins(PhysicsPlugins::new(PhysicsStep))
+            // Position is the source of truth. Copying Transform back into it
+            // goes through GlobalTransform, which rollback doesn't restore.
+            .insert_resource(SyncConfig {
+                transform_to_position: false,
+                ..default()
+            })
+            .add_event::<WallHit>()
+            .add_event::<PlayerFell>()
+            .add_event::<EdgeCrossed>()
+            .add_systems(
+                GgrsSchedule,
+                (step_physics, update_ground_detection, handle_boundaries)
+                    .chain()
+                    .in_set(RollbackSet::Physics),
+            )
+            .add_systems(
+                FixedPostUpdate,
+                (step_physics, update_ground_detection, handle_boundaries)
+                    .chain()
+                    .run_if(not(resource_exists::<Session<GGRSConfig>>)),
+            );
     }
 }
 
+// Advances Avian by one tick of whatever clock the calling schedule runs on
+pub fn step_physics(world: &mut World) {
+    world.run_schedule(PhysicsStep);
+}
+
+// Ground contact is re-derived from a raycast every tick rather than tracked
+// through collision events, so there's no state for a rollback to get wrong.
 pub fn update_ground_detection(
-    mut collision_event_reader: EventReader<CollisionStarted>,
-    mut collision_ended_reader: EventReader<CollisionEnded>,
-    mut player_query: Query<(Entity, &mut Player)>,
-    groundable_query: Query<Entity, With<Groundable>>,
+    mut player_query: Query<(Entity, &Position, &LinearVelocity, &mut Player)>,
+    groundable_query: Query<(), With<Groundable>>,
     spatial_query: SpatialQuery,
-    player_transform_query: Query<&Transform, With<Player>>,
 ) {
-    // Handle collision started events
-    for CollisionStarted(entity1, entity2) in collision_event_reader.read() {
-        // Check if player is colliding with groundable surface
-        if let Ok((player_entity, mut player)) = player_query.get_mut(*entity1) {
-            if groundable_query.contains(*entity2) {
-                player.is_grounded = true;
-                player.can_jump = true;
-            }
-        } else if let Ok((player_entity, mut player)) = player_query.get_mut(*entity2) {
-            if groundable_query.contains(*entity1) {
-                player.is_grounded = true;
-                player.can_jump = true;
-            }
-        }
+    let max_distance = PLAYER_HEIGHT / 2.0 + PLAYER_RADIUS + GROUND_TOLERANCE;
+
+    for (player_entity, position, velocity, mut player) in player_query.iter_mut() {
+        let filter = SpatialQueryFilter::default().with_excluded_entities([player_entity]);
+        let on_ground = spatial_query
+            .cast_ray_predicate(position.0, Dir3::NEG_Y, max_distance, true, &filter, &|entity| {
+                groundable_query.contains(entity)
+            })
+            .is_some();
+
+        // Still rising off the ground means we just jumped
+        let grounded = on_ground && velocity.y <= GROUND_TOLERANCE;
+        player.is_grounded = grounded;
+        player.can_jump = grounded;
     }
+}
 
-    // Handle collision ended events
-    for CollisionEnded(entity1, entity2) in collision_ended_reader.read() {
-        // Use raycast to double-check if player is still grounded
-        if let Ok((player_entity, mut player)) = player_query.get_mut(*entity1) {
-            if groundable_query.contains(*entity2) {
-                // Perform downward raycast to verify ground contact
-                if let Ok(transform) = player_transform_query.get(player_entity) {
-                    let ray_origin = transform.translation;
-                    let ray_direction = Dir3::NEG_Y;
-                    let max_distance = 1.2; // Slightly more than player height
-                    
-                    if let Some(_hit) = spatial_query.cast_ray(
-                        ray_origin,
-                        ray_direction,
-                        max_distance,
-                        true,
-                        &SpatialQueryFilter::default(),
-                    ) {
-                        // Still touching ground
-                        player.is_grounded = true;
-                        player.can_jump = true;
-                    } else {
-                        // No longer grounded
-                        player.is_grounded = false;
-                        player.can_jump = false;
-                    }
+// Sent when a player runs into a boundary that stops them
+#[derive(Event, Debug, Clone, Copy)]
+pub struct WallHit {
+    pub player: Entity,
+    pub player_id: u32,
+    pub edge: Edge,
+}
+
+// Sent when a player leaves the chunk over a kill boundary
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlayerFell {
+    pub player: Entity,
+    pub player_id: u32,
+    pub edge: Edge,
+}
+
+// Players still in play
+type BoundedPlayer = (
+    Entity,
+    &'static mut Player,
+    &'static mut Position,
+    &'static mut LinearVelocity,
+    &'static mut Transform,
+);
+
+// Every edge of the current chunk does what the rules' arena says it does
+// there. Kill boundaries let the player go and kill them on the way out,
+// advance boundaries let the right-of-way holder through to their goal and
+// stop everyone else, and walls stop everyone. Stopping a player holds them
+// on the edge and takes away the velocity carrying them out. Only players
+// who reach an edge from inside the chunk get here: whoever an advancement
+// leaves outside it is respawning by then (see `respawn.rs`).
+pub fn handle_boundaries(
+    mut player_query: Query<BoundedPlayer, Without<Respawning>>,
+    rules: Res<MatchRules>,
+    match_state: Res<MatchState>,
+    (mut wall_writer, mut fell_writer, mut edge_writer): (
+        EventWriter<WallHit>,
+        EventWriter<PlayerFell>,
+        EventWriter<EdgeCrossed>,
+    ),
+) {
+    let (west, east) = rules.arena.chunk_span(match_state.chunk);
+    let half_width = rules.arena.width / 2.0;
+    let boundaries = rules.arena.boundaries(match_state.chunk);
+
+    for (entity, mut player, mut position, mut velocity, mut transform) in player_query.iter_mut() {
+        if player.health <= 0 {
+            continue;
+        }
+
+        let crossed = [
+            (Edge::East, position.x > east),
+            (Edge::West, position.x < west),
+            (Edge::North, position.z < -half_width),
+            (Edge::South, position.z > half_width),
+        ];
+        for (edge, _) in crossed.into_iter().filter(|&(_, crossed)| crossed) {
+            let player_id = player.network_id;
+            match boundaries.get(edge) {
+                Boundary::Kill => {
+                    info!("Player {} fell off the {:?} edge", player_id, edge);
+                    player.health = 0;
+                    fell_writer.write(PlayerFell { player: entity, player_id, edge });
+                    break;
                 }
-            }
-        } else if let Ok((player_entity, mut player)) = player_query.get_mut(*entity2) {
-            if groundable_query.contains(*entity1) {
-                // Perform downward raycast to verify ground contact
-                if let Ok(transform) = player_transform_query.get(player_entity) {
-                    let ray_origin = transform.translation;
-                    let ray_direction = Dir3::NEG_Y;
-                    let max_distance = 1.2;
-                    
-                    if let Some(_hit) = spatial_query.cast_ray(
-                        ray_origin,
-                        ray_direction,
-                        max_distance,
-                        true,
-                        &SpatialQueryFilter::default(),
-                    ) {
-                        player.is_grounded = true;
-                        player.can_jump = true;
-                    } else {
-                        player.is_grounded = false;
-                        player.can_jump = false;
+                Boundary::Advance if match_state.can_advance(player_id, edge) => {
+                    edge_writer.write(EdgeCrossed { player: entity, player_id, edge });
+                }
+                Boundary::Advance | Boundary::Wall => {
+                    match edge {
+                        Edge::East => {
+                            position.x = east;
+                            velocity.x = velocity.x.min(0.0);
+                        }
+                        Edge::West => {
+                            position.x = west;
+                            velocity.x = velocity.x.max(0.0);
+                        }
+                        Edge::North => {
+                            position.z = -half_width;
+                            velocity.z = velocity.z.max(0.0);
+                        }
+                        Edge::South => {
+                            position.z = half_width;
+                            velocity.z = velocity.z.min(0.0);
+                        }
                     }
+                    wall_writer.write(WallHit { player: entity, player_id, edge });
                 }
             }
         }
+
+        // Combat reads Transform before the next step gets to sync it
+        transform.translation = position.0;
     }
 }
 
-pub fn handle_boundaries(
-    mut player_query: Query<(&mut Transform, &mut LinearVelocity), With<Player>>,
-    arena_config: Res<ArenaConfig>,
-) {
-    for (mut transform, mut velocity) in player_query.iter_mut() {
-        let half_length = arena_config.length / 2.0;
-        let half_width = arena_config.width / 2.0;
-
-        // Check and clamp player position within arena boundaries
-        if transform.translation.x > half_length {
-            transform.translation.x = half_length;
-            velocity.x = 0.0;
-        } else if transform.translation.x < -half_length {
-            transform.translation.x = -half_length;
-            velocity.x = 0.0;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
 
-        if transform.translation.z > half_width {
-            transform.translation.z = half_width;
-            velocity.z = 0.0;
-        } else if transform.translation.z < -half_width {
-            transform.translation.z = -half_width;
-            velocity.z = 0.0;
-        }
+    // A world with just enough in it for `handle_boundaries`, and one player
+    fn boundary_world(position: Vec3, velocity: Vec3) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<MatchRules>();
+        world.init_resource::<MatchState>();
+        world.init_resource::<Events<WallHit>>();
+        world.init_resource::<Events<PlayerFell>>();
+        world.init_resource::<Events<EdgeCrossed>>();
+        let player = world
+            .spawn((
+                Player::default(),
+                Position(position),
+                LinearVelocity(velocity),
+                Transform::from_translation(position),
+            ))
+            .id();
+        (world, player)
+    }
 
-        // Prevent falling below ground level
-        if transform.translation.y < 0.1 {
-            transform.translation.y = 0.1;
-            velocity.y = 0.0;
-        }
+    fn sent<E: Event + Copy>(world: &World) -> Vec<E> {
+        world.resource::<Events<E>>().iter_current_update_events().copied().collect()
+    }
+
+    #[test]
+    fn leaving_north_or_south_kills() {
+        let (mut world, player) = boundary_world(Vec3::new(0.0, 1.0, 25.5), Vec3::Z * 4.0);
+        world.run_system_once(handle_boundaries).unwrap();
+
+        assert_eq!(world.get::<Player>(player).unwrap().health, 0);
+        let fell = sent::<PlayerFell>(&world);
+        assert_eq!(fell.len(), 1);
+        assert_eq!(fell[0].edge, Edge::South);
+        // Nothing holds them back on the way down
+        assert_eq!(world.get::<Position>(player).unwrap().z, 25.5);
+        assert_eq!(world.get::<LinearVelocity>(player).unwrap().z, 4.0);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn goal_edge_is_a_wall_without_right_of_way() {
+        let (mut world, player) = boundary_world(Vec3::new(75.5, 1.0, 0.0), Vec3::new(3.0, 0.0, 1.0));
+        world.run_system_once(handle_boundaries).unwrap();
+
+        assert_eq!(world.get::<Position>(player).unwrap().x, 75.0);
+        assert_eq!(world.get::<Transform>(player).unwrap().translation.x, 75.0);
+        assert_eq!(world.get::<LinearVelocity>(player).unwrap().0, Vec3::Z);
+        assert_eq!(sent::<WallHit>(&world)[0].edge, Edge::East);
+        assert!(sent::<EdgeCrossed>(&world).is_empty());
+    }
+
+    #[test]
+    fn right_of_way_holder_crosses_into_the_next_chunk() {
+        let (mut world, player) = boundary_world(Vec3::new(75.5, 1.0, 0.0), Vec3::X * 3.0);
+        world.resource_mut::<MatchState>().record_kill(0, 1);
+        world.run_system_once(handle_boundaries).unwrap();
+
+        assert_eq!(world.get::<Position>(player).unwrap().x, 75.5);
+        assert!(sent::<WallHit>(&world).is_empty());
+        let crossed = sent::<EdgeCrossed>(&world);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!((crossed[0].player_id, crossed[0].edge), (0, Edge::East));
+    }
+
+    #[test]
+    fn chunks_can_override_their_boundaries() {
+        let (mut world, player) = boundary_world(Vec3::new(225.5, 1.0, -26.0), Vec3::ZERO);
+        world.resource_mut::<MatchState>().chunk = 1;
+        world.resource_mut::<MatchRules>().arena.chunk_boundaries.insert(
+            1,
+            ChunkBoundaries {
+                north: Boundary::Wall,
+                east: Boundary::Kill,
+                ..default()
+            },
+        );
+        world.run_system_once(handle_boundaries).unwrap();
+
+        assert_eq!(sent::<PlayerFell>(&world)[0].edge, Edge::East);
+        assert!(sent::<WallHit>(&world).is_empty());
+        assert_eq!(world.get::<Player>(player).unwrap().health, 0);
+    }
+}
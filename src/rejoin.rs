@@ -0,0 +1,834 @@
+use avian3d::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ggrs::{AddRollbackCommandExtension, GgrsTime, Rollback, RollbackFrameCount, Session};
+use bevy_matchbox::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::advancement::MatchState;
+use crate::components::*;
+use crate::control::{self, ControlMessage, MatchControl, MatchRoster};
+use crate::netsim::NetworkSimulator;
+use crate::network_stats::RollbackStats;
+use crate::networking::{
+    open_direct_socket, open_matchbox_socket, p2p_session, spawn_network_player, spectator_session,
+    GGRSConfig, GgrsChannel, PeerSocket, CONTROL_CHANNEL,
+};
+use crate::resources::*;
+use crate::rules::MatchRules;
+use crate::session_events::SessionHealth;
+use crate::signaling::{room_url, LocalSignalingServer};
+use crate::GameState;
+
+// How long a disconnected player's slot is held for them
+pub const REJOIN_GRACE: Duration = Duration::from_secs(60);
+
+// Rejoining
+// =========
+//
+// A dropped connection doesn't have to end the match. The dropped player
+// connects to the same room again, gets a new peer id from signaling, and
+// asks for their old slot back. One survivor (the lowest peer still
+// present, so everyone agrees who) snapshots the match and sends it to
+// everyone. Every peer, the sender included, throws away its simulated
+// state, rebuilds it from that snapshot and starts a new GGRS session from
+// frame 0. Nobody keeps rolling back into the old session, so it doesn't
+// matter that the snapshot may include a prediction or two: from here on
+// all peers simulate from the same state.
+//
+// The slot only goes back to whoever shows its rejoin token, and only once
+// GGRS has given up on the player who held it (see `MatchControl::can_rejoin`).
+// Requests go to the other players only, never to spectators.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RejoinMessage {
+    // Rejoining peer -> every other player
+    Request { previous: PeerId, token: u64 },
+    // Authority -> everyone
+    Resync(Box<Resync>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Resync {
+    pub previous: PeerId,
+    pub rejoined: PeerId,
+    // GGRS channel epoch for the new session
+    pub epoch: u8,
+    pub roster: MatchRoster,
+    pub snapshot: MatchSnapshot,
+}
+
+// Everything the rollback schedule simulates, with players named by peer
+// rather than handle since handles are handed out again afterwards
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchSnapshot {
+    pub frame: u32,
+    pub players: Vec<PlayerSnapshot>,
+    pub arrows: Vec<ArrowSnapshot>,
+    pub stats: StatsSnapshot,
+    // `MatchState`, by peer as well
+    pub right_of_way: Option<PeerId>,
+    pub chunk: i32,
+    pub advancements: Vec<(PeerId, u32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerSnapshot {
+    pub peer: PeerId,
+    pub player: Player,
+    pub bow: Bow,
+    pub dodge: DodgeAbility,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    pub respawning: Option<Respawning>,
+}
+
+// `GameStats`, by peer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatsSnapshot {
+    pub kills: Vec<(PeerId, u32)>,
+    pub deaths: Vec<(PeerId, u32)>,
+    pub shots_fired: Vec<(PeerId, u32)>,
+    pub shots_hit: Vec<(PeerId, u32)>,
+    pub game_time: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArrowSnapshot {
+    pub shooter: PeerId,
+    pub arrow: Arrow,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+impl MatchSnapshot {
+    // Who a snapshotted peer is once the rejoin has happened
+    fn renamed(peer: PeerId, resync: &Resync) -> PeerId {
+        if peer == resync.previous {
+            resync.rejoined
+        } else {
+            peer
+        }
+    }
+}
+
+// The UI asking to reconnect after we lost everyone
+#[derive(Event, Debug, Clone, Copy)]
+pub struct RequestRejoin;
+
+// Rejoin traffic picked up by `control::run_match_control`
+#[derive(Event, Debug, Clone)]
+pub struct RejoinReceived {
+    pub from: PeerId,
+    pub message: RejoinMessage,
+}
+
+// We're on a fresh socket, waiting for someone to take us back
+#[derive(Resource, Debug, Clone)]
+pub struct Rejoining {
+    pub previous: PeerId,
+    pub token: u64,
+    pub deadline: Duration, // Real time
+}
+
+// A resync waiting to be applied this frame
+#[derive(Resource, Debug, Clone)]
+struct PendingResync(Resync);
+
+pub struct RejoinPlugin;
+
+impl Plugin for RejoinPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RequestRejoin>()
+            .add_event::<RejoinReceived>()
+            .add_systems(OnEnter(GameState::MainMenu), cancel_rejoin)
+            .add_systems(OnEnter(GameState::GameOver), cancel_rejoin)
+            .add_systems(
+                Update,
+                (
+                    start_rejoin.run_if(not(resource_exists::<Rejoining>)),
+                    seek_rejoin
+                        .run_if(resource_exists::<Rejoining>.and(resource_exists::<PeerSocket>)),
+                    answer_rejoin.run_if(
+                        not(resource_exists::<Rejoining>).and(resource_exists::<PeerSocket>),
+                    ),
+                    (restart_session, restore_snapshot)
+                        .chain()
+                        .run_if(resource_exists::<PendingResync>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and(resource_exists::<MatchControl>)),
+            );
+    }
+}
+
+fn cancel_rejoin(mut commands: Commands) {
+    commands.remove_resource::<Rejoining>();
+    commands.remove_resource::<PendingResync>();
+}
+
+// Drops the old session and connects to the room again as a new peer. A
+// Direct Connect client connects to the host again; the host itself has
+// nowhere to go back to, everyone else only knew its address.
+fn start_rejoin(
+    mut commands: Commands,
+    mut requests: EventReader<RequestRejoin>,
+    control: Res<MatchControl>,
+    mut lobby_state: ResMut<LobbyState>,
+    settings: Res<SignalingSettings>,
+    local_server: Option<Res<LocalSignalingServer>>,
+    time: Res<Time<Real>>,
+) {
+    if requests.read().last().is_none() {
+        return;
+    }
+    let Some(room_id) = lobby_state.room_id.clone() else {
+        return;
+    };
+    let Some(token) = control.rejoin_token() else {
+        warn!(
+            "No rejoin token for {}, so there's no slot to take back",
+            control.local
+        );
+        return;
+    };
+
+    let socket = match lobby_state.transport {
+        LobbyTransport::Signaling => {
+            let url = room_url(&settings, local_server.as_deref(), &room_id);
+            PeerSocket::Matchbox(open_matchbox_socket(url))
+        }
+        LobbyTransport::DirectHost { .. } => {
+            warn!("The host of a Direct Connect match can't rejoin it");
+            return;
+        }
+        transport => match open_direct_socket(transport) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("{e}");
+                lobby_state.push_system_message(&format!("Couldn't rejoin: {e}"));
+                return;
+            }
+        },
+    };
+
+    info!("Rejoining room {room_id} in place of {}", control.local);
+    commands.remove_resource::<Session<GGRSConfig>>();
+    commands.remove_resource::<GgrsChannel>();
+    commands.insert_resource(socket);
+    commands.insert_resource(Rejoining {
+        previous: control.local,
+        token,
+        deadline: time.elapsed() + REJOIN_GRACE,
+    });
+    lobby_state.push_system_message(&format!("Rejoining room {room_id}..."));
+}
+
+// Asks every player we meet for our old slot until one of them answers
+fn seek_rejoin(
+    mut commands: Commands,
+    mut socket: ResMut<PeerSocket>,
+    (rejoining, control): (Res<Rejoining>, Res<MatchControl>),
+    mut connection_info: ResMut<ConnectionInfo>,
+    mut game_state: ResMut<NextState<GameState>>,
+    time: Res<Time<Real>>,
+) {
+    if time.elapsed() > rejoining.deadline {
+        warn!("Gave up rejoining as {}", rejoining.previous);
+        connection_info.connection_error = Some("Couldn't rejoin the match in time".to_string());
+        game_state.set(GameState::MainMenu);
+        return;
+    }
+    if socket.id().is_none() || !socket.has_channel(CONTROL_CHANNEL) {
+        return;
+    }
+
+    for (peer, state) in socket.update_peers() {
+        if state == PeerState::Connected && control.players.contains(&peer) {
+            let request = RejoinMessage::Request {
+                previous: rejoining.previous,
+                token: rejoining.token,
+            };
+            control::send(&mut socket, peer, ControlMessage::Rejoin(request));
+        }
+    }
+
+    for (from, message) in control::receive(&mut socket) {
+        match message {
+            ControlMessage::Rejoin(RejoinMessage::Resync(resync))
+                if resync.previous == rejoining.previous =>
+            {
+                info!("{from} took us back at frame {}", resync.snapshot.frame);
+                commands.insert_resource(PendingResync(*resync));
+            }
+            _ => {} // Nothing else is for us until we're back in
+        }
+    }
+}
+
+// Everything about a player that a snapshot carries
+type PlayerState = (
+    &'static Player,
+    &'static Bow,
+    &'static DodgeAbility,
+    &'static Position,
+    &'static Rotation,
+    &'static LinearVelocity,
+    &'static AngularVelocity,
+    Option<&'static Respawning>,
+);
+
+// Entities a resync throws away and respawns
+type Simulated = (With<Rollback>, Or<(With<Player>, With<Arrow>)>);
+
+// What the authority snapshots when someone asks to rejoin
+#[derive(SystemParam)]
+struct SnapshotSource<'w, 's> {
+    players: Query<'w, 's, PlayerState, With<Rollback>>,
+    arrows: Query<'w, 's, (&'static Arrow, &'static Transform), With<Rollback>>,
+    stats: Res<'w, GameStats>,
+    match_state: Res<'w, MatchState>,
+    frame: Res<'w, RollbackFrameCount>,
+    network_session: Res<'w, NetworkSession>,
+}
+
+impl SnapshotSource<'_, '_> {
+    fn capture(&self, control: &MatchControl) -> MatchSnapshot {
+        let peer = |id: u32| control.handles.get(id as usize).copied();
+
+        let mut players = self
+            .players
+            .iter()
+            .filter_map(
+                |(player, bow, dodge, position, rotation, linear, angular, respawning)| {
+                    Some(PlayerSnapshot {
+                        peer: peer(player.network_id)?,
+                        player: *player,
+                        bow: *bow,
+                        dodge: *dodge,
+                        position: position.0,
+                        rotation: rotation.0,
+                        linear_velocity: linear.0,
+                        angular_velocity: angular.0,
+                        respawning: respawning.copied(),
+                    })
+                },
+            )
+            .collect::<Vec<_>>();
+        players.sort_by_key(|snapshot| snapshot.player.network_id);
+
+        let arrows = self
+            .arrows
+            .iter()
+            .filter_map(|(arrow, transform)| {
+                Some(ArrowSnapshot {
+                    shooter: peer(arrow.shooter_id)?,
+                    arrow: *arrow,
+                    translation: transform.translation,
+                    rotation: transform.rotation,
+                })
+            })
+            .collect();
+
+        // Sorted, so every authority sends the same snapshot
+        let by_peer = |counts: &BTreeMap<u32, u32>| {
+            let mut counts = counts
+                .iter()
+                .filter_map(|(&id, &count)| Some((peer(id)?, count)))
+                .collect::<Vec<_>>();
+            counts.sort();
+            counts
+        };
+        let stats = StatsSnapshot {
+            kills: by_peer(&self.stats.kills),
+            deaths: by_peer(&self.stats.deaths),
+            shots_fired: by_peer(&self.stats.shots_fired),
+            shots_hit: by_peer(&self.stats.shots_hit),
+            game_time: self.stats.game_time,
+        };
+
+        let advancements = self
+            .match_state
+            .advancements
+            .iter()
+            .filter_map(|(&id, &advancements)| Some((peer(id)?, advancements)))
+            .collect();
+
+        MatchSnapshot {
+            frame: self.network_session.resumed_from + self.frame.0.max(0) as u32,
+            players,
+            arrows,
+            stats,
+            right_of_way: self.match_state.right_of_way.and_then(peer),
+            chunk: self.match_state.chunk,
+            advancements,
+        }
+    }
+}
+
+// Survivors: answer rejoin requests if it's our turn, and take resyncs
+// from whoever's turn it was
+fn answer_rejoin(
+    mut commands: Commands,
+    mut received: EventReader<RejoinReceived>,
+    mut socket: ResMut<PeerSocket>,
+    control: Res<MatchControl>,
+    channel: Option<Res<GgrsChannel>>,
+    source: SnapshotSource,
+) {
+    for RejoinReceived { from, message } in received.read() {
+        match message {
+            RejoinMessage::Request { previous, token } => {
+                if !control.can_rejoin(*previous, *token) {
+                    warn!("{from} asked for {previous}'s slot, which isn't open");
+                    continue;
+                }
+                if control.resync_authority(*previous) != Some(control.local) {
+                    continue; // Someone else will answer
+                }
+                let Some(channel) = channel.as_deref() else {
+                    continue;
+                };
+
+                let resync = Resync {
+                    previous: *previous,
+                    rejoined: *from,
+                    epoch: channel.epoch().wrapping_add(1),
+                    roster: control.rejoined_roster(*previous, *from),
+                    snapshot: source.capture(&control),
+                };
+                info!(
+                    "Taking {from} back as {previous} at frame {}",
+                    resync.snapshot.frame
+                );
+                let message = ControlMessage::Rejoin(RejoinMessage::Resync(Box::new(resync)));
+                let packet = control::encode(message.clone());
+                control::broadcast(&mut socket, message);
+
+                // Everyone else gets the snapshot after a JSON round trip, so
+                // we restore from the same one
+                if let Ok(ControlMessage::Rejoin(RejoinMessage::Resync(resync))) =
+                    control::decode(&packet)
+                {
+                    commands.insert_resource(PendingResync(*resync));
+                }
+                return;
+            }
+            RejoinMessage::Resync(resync) => {
+                if control.resync_authority(resync.previous) != Some(*from) {
+                    warn!("Ignoring resync from {from}, whose turn it wasn't");
+                    continue;
+                }
+                commands.insert_resource(PendingResync((**resync).clone()));
+                return;
+            }
+        }
+    }
+}
+
+// Takes on the new roster and starts the new session. Spectators follow
+// along on a new spectator session, unless it was the broadcaster who
+// dropped: they lost their feed with them and have already left.
+fn restart_session(
+    mut commands: Commands,
+    pending: Res<PendingResync>,
+    mut control: ResMut<MatchControl>,
+    mut socket: ResMut<PeerSocket>,
+    channel: Option<Res<GgrsChannel>>,
+    (mut lobby_state, simulator): (ResMut<LobbyState>, Res<NetworkSimulator>),
+    (mut session_health, rules): (ResMut<SessionHealth>, Res<MatchRules>),
+) {
+    let resync = &pending.0;
+    let Some(local) = socket.id() else {
+        return;
+    };
+    let lost_feed = control.broadcaster == Some(resync.previous);
+    let spectating = control.is_spectating() && !lost_feed;
+    if !resync.roster.players.contains(&local) && !spectating {
+        warn!("Resync for a match we're not in, ignoring it");
+        commands.remove_resource::<PendingResync>();
+        return;
+    }
+
+    // Survivors carry on with their channel, the rejoiner takes its new one
+    let channel = match channel {
+        Some(channel) => Ok(channel.with_epoch(resync.epoch)),
+        None => {
+            GgrsChannel::take(&mut socket, &rules).map(|channel| channel.with_epoch(resync.epoch))
+        }
+    };
+
+    control.adopt(&resync.roster);
+    control.pass_on_rejoin_token(resync.previous, resync.rejoined);
+    control.local = local;
+    if lost_feed {
+        control.broadcaster = Some(resync.rejoined);
+        control.spectators.clear();
+    }
+    // A spectator who left since the match started would hold up the start
+    let connected = socket.connected_peers();
+    let spectators = control
+        .fed_spectators()
+        .into_iter()
+        .filter(|peer| connected.contains(peer))
+        .collect::<Vec<_>>();
+    let session = channel.and_then(|channel| {
+        let socket = simulator.wrap(channel.clone());
+        let session = match control.broadcaster {
+            Some(broadcaster) if spectating => {
+                spectator_session(broadcaster, control.handles.len(), socket)?
+            }
+            _ => p2p_session(local, &control.handles, &spectators, socket)?,
+        };
+        Ok((session, channel))
+    });
+    let (session, channel) = match session {
+        Ok(started) => started,
+        Err(e) => {
+            error!("Failed to restart GGRS session after a rejoin: {e}");
+            lobby_state.push_system_message(&format!("Failed to resume the match: {e}"));
+            commands.remove_resource::<PendingResync>();
+            return;
+        }
+    };
+
+    if let Some(player) = lobby_state
+        .players
+        .iter_mut()
+        .find(|player| player.id == resync.previous.0)
+    {
+        player.id = resync.rejoined.0;
+    }
+    let name = lobby_state.player_name(resync.rejoined.0);
+    lobby_state.push_system_message(&format!("{name} rejoined the match"));
+
+    *session_health = SessionHealth::default();
+    commands.remove_resource::<Rejoining>();
+    commands.insert_resource(session);
+    commands.insert_resource(channel);
+    // bevy_ggrs only resets its frame count when there's no session at all,
+    // and its clock would refuse to run backwards to frame 0
+    commands.insert_resource(RollbackFrameCount(0));
+    commands.insert_resource(Time::new_with(GgrsTime));
+}
+
+// Replaces every rollback entity with what the snapshot says
+fn restore_snapshot(
+    mut commands: Commands,
+    pending: Res<PendingResync>,
+    (control, rules): (Res<MatchControl>, Res<MatchRules>),
+    simulated: Query<Entity, Simulated>,
+    mut contact_graph: ResMut<ContactGraph>,
+    (mut stats, mut match_state): (ResMut<GameStats>, ResMut<MatchState>),
+    (mut network_session, mut rollback_stats): (ResMut<NetworkSession>, ResMut<RollbackStats>),
+) {
+    let resync = &pending.0;
+    let snapshot = &resync.snapshot;
+    let handle = |peer: PeerId| control.handle_of(MatchSnapshot::renamed(peer, resync));
+
+    for entity in simulated.iter() {
+        commands.entity(entity).despawn();
+    }
+    // Contacts refer to the bodies we just removed. Every peer starts the
+    // solver cold, which is the same on all of them.
+    *contact_graph = ContactGraph::default();
+
+    for snapshot in &snapshot.players {
+        let Some(handle) = handle(snapshot.peer) else {
+            continue; // Didn't make it into the new session
+        };
+        let is_local = control.handles[handle] == control.local;
+        let transform =
+            Transform::from_translation(snapshot.position).with_rotation(snapshot.rotation);
+        let entity = spawn_network_player(&mut commands, &rules, handle, is_local, transform);
+        commands.entity(entity).insert((
+            Player {
+                id: handle as u32,
+                network_id: handle as u32,
+                is_local,
+                ..snapshot.player
+            },
+            snapshot.bow,
+            snapshot.dodge,
+            Position(snapshot.position),
+            Rotation(snapshot.rotation),
+            LinearVelocity(snapshot.linear_velocity),
+            AngularVelocity(snapshot.angular_velocity),
+        ));
+        if let Some(respawning) = snapshot.respawning {
+            commands.entity(entity).insert(respawning);
+        }
+    }
+
+    for snapshot in &snapshot.arrows {
+        let Some(shooter) = handle(snapshot.shooter) else {
+            continue;
+        };
+        commands
+            .spawn((
+                Arrow {
+                    shooter_id: shooter as u32,
+                    ..snapshot.arrow
+                },
+                Transform::from_translation(snapshot.translation).with_rotation(snapshot.rotation),
+            ))
+            .add_rollback();
+    }
+
+    let by_handle = |counts: &[(PeerId, u32)]| {
+        counts
+            .iter()
+            .filter_map(|&(peer, count)| Some((handle(peer)? as u32, count)))
+            .collect::<BTreeMap<_, _>>()
+    };
+    *stats = GameStats {
+        kills: by_handle(&snapshot.stats.kills),
+        deaths: by_handle(&snapshot.stats.deaths),
+        shots_fired: by_handle(&snapshot.stats.shots_fired),
+        shots_hit: by_handle(&snapshot.stats.shots_hit),
+        game_time: snapshot.stats.game_time,
+    };
+    *match_state = MatchState {
+        right_of_way: snapshot
+            .right_of_way
+            .and_then(|peer| Some(handle(peer)? as u32)),
+        chunk: snapshot.chunk,
+        advancements: snapshot
+            .advancements
+            .iter()
+            .filter_map(|&(peer, advancements)| Some((handle(peer)? as u32, advancements)))
+            .collect(),
+        // A win that wasn't confirmed yet is decided again on the first
+        // frame of the new session
+        ..default()
+    };
+
+    network_session.resumed_from = snapshot.frame;
+    rollback_stats.restart_frames();
+    info!(
+        "Resumed at frame {} with {} players and {} arrows",
+        snapshot.frame,
+        snapshot.players.len(),
+        snapshot.arrows.len()
+    );
+    commands.remove_resource::<PendingResync>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direct::DirectSocket;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use uuid::Uuid;
+
+    fn peer(n: u128) -> PeerId {
+        PeerId(Uuid::from_u128(n))
+    }
+
+    fn resync() -> Resync {
+        let player = PlayerSnapshot {
+            peer: peer(2),
+            player: Player {
+                id: 1,
+                network_id: 1,
+                ..default()
+            },
+            bow: Bow {
+                draw_time: 0.1 + 0.2,
+                ..default()
+            },
+            dodge: DodgeAbility::default(),
+            position: Vec3::new(1.0 / 3.0, 0.9, -std::f32::consts::PI),
+            rotation: Quat::from_rotation_y(0.7),
+            linear_velocity: Vec3::new(1e-7, -9.81 * 0.0333, 5.0),
+            angular_velocity: Vec3::ZERO,
+            respawning: Some(Respawning {
+                died_at: Vec3::new(-4.5, 0.2, 1.0 / 3.0),
+                timer: 2.9,
+            }),
+        };
+        let arrow = ArrowSnapshot {
+            shooter: peer(2),
+            arrow: Arrow {
+                damage: 1,
+                lifetime: 4.9666667,
+                shooter_id: 1,
+                velocity: Vec3::new(0.1, 17.3, -33.33333),
+                last_position: Vec3::new(2.0 / 7.0, 1.6, 0.0),
+            },
+            translation: Vec3::new(2.1, 1.7, -0.3),
+            rotation: Quat::from_rotation_x(-0.2),
+        };
+        Resync {
+            previous: peer(2),
+            rejoined: peer(9),
+            epoch: 1,
+            roster: MatchRoster {
+                players: vec![peer(1), peer(9)],
+                forfeited: Vec::new(),
+                disconnected: Vec::new(),
+            },
+            snapshot: MatchSnapshot {
+                frame: 1234,
+                players: vec![player],
+                arrows: vec![arrow],
+                stats: StatsSnapshot {
+                    kills: vec![(peer(2), 3)],
+                    deaths: vec![(peer(1), 3), (peer(2), 1)],
+                    shots_fired: vec![(peer(1), 5), (peer(2), 7)],
+                    shots_hit: vec![(peer(2), 3)],
+                    game_time: 41.0 / 3.0,
+                },
+                right_of_way: Some(peer(2)),
+                chunk: -2,
+                advancements: vec![(peer(2), 2)],
+            },
+        }
+    }
+
+    #[test]
+    fn snapshots_survive_the_wire_exactly() {
+        let message = ControlMessage::Rejoin(RejoinMessage::Resync(Box::new(resync())));
+        let decoded = control::decode(&control::encode(message.clone())).unwrap();
+        assert_eq!(decoded, message);
+
+        // Equal isn't enough for floats we're going to simulate from
+        let (
+            ControlMessage::Rejoin(RejoinMessage::Resync(sent)),
+            ControlMessage::Rejoin(RejoinMessage::Resync(got)),
+        ) = (message, decoded)
+        else {
+            unreachable!();
+        };
+        let bits = |v: Vec3| v.to_array().map(f32::to_bits);
+        assert_eq!(
+            bits(sent.snapshot.players[0].position),
+            bits(got.snapshot.players[0].position)
+        );
+        assert_eq!(
+            bits(sent.snapshot.arrows[0].arrow.velocity),
+            bits(got.snapshot.arrows[0].arrow.velocity)
+        );
+        assert_eq!(
+            sent.snapshot.players[0].bow.draw_time.to_bits(),
+            got.snapshot.players[0].bow.draw_time.to_bits()
+        );
+        assert_eq!(
+            sent.snapshot.stats.game_time.to_bits(),
+            got.snapshot.stats.game_time.to_bits()
+        );
+    }
+
+    // Peer 1, still playing after peer 2 dropped. The socket is a Direct
+    // Connect client nobody answers, so nothing leaves the machine.
+    fn survivor() -> World {
+        let mut control = MatchControl::new(peer(1), &[peer(1), peer(2)])
+            .with_rejoin_tokens(&[(peer(1), 11), (peer(2), 22)]);
+        control.disconnect(peer(2));
+        let nobody = SocketAddr::from((Ipv4Addr::LOCALHOST, 9));
+        let mut socket = PeerSocket::Direct(Box::new(DirectSocket::join(nobody).unwrap()));
+        let channel = GgrsChannel::take(&mut socket, &MatchRules::default()).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(control);
+        world.insert_resource(socket);
+        world.insert_resource(channel);
+        world.insert_resource(RollbackFrameCount(0));
+        world.init_resource::<GameStats>();
+        world.init_resource::<MatchState>();
+        world.init_resource::<NetworkSession>();
+        world.init_resource::<Events<RejoinReceived>>();
+        world
+    }
+
+    fn ask(world: &mut World, from: u128, previous: u128, token: u64) -> Option<Resync> {
+        world.send_event(RejoinReceived {
+            from: peer(from),
+            message: RejoinMessage::Request {
+                previous: peer(previous),
+                token,
+            },
+        });
+        world.run_system_once(answer_rejoin).unwrap();
+        world.resource_mut::<Events<RejoinReceived>>().clear();
+        world
+            .remove_resource::<PendingResync>()
+            .map(|pending| pending.0)
+    }
+
+    #[test]
+    fn only_the_dropped_players_token_takes_their_slot() {
+        let mut world = survivor();
+        assert!(ask(&mut world, 7, 2, 23).is_none(), "wrong token");
+        assert!(ask(&mut world, 7, 1, 11).is_none(), "still playing");
+
+        let resync = ask(&mut world, 9, 2, 22).expect("peer 2's token");
+        assert_eq!((resync.previous, resync.rejoined), (peer(2), peer(9)));
+        assert_eq!(resync.roster.players, vec![peer(1), peer(9)]);
+        assert_eq!(resync.epoch, 1);
+    }
+
+    #[test]
+    fn restoring_replaces_the_match_with_the_snapshot() {
+        let resync = resync();
+        let mut control = MatchControl::new(peer(1), &[peer(1), peer(2)]);
+        control.adopt(&resync.roster);
+
+        let mut world = World::new();
+        world.insert_resource(control);
+        world.insert_resource(PendingResync(resync));
+        world.init_resource::<MatchRules>();
+        world.init_resource::<ContactGraph>();
+        world.init_resource::<GameStats>();
+        world.init_resource::<MatchState>();
+        world.init_resource::<NetworkSession>();
+        world.init_resource::<RollbackStats>();
+        let stale = world
+            .commands()
+            .spawn(Player::default())
+            .add_rollback()
+            .id();
+        world.flush();
+
+        world.run_system_once(restore_snapshot).unwrap();
+
+        assert!(world.get_entity(stale).is_err());
+        assert!(!world.contains_resource::<PendingResync>());
+        // Peer 2 came back as peer 9, who sorts after peer 1
+        let mut players = world.query::<(&Player, &Position, Has<Respawning>)>();
+        let [(player, position, respawning)] = players.iter(&world).collect::<Vec<_>>()[..] else {
+            panic!("one player in the snapshot");
+        };
+        assert_eq!((player.network_id, player.is_local), (1, false));
+        assert_eq!(position.0, Vec3::new(1.0 / 3.0, 0.9, -std::f32::consts::PI));
+        assert!(respawning);
+        let mut arrows = world.query::<&Arrow>();
+        assert_eq!(
+            arrows
+                .iter(&world)
+                .map(|arrow| arrow.shooter_id)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+
+        let stats = world.resource::<GameStats>();
+        assert_eq!(stats.kills, [(1, 3)].into());
+        assert_eq!(stats.deaths, [(0, 3), (1, 1)].into());
+        assert_eq!(stats.game_time, 41.0 / 3.0);
+        let match_state = world.resource::<MatchState>();
+        assert_eq!(match_state.right_of_way, Some(1));
+        assert_eq!(match_state.chunk, -2);
+        assert_eq!(match_state.advancements, [(1, 2)].into());
+        assert_eq!(world.resource::<NetworkSession>().resumed_from, 1234);
+    }
+
+    #[test]
+    fn the_rejoined_peer_takes_over_the_old_one() {
+        let resync = resync();
+        assert_eq!(MatchSnapshot::renamed(peer(2), &resync), peer(9));
+        assert_eq!(MatchSnapshot::renamed(peer(1), &resync), peer(1));
+    }
+}
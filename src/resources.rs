@@ -1,37 +1,251 @@
 use bevy::prelude::*;
-// use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use uuid::Uuid;
 
+use crate::advancement::Edge;
+use crate::direct::DEFAULT_DIRECT_PORT;
+
 // Arena Configuration
-#[derive(Resource, Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ArenaConfig {
     pub width: f32,
-    pub height: f32,
-    pub depth: f32,
-    pub length: f32,
-    pub boundary_force: f32,
+    pub length: f32, // Of one chunk
+    pub chunks: i32, // Chunks of ground either side of the middle one
+    pub boundaries: ChunkBoundaries, // Every chunk without an override
+    // Ordered, so the rules fingerprint doesn't depend on hash order
+    pub chunk_boundaries: BTreeMap<i32, ChunkBoundaries>,
 }
 
 impl Default for ArenaConfig {
     fn default() -> Self {
         Self {
-            width: 10.0,
-            height: 10.0,
-            depth: 10.0,
-            length: 10.0,
-            boundary_force: 50.0,
+            width: 50.0,
+            length: 150.0,
+            chunks: 5, // Enough for a first-to-5 match to end at either side
+            boundaries: ChunkBoundaries::default(),
+            chunk_boundaries: BTreeMap::new(),
+        }
+    }
+}
+
+impl ArenaConfig {
+    // Where chunk `chunk` starts and ends along x. Chunks sit end to end,
+    // with chunk 0 centred on the origin.
+    pub fn chunk_span(&self, chunk: i32) -> (f32, f32) {
+        let center = chunk as f32 * self.length;
+        (center - self.length / 2.0, center + self.length / 2.0)
+    }
+
+    pub fn boundaries(&self, chunk: i32) -> ChunkBoundaries {
+        self.chunk_boundaries
+            .get(&chunk)
+            .copied()
+            .unwrap_or(self.boundaries)
+    }
+}
+
+// What happens to a player who reaches an edge of the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+    Kill,
+    // Lets the right-of-way holder through to their goal, a wall to the rest
+    Advance,
+    Wall,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkBoundaries {
+    pub north: Boundary,
+    pub south: Boundary,
+    pub east: Boundary,
+    pub west: Boundary,
+}
+
+// design.md: falling off north/south is death, east/west is advancement
+impl Default for ChunkBoundaries {
+    fn default() -> Self {
+        Self {
+            north: Boundary::Kill,
+            south: Boundary::Kill,
+            east: Boundary::Advance,
+            west: Boundary::Advance,
+        }
+    }
+}
+
+impl ChunkBoundaries {
+    pub fn get(&self, edge: Edge) -> Boundary {
+        match edge {
+            Edge::North => self.north,
+            Edge::South => self.south,
+            Edge::East => self.east,
+            Edge::West => self.west,
+        }
+    }
+}
+
+// Bow Tuning
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BowConfig {
+    pub reload_time: f32,
+    pub full_draw_time: f32,
+    pub min_charge: f32,
+    pub charge_curve: ChargeCurve,
+    pub min_arrow_speed: f32,
+    pub max_arrow_speed: f32,
+    pub arrow_gravity: f32,
+    pub arrow_lifetime: f32,
+}
+
+// Shape of the power ramp while the bow is held. Kept to plain
+// multiplications so every peer computes bit-identical charges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChargeCurve {
+    Linear,
+    #[default]
+    EaseOut, // Most of the power comes early, the last bit takes patience
+    EaseIn,
+}
+
+impl Default for BowConfig {
+    fn default() -> Self {
+        Self {
+            reload_time: 1.0, // 1-second reload from design.md
+            full_draw_time: 1.2,
+            min_charge: 0.25, // A quick tap still fires a short arrow
+            charge_curve: ChargeCurve::default(),
+            min_arrow_speed: 12.0,
+            max_arrow_speed: 40.0,
+            arrow_gravity: 9.81,
+            arrow_lifetime: 5.0,
         }
     }
 }
 
-// Game Statistics
+impl BowConfig {
+    // Power in [min_charge, 1.0] after holding the draw for `draw_time` seconds
+    pub fn charge_for(&self, draw_time: f32) -> f32 {
+        let t = (draw_time / self.full_draw_time).clamp(0.0, 1.0);
+        let shaped = match self.charge_curve {
+            ChargeCurve::Linear => t,
+            ChargeCurve::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            ChargeCurve::EaseIn => t * t,
+        };
+        self.min_charge + (1.0 - self.min_charge) * shaped
+    }
+
+    // Launch speed of an arrow released at `charge`
+    pub fn arrow_speed(&self, charge: f32) -> f32 {
+        self.min_arrow_speed + (self.max_arrow_speed - self.min_arrow_speed) * charge
+    }
+}
+
+// Dodge Tuning
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DodgeConfig {
+    pub cooldown: f32,
+    pub invincibility_time: f32,
+    pub dash_impulse: f32,
+}
+
+impl Default for DodgeConfig {
+    fn default() -> Self {
+        Self {
+            cooldown: 7.0, // design.md: 7-second cooldown
+            invincibility_time: 1.0, // design.md: 1 second of i-frames
+            dash_impulse: 12.0,
+        }
+    }
+}
+
+// Respawn Tuning
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RespawnConfig {
+    pub distance: f32,
+    pub spawn_ahead: f32,
+    pub delay: f32,
+}
+
+impl Default for RespawnConfig {
+    fn default() -> Self {
+        Self {
+            distance: 20.0, // design.md: 20m threshold
+            spawn_ahead: 10.0, // In front of the advancer, with room to react
+            delay: 3.0, // Only when nobody holds the right of way
+        }
+    }
+}
+
+// Game Statistics, by player handle. Rolled back, so a mispredicted kill
+// never gets counted, and ordered, so the checksum doesn't depend on hash
+// order.
 #[derive(Resource, Debug, Clone, Default)]
 pub struct GameStats {
-    pub kills: std::collections::HashMap<u32, u32>,
+    pub kills: BTreeMap<u32, u32>,
+    pub deaths: BTreeMap<u32, u32>,
+    pub shots_fired: BTreeMap<u32, u32>,
+    pub shots_hit: BTreeMap<u32, u32>,
+    pub game_time: f32,
+}
+
+impl GameStats {
+    // One results line for each `(handle, name)`, in that order
+    pub fn results(&self, players: &[(u32, String)]) -> Vec<PlayerResult> {
+        let count = |map: &BTreeMap<u32, u32>, id: u32| map.get(&id).copied().unwrap_or(0);
+        players
+            .iter()
+            .map(|(id, name)| PlayerResult {
+                name: name.clone(),
+                kills: count(&self.kills, *id),
+                deaths: count(&self.deaths, *id),
+                shots_fired: count(&self.shots_fired, *id),
+                shots_hit: count(&self.shots_hit, *id),
+            })
+            .collect()
+    }
+}
+
+// How a match ended, for the game over screen
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct MatchResult {
+    pub winners: Vec<String>,
+    pub reason: MatchEndReason,
+    pub players: Vec<PlayerResult>,
+    pub duration: f32, // Seconds of simulated play
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchEndReason {
+    // Everyone else gave up or dropped out
+    Forfeit {
+        forfeited: Vec<String>,
+        disconnected: Vec<String>,
+    },
+    // The winner ran off the opponent's end this many times
+    Advancements { advancements: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerResult {
+    pub name: String,
+    pub kills: u32,
     pub deaths: u32,
     pub shots_fired: u32,
     pub shots_hit: u32,
-    pub game_time: f32,
+}
+
+impl PlayerResult {
+    // Share of arrows that hit, if any were fired
+    pub fn accuracy(&self) -> Option<f32> {
+        (self.shots_fired > 0).then(|| self.shots_hit as f32 / self.shots_fired as f32)
+    }
 }
 
 // Lobby Management
@@ -47,6 +261,19 @@ pub struct LobbyState {
     pub local_player_name: String,
     pub chat_messages: Vec<ChatMessage>,
     pub current_chat_input: String,
+    pub transport: LobbyTransport,
+}
+
+// How the peers in a lobby reach each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LobbyTransport {
+    // A signaling server, then WebRTC
+    #[default]
+    Signaling,
+    // Direct Connect: we listen on this UDP port
+    DirectHost { port: u16 },
+    // Direct Connect to a host's address
+    DirectJoin { host: SocketAddr },
 }
 
 #[derive(Debug, Clone)]
@@ -56,6 +283,7 @@ pub struct LobbyPlayer {
     pub is_ready: bool,
     pub network_handle: u32, // Simplified for now
     pub is_local: bool,
+    pub is_spectator: bool, // Joined a full lobby; watches instead of playing
 }
 
 #[derive(Debug, Clone)]
@@ -86,10 +314,40 @@ impl Default for LobbyState {
             local_player_name: "Player".to_string(),
             chat_messages: Vec::new(),
             current_chat_input: String::new(),
+            transport: LobbyTransport::default(),
         }
     }
 }
 
+impl LobbyState {
+    // Display name for a peer, even one that never made it into the roster
+    pub fn player_name(&self, id: Uuid) -> String {
+        self.players
+            .iter()
+            .find(|player| player.id == id)
+            .map(|player| player.name.clone())
+            .unwrap_or_else(|| format!("Player_{id}"))
+    }
+
+    pub fn push_system_message(&mut self, message: &str) {
+        self.chat_messages.push(ChatMessage {
+            sender: "System".to_string(),
+            message: message.to_string(),
+            timestamp: 0.0, // Would use actual time in production
+            message_type: ChatMessageType::System,
+        });
+    }
+
+    pub fn push_chat_message(&mut self, sender: &str, message: &str) {
+        self.chat_messages.push(ChatMessage {
+            sender: sender.to_string(),
+            message: message.to_string(),
+            timestamp: 0.0,
+            message_type: ChatMessageType::Player,
+        });
+    }
+}
+
 // Connection Information
 #[derive(Resource, Debug, Clone, Default)]
 pub struct ConnectionInfo {
@@ -110,10 +368,61 @@ pub enum ConnectionStatus {
     Failed(String),
 }
 
+// Signaling Server Settings
+#[derive(Resource, Debug, Clone)]
+pub struct SignalingSettings {
+    pub server_url: String, // Rooms live at {server_url}/{room_id}
+    pub host_local_server: bool,
+    pub local_server_port: u16,
+}
+
+impl Default for SignalingSettings {
+    fn default() -> Self {
+        Self {
+            server_url: "ws://44.206.226.40:3536".to_string(),
+            host_local_server: false,
+            local_server_port: 3536, // Same port matchbox_server uses
+        }
+    }
+}
+
+// Direct Connect Settings
+#[derive(Resource, Debug, Clone)]
+pub struct DirectConnectSettings {
+    pub port: u16, // Where we listen when hosting
+    pub host_address: String, // host:port, as typed
+}
+
+impl Default for DirectConnectSettings {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_DIRECT_PORT,
+            host_address: String::new(),
+        }
+    }
+}
+
+// SyncTest Dev Mode
+#[derive(Resource, Debug, Clone)]
+pub struct SyncTestSettings {
+    pub num_players: usize,
+    pub check_distance: usize, // Frames rolled back and resimulated every tick
+}
+
+impl Default for SyncTestSettings {
+    fn default() -> Self {
+        Self {
+            num_players: 2,
+            check_distance: 7, // Deep enough to cover a typical P2P rollback
+        }
+    }
+}
+
 // Network Session Information
 #[derive(Resource, Debug, Clone, Default)]
 pub struct NetworkSession {
     pub session_id: Option<String>,
     pub is_active: bool,
     pub frame_count: u32,
+    pub resumed_from: u32, // Match frame the current GGRS session started on
 } 
\ No newline at end of file
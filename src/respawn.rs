@@ -0,0 +1,264 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_ggrs::GgrsSchedule;
+
+use crate::advancement::{check_for_winner, forward, MatchState};
+use crate::combat::FIXED_DT;
+use crate::components::*;
+use crate::networking::RollbackSet;
+use crate::resources::*;
+use crate::rules::MatchRules;
+
+// Same height `spawn_position` drops players in from
+const SPAWN_HEIGHT: f32 = 1.0;
+// Nobody respawns closer than this to either end of the chunk
+const SPAWN_MARGIN: f32 = 2.0;
+
+// Respawning
+// ==========
+//
+// A player whose health runs out stays down, ignoring their inputs, until
+// the advancing player (whoever holds the right of way) is `distance` metres
+// past the spot where they fell. Then they come back `spawn_ahead` metres in
+// front of the advancer, in the advancer's lane, with no spawn protection.
+// If nobody can advance (no kill yet, or the holder is the one who died)
+// there's nobody to wait for, so they come back where they fell after
+// `delay` seconds instead.
+//
+// When the fight moves on a chunk, anyone still alive in the old one is
+// left outside it. They go down on the spot and come back the same way,
+// in front of the advancer, rather than being dragged along to the new
+// chunk's edge. Nobody killed them, so it isn't a death in the stats.
+//
+// Everything here is derived from rolled-back state with plain arithmetic,
+// so every peer picks the same spawn point on the same frame.
+pub struct RespawnPlugin;
+
+impl Plugin for RespawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchRules>().add_systems(
+            GgrsSchedule,
+            (leave_behind_stragglers, start_respawning, respawn_players)
+                .chain()
+                .in_set(RollbackSet::Advancement)
+                .after(check_for_winner),
+        );
+    }
+}
+
+// Where a dead player comes back in front of the advancer, once the
+// advancer has got far enough past where they died. `span` is the chunk the
+// fight is in.
+pub fn respawn_point(
+    died_at: Vec3,
+    advancer_id: u32,
+    advancer_at: Vec3,
+    span: (f32, f32),
+    config: &RespawnConfig,
+) -> Option<Vec3> {
+    let forward = forward(advancer_id);
+    if (advancer_at.x - died_at.x) * forward < config.distance {
+        return None;
+    }
+    let x = advancer_at.x + forward * config.spawn_ahead;
+    Some(Vec3::new(
+        clamp_to_span(x, span),
+        SPAWN_HEIGHT,
+        advancer_at.z,
+    ))
+}
+
+// Where a dead player comes back when nobody is advancing
+pub fn fallback_point(died_at: Vec3, span: (f32, f32)) -> Vec3 {
+    Vec3::new(clamp_to_span(died_at.x, span), SPAWN_HEIGHT, 0.0)
+}
+
+fn clamp_to_span(x: f32, (start, end): (f32, f32)) -> f32 {
+    // Not `clamp`, which panics on chunks shorter than both margins
+    x.max(start + SPAWN_MARGIN).min(end - SPAWN_MARGIN)
+}
+
+pub fn leave_behind_stragglers(
+    rules: Res<MatchRules>,
+    match_state: Res<MatchState>,
+    mut player_query: Query<(&mut Player, &Position), Without<Respawning>>,
+) {
+    let (west, east) = rules.arena.chunk_span(match_state.chunk);
+    for (mut player, position) in player_query.iter_mut() {
+        if player.health > 0 && (position.x < west || position.x > east) {
+            info!(
+                "Player {} was left behind in the last chunk",
+                player.network_id
+            );
+            player.health = 0;
+        }
+    }
+}
+
+pub fn start_respawning(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    player_query: Query<(Entity, &Player, &Position), Without<Respawning>>,
+) {
+    for (entity, player, position) in player_query.iter() {
+        if player.health > 0 {
+            continue;
+        }
+        commands.entity(entity).insert(Respawning {
+            died_at: position.0,
+            timer: rules.respawn.delay,
+        });
+    }
+}
+
+type DeadPlayer = (
+    Entity,
+    &'static mut Player,
+    &'static mut Respawning,
+    &'static Transform,
+);
+
+pub fn respawn_players(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    match_state: Res<MatchState>,
+    mut dead_query: Query<DeadPlayer>,
+    living_query: Query<(&Player, &Position), Without<Respawning>>,
+) {
+    let span = rules.arena.chunk_span(match_state.chunk);
+    let advancer = match_state.right_of_way.and_then(|id| {
+        living_query
+            .iter()
+            .find(|(player, _)| player.network_id == id && player.health > 0)
+            .map(|(_, position)| (id, position.0))
+    });
+
+    for (entity, mut player, mut respawning, transform) in dead_query.iter_mut() {
+        let spawn = match advancer {
+            Some((advancer_id, advancer_at)) if advancer_id != player.network_id => respawn_point(
+                respawning.died_at,
+                advancer_id,
+                advancer_at,
+                span,
+                &rules.respawn,
+            ),
+            _ => {
+                respawning.timer -= FIXED_DT;
+                (respawning.timer <= 0.0).then(|| fallback_point(respawning.died_at, span))
+            }
+        };
+        let Some(spawn) = spawn else {
+            continue;
+        };
+
+        info!("Player {} respawned at {}", player.network_id, spawn);
+        player.health = Player::default().health;
+        commands
+            .entity(entity)
+            .insert((
+                Position(spawn),
+                Transform {
+                    translation: spawn,
+                    ..*transform
+                },
+                LinearVelocity::ZERO,
+                AngularVelocity::ZERO,
+                Bow::default(),
+                DodgeAbility::default(), // No spawn protection
+            ))
+            .remove::<Respawning>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPAN: (f32, f32) = (-75.0, 75.0);
+
+    #[test]
+    fn nobody_respawns_until_the_advancer_is_far_enough_past() {
+        let config = RespawnConfig::default();
+        let died_at = Vec3::new(-10.0, 0.5, 3.0);
+
+        // Handle 0 advances east
+        let short = Vec3::new(9.9, 1.0, 0.0);
+        assert_eq!(respawn_point(died_at, 0, short, SPAN, &config), None);
+        let far = Vec3::new(10.0, 1.0, -4.0);
+        assert_eq!(
+            respawn_point(died_at, 0, far, SPAN, &config),
+            Some(Vec3::new(20.0, SPAWN_HEIGHT, -4.0))
+        );
+
+        // Handle 1 advances west, so being east of the body doesn't count
+        assert_eq!(respawn_point(died_at, 1, far, SPAN, &config), None);
+        let died_at = Vec3::new(10.0, 0.5, 0.0);
+        let far = Vec3::new(-15.0, 1.0, 2.0);
+        assert_eq!(
+            respawn_point(died_at, 1, far, SPAN, &config),
+            Some(Vec3::new(-25.0, SPAWN_HEIGHT, 2.0))
+        );
+    }
+
+    #[test]
+    fn spawn_points_stay_inside_the_chunk() {
+        let config = RespawnConfig::default();
+        let advancer_at = Vec3::new(70.0, 1.0, 0.0);
+        let spawn = respawn_point(Vec3::ZERO, 0, advancer_at, SPAN, &config).unwrap();
+        assert_eq!(spawn.x, SPAN.1 - SPAWN_MARGIN);
+
+        let fallback = fallback_point(Vec3::new(-80.0, -3.0, 30.0), SPAN);
+        assert_eq!(
+            fallback,
+            Vec3::new(SPAN.0 + SPAWN_MARGIN, SPAWN_HEIGHT, 0.0)
+        );
+    }
+
+    #[test]
+    fn an_opponent_left_behind_respawns_instead_of_being_dragged_along() {
+        use crate::advancement::{apply_edge_crossings, EdgeCrossed, PlayerAdvanced};
+        use crate::physics::{handle_boundaries, PlayerFell, WallHit};
+        use bevy::ecs::system::RunSystemOnce;
+
+        let mut world = World::new();
+        world.init_resource::<MatchRules>();
+        world.init_resource::<MatchState>();
+        world.init_resource::<Events<WallHit>>();
+        world.init_resource::<Events<PlayerFell>>();
+        world.init_resource::<Events<EdgeCrossed>>();
+        world.init_resource::<Events<PlayerAdvanced>>();
+        world.resource_mut::<MatchState>().record_kill(0, 1);
+        let mut spawn = |network_id, x| {
+            let at = Vec3::new(x, 1.0, 0.0);
+            world
+                .spawn((
+                    Player {
+                        network_id,
+                        ..default()
+                    },
+                    Position(at),
+                    LinearVelocity(Vec3::X),
+                    Transform::from_translation(at),
+                ))
+                .id()
+        };
+        // Player 0 runs off the east end of chunk 0 with player 1 alive behind
+        let advancer = spawn(0, 75.5);
+        let opponent = spawn(1, 10.0);
+
+        world.run_system_once(handle_boundaries).unwrap();
+        world.run_system_once(apply_edge_crossings).unwrap();
+        assert_eq!(world.resource::<MatchState>().chunk, 1);
+        world.run_system_once(leave_behind_stragglers).unwrap();
+        world.run_system_once(start_respawning).unwrap();
+        // The next frame's boundaries leave them where they were
+        world.run_system_once(handle_boundaries).unwrap();
+
+        assert_eq!(world.get::<Player>(opponent).unwrap().health, 0);
+        assert_eq!(world.get::<Position>(opponent).unwrap().x, 10.0);
+        let respawning = world.get::<Respawning>(opponent).unwrap();
+        assert_eq!(respawning.died_at.x, 10.0);
+        assert_eq!(world.get::<Player>(advancer).unwrap().health, 1);
+        assert!(world.get::<Respawning>(advancer).is_none());
+    }
+}
@@ -0,0 +1,229 @@
+use bevy::prelude::*;
+use bevy_ggrs::checksum_hasher;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+use std::path::Path;
+
+use crate::cli::CliArgs;
+use crate::components::Player;
+use crate::resources::*;
+use crate::GameState;
+
+// Match Rules
+// ===========
+//
+// Every number the simulation plays by, in one place. The rules are read
+// from a RON file (`--rules <PATH>`, see `rules.ron` for the defaults) when
+// the game starts, and any field the file leaves out keeps its default.
+//
+// The rules are part of the simulation: two peers stepping the same inputs
+// with different reload times drift apart. So a client plays by the host's
+// rules, sent over in the lobby, and both sides compare fingerprints before
+// a match starts (see `lobby.rs`). That check happens once, in the lobby,
+// and the session that follows takes it on trust. So the fingerprint is
+// also the GGRS session's handshake: every GGRS packet carries it, and a
+// peer with other rules never gets as far as synchronizing (see
+// `GgrsChannel`). Our own rules, the ones we host by, are kept in
+// `HouseRules` and back in force at the main menu.
+//
+// Rules that can't make a match, like a first-to-7 on an arena with room
+// for 5 advancements, are turned away when they're loaded.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchRules {
+    pub movement_speed: f32,
+    pub jump_force: f32,
+    pub advancements_to_win: u32,
+    pub bow: BowConfig,
+    pub dodge: DodgeConfig,
+    pub respawn: RespawnConfig,
+    pub arena: ArenaConfig,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            movement_speed: 5.0,    // 5 meter per second base movement
+            jump_force: 8.0,        // Hop-style jump force
+            advancements_to_win: 5, // design.md: first to 5 advancements
+            bow: BowConfig::default(),
+            dodge: DodgeConfig::default(),
+            respawn: RespawnConfig::default(),
+            arena: ArenaConfig::default(),
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let rules: Self = ron::from_str(text).map_err(|e| e.to_string())?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // Ground is only laid down for `chunks` either side of the middle
+        // (see `setup_scene`), so every advancement to win needs one
+        if self.arena.chunks < 0 || self.advancements_to_win > self.arena.chunks as u32 {
+            return Err(format!(
+                "advancements_to_win is {} but the arena only has room for {} advancements",
+                self.advancements_to_win,
+                self.arena.chunks.max(0)
+            ));
+        }
+        let positive = [
+            ("arena.length", self.arena.length),
+            ("arena.width", self.arena.width),
+            ("bow.full_draw_time", self.bow.full_draw_time),
+            ("dodge.cooldown", self.dodge.cooldown),
+        ];
+        match positive
+            .iter()
+            .find(|(_, value)| value.is_nan() || *value <= 0.0)
+        {
+            Some((name, value)) => Err(format!("{name} must be above zero, not {value}")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("rules always serialize")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read rules from {}: {e}", path.display()))?;
+        Self::from_ron(&text).map_err(|e| format!("invalid rules in {}: {e}", path.display()))
+    }
+
+    // Equal rules, equal fingerprints, on every peer. Hashes the same bytes
+    // the rules cross the wire as, so float bits count and formatting doesn't.
+    pub fn fingerprint(&self) -> u64 {
+        let bytes = bincode::serde::encode_to_vec(self, bincode::config::standard())
+            .expect("rules always serialize");
+        let mut hasher = checksum_hasher();
+        hasher.write(&bytes);
+        hasher.finish()
+    }
+
+    // A fresh player as these rules have them
+    pub fn player(&self) -> Player {
+        Player {
+            movement_speed: self.movement_speed,
+            jump_force: self.jump_force,
+            ..default()
+        }
+    }
+}
+
+// The rules we loaded at startup, and host by
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct HouseRules(pub MatchRules);
+
+pub struct RulesPlugin;
+
+impl Plugin for RulesPlugin {
+    fn build(&self, app: &mut App) {
+        let path = app
+            .world()
+            .get_resource::<CliArgs>()
+            .and_then(|args| args.rules.clone());
+        let rules = match path {
+            Some(path) => match MatchRules::load(&path) {
+                Ok(rules) => {
+                    info!("Playing by the rules in {}", path.display());
+                    rules
+                }
+                Err(e) => {
+                    error!("{e}; playing by the default rules");
+                    MatchRules::default()
+                }
+            },
+            None => MatchRules::default(),
+        };
+
+        app.insert_resource(HouseRules(rules.clone()))
+            .insert_resource(rules)
+            .add_systems(OnEnter(GameState::MainMenu), restore_house_rules);
+    }
+}
+
+// Whatever the last host's rules were, they're over with the match
+fn restore_house_rules(house_rules: Res<HouseRules>, mut rules: ResMut<MatchRules>) {
+    if *rules != house_rules.0 {
+        *rules = house_rules.0.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_shipped_rules_file_is_the_defaults() {
+        let shipped = MatchRules::from_ron(include_str!("../rules.ron")).unwrap();
+        assert_eq!(shipped, MatchRules::default());
+    }
+
+    #[test]
+    fn rules_survive_a_round_trip() {
+        let mut rules = MatchRules::default();
+        rules.bow.charge_curve = ChargeCurve::EaseIn;
+        rules.arena.chunk_boundaries.insert(
+            2,
+            ChunkBoundaries {
+                east: Boundary::Wall,
+                ..default()
+            },
+        );
+        assert_eq!(MatchRules::from_ron(&rules.to_ron()), Ok(rules));
+    }
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let rules = MatchRules::from_ron("(jump_force: 10.0, dodge: (cooldown: 4.0))").unwrap();
+        assert_eq!(rules.jump_force, 10.0);
+        assert_eq!(rules.dodge.cooldown, 4.0);
+        assert_eq!(
+            rules.dodge.dash_impulse,
+            DodgeConfig::default().dash_impulse
+        );
+        assert_eq!(rules.bow, BowConfig::default());
+
+        assert!(MatchRules::from_ron("(jump_force: \"high\")").is_err());
+    }
+
+    #[test]
+    fn rules_that_cant_make_a_match_are_refused() {
+        let too_many_advancements = "(advancements_to_win: 6, arena: (chunks: 5))";
+        assert!(MatchRules::from_ron(too_many_advancements).is_err());
+        assert!(MatchRules::from_ron("(advancements_to_win: 5, arena: (chunks: 5))").is_ok());
+        assert!(MatchRules::from_ron("(arena: (chunks: -1))").is_err());
+
+        for broken in [
+            "(arena: (length: 0.0))",
+            "(arena: (width: -50.0))",
+            "(bow: (full_draw_time: 0.0))",
+            "(dodge: (cooldown: -1.0))",
+        ] {
+            assert!(
+                MatchRules::from_ron(broken).is_err(),
+                "{broken} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn any_change_changes_the_fingerprint() {
+        let rules = MatchRules::default();
+        assert_eq!(rules.fingerprint(), MatchRules::default().fingerprint());
+
+        let mut faster_reload = rules.clone();
+        faster_reload.bow.reload_time = 0.9;
+        assert_ne!(faster_reload.fingerprint(), rules.fingerprint());
+        let mut quicker_match = rules.clone();
+        quicker_match.advancements_to_win = 3;
+        assert_ne!(quicker_match.fingerprint(), rules.fingerprint());
+    }
+}
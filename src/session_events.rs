@@ -0,0 +1,356 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ggrs::{ggrs::GgrsEvent, Rollback, RollbackFrameCount, Session};
+use bevy_matchbox::prelude::PeerId;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::advancement::MatchState;
+use crate::components::Player;
+use crate::control::MatchControl;
+use crate::networking::{GGRSConfig, GgrsChannel};
+use crate::rejoin::{Rejoining, REJOIN_GRACE};
+use crate::resources::*;
+use crate::GameState;
+
+// Session Events
+// ==============
+//
+// GGRS tells us about the network through events rather than errors: a
+// peer going quiet, coming back, being dropped for good, or simulating a
+// frame differently from us. Each one gets logged (design.md asks for
+// comprehensive logging around disconnects and desyncs), and the ones
+// players should know about end up on screen or in the chat.
+//
+// Spectators come and go without holding anything up. A spectator who
+// loses the host has nothing left to watch and goes back to the menu.
+//
+// Matches end here too, whether by forfeit or by someone winning, or
+// before they've begun if a peer turns out to play by other rules.
+pub struct SessionEventsPlugin;
+
+impl Plugin for SessionEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SessionHealth>()
+            .add_systems(OnEnter(GameState::InGame), reset_session_health)
+            .add_systems(OnEnter(GameState::GameOver), stop_session)
+            .add_systems(OnEnter(GameState::MainMenu), stop_session)
+            .add_systems(
+                Update,
+                (
+                    handle_session_events.run_if(resource_exists::<Session<GGRSConfig>>),
+                    refuse_other_rules.run_if(resource_exists::<GgrsChannel>),
+                    // While we're the one rejoining, the clock isn't running for us
+                    expire_rejoin_grace.run_if(
+                        resource_exists::<MatchControl>.and(not(resource_exists::<Rejoining>)),
+                    ),
+                    finish_decided_match.run_if(resource_exists::<MatchControl>),
+                    finish_won_match.run_if(resource_exists::<Session<GGRSConfig>>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+// Peers GGRS hasn't heard from in a while, peers it gave up on whose slot
+// is still held for them, and the last desync, if any
+#[derive(Resource, Debug, Default)]
+pub struct SessionHealth {
+    pub interrupted: BTreeMap<PeerId, Interruption>,
+    pub awaiting_rejoin: BTreeMap<PeerId, Interruption>,
+    pub last_desync: Option<DesyncReport>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Interruption {
+    pub name: String,
+    // Real time at which GGRS gives up on them, or their slot is given up
+    pub deadline: Duration,
+}
+
+impl Interruption {
+    pub fn remaining(&self, now: Duration) -> Duration {
+        self.deadline.saturating_sub(now)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DesyncReport {
+    pub frame: i32,
+    pub name: String,
+}
+
+fn reset_session_health(mut status: ResMut<SessionHealth>) {
+    *status = SessionHealth::default();
+}
+
+// Nothing left to simulate once the match is over
+fn stop_session(mut commands: Commands) {
+    commands.remove_resource::<Session<GGRSConfig>>();
+}
+
+fn handle_session_events(
+    mut session: ResMut<Session<GGRSConfig>>,
+    mut status: ResMut<SessionHealth>,
+    mut control: Option<ResMut<MatchControl>>,
+    mut lobby_state: ResMut<LobbyState>,
+    mut connection_info: ResMut<ConnectionInfo>,
+    mut game_state: ResMut<NextState<GameState>>,
+    time: Res<Time<Real>>,
+) {
+    let events = match session.as_mut() {
+        Session::P2P(session) => session.events().collect::<Vec<_>>(),
+        Session::Spectator(session) => session.events().collect(),
+        Session::SyncTest(_) => return, // Nobody to lose touch with
+    };
+    let is_spectator = |control: &Option<ResMut<MatchControl>>, addr: &PeerId| {
+        control
+            .as_ref()
+            .is_some_and(|control| control.spectators.contains(addr))
+    };
+
+    for event in events {
+        match event {
+            GgrsEvent::Synchronizing { addr, total, count } => {
+                debug!("Synchronizing with {addr}: {count}/{total}");
+            }
+            GgrsEvent::Synchronized { addr } => {
+                info!("Synchronized with {addr}");
+            }
+            GgrsEvent::NetworkInterrupted { addr, .. } if is_spectator(&control, &addr) => {
+                debug!("Spectator {addr} went quiet");
+            }
+            GgrsEvent::NetworkInterrupted {
+                addr,
+                disconnect_timeout,
+            } => {
+                let name = lobby_state.player_name(addr.0);
+                warn!("Connection to {name} ({addr}) interrupted, dropping them in {disconnect_timeout} ms");
+                let deadline = time.elapsed() + Duration::from_millis(disconnect_timeout as u64);
+                status
+                    .interrupted
+                    .insert(addr, Interruption { name, deadline });
+            }
+            GgrsEvent::NetworkResumed { addr } => {
+                let name = lobby_state.player_name(addr.0);
+                info!("Connection to {name} ({addr}) resumed");
+                if status.interrupted.remove(&addr).is_some() {
+                    lobby_state.push_system_message(&format!("{name} reconnected"));
+                }
+            }
+            GgrsEvent::Disconnected { addr } if is_spectator(&control, &addr) => {
+                let name = lobby_state.player_name(addr.0);
+                info!("Spectator {name} ({addr}) is gone");
+                if let Some(control) = control.as_mut() {
+                    control.drop_spectator(addr);
+                }
+                lobby_state.push_system_message(&format!("{name} stopped watching"));
+            }
+            GgrsEvent::Disconnected { addr }
+                if control
+                    .as_ref()
+                    .is_some_and(|control| control.is_spectating()) =>
+            {
+                // A spectator session only ever talks to the broadcaster
+                let name = lobby_state.player_name(addr.0);
+                warn!("Lost the match feed from {name} ({addr})");
+                connection_info.connection_error = Some(format!("Lost the match feed from {name}"));
+                game_state.set(GameState::MainMenu);
+            }
+            GgrsEvent::Disconnected { addr } => {
+                let name = lobby_state.player_name(addr.0);
+                warn!("{name} ({addr}) disconnected, holding their slot for {REJOIN_GRACE:?}");
+                status.interrupted.remove(&addr);
+                lobby_state.push_system_message(&format!("{name} disconnected"));
+                if control.as_mut().is_some_and(|control| control.disconnect(addr)) {
+                    let deadline = time.elapsed() + REJOIN_GRACE;
+                    status
+                        .awaiting_rejoin
+                        .insert(addr, Interruption { name, deadline });
+                }
+            }
+            GgrsEvent::WaitRecommendation { skip_frames } => {
+                // bevy_ggrs already slows us down when we're ahead
+                debug!("GGRS recommends waiting {skip_frames} frames for the other peers");
+            }
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => {
+                let name = lobby_state.player_name(addr.0);
+                error!(
+                    "Desync with {name} ({addr}) on frame {frame}: local checksum {local_checksum:032X}, theirs {remote_checksum:032X}"
+                );
+                // Only the first makes it to the chat; the log gets all of them
+                if status.last_desync.is_none() {
+                    lobby_state.push_system_message(&format!(
+                        "Desync detected with {name} on frame {frame}"
+                    ));
+                }
+                status.last_desync = Some(DesyncReport { frame, name });
+            }
+        }
+    }
+}
+
+// A peer whose GGRS packets carry other rules never synchronizes with us
+// (see `GgrsChannel`), so there's no match to wait for
+fn refuse_other_rules(
+    channel: Res<GgrsChannel>,
+    lobby_state: Res<LobbyState>,
+    mut connection_info: ResMut<ConnectionInfo>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(&peer) = channel.refused().first() else {
+        return;
+    };
+    let name = lobby_state.player_name(peer.0);
+    error!("{name} ({peer}) plays by different rules, calling the match off");
+    connection_info.connection_error = Some(format!("{name} plays by different rules"));
+    game_state.set(GameState::MainMenu);
+}
+
+// Players who didn't make it back in time forfeit
+fn expire_rejoin_grace(
+    mut status: ResMut<SessionHealth>,
+    mut control: ResMut<MatchControl>,
+    mut lobby_state: ResMut<LobbyState>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    let expired = status
+        .awaiting_rejoin
+        .iter()
+        .filter(|(_, waiting)| waiting.remaining(now).is_zero())
+        .map(|(&peer, _)| peer)
+        .collect::<Vec<_>>();
+
+    for peer in expired {
+        let Some(waiting) = status.awaiting_rejoin.remove(&peer) else {
+            continue;
+        };
+        if control.abandon(peer) {
+            warn!("{} ({peer}) didn't rejoin in time", waiting.name);
+            lobby_state.push_system_message(&format!("{} didn't make it back", waiting.name));
+        }
+    }
+}
+
+// Everything the results screen lists about the players, however the
+// match ended
+#[derive(SystemParam)]
+struct Standings<'w, 's> {
+    stats: Res<'w, GameStats>,
+    players: Query<'w, 's, &'static Player, With<Rollback>>,
+    control: Option<Res<'w, MatchControl>>,
+    lobby_state: Res<'w, LobbyState>,
+}
+
+impl Standings<'_, '_> {
+    fn name(&self, handle: u32) -> String {
+        self.control
+            .as_ref()
+            .and_then(|control| control.handles.get(handle as usize))
+            .map(|peer| self.lobby_state.player_name(peer.0))
+            .unwrap_or_else(|| format!("Player {handle}"))
+    }
+
+    fn results(&self) -> Vec<PlayerResult> {
+        let mut handles = self
+            .players
+            .iter()
+            .map(|player| player.network_id)
+            .collect::<Vec<_>>();
+        handles.sort_unstable();
+        let named = handles
+            .into_iter()
+            .map(|handle| (handle, self.name(handle)))
+            .collect::<Vec<_>>();
+        self.stats.results(&named)
+    }
+}
+
+// Ends the match once forfeits and disconnects leave at most one player
+fn finish_decided_match(
+    mut commands: Commands,
+    control: Res<MatchControl>,
+    lobby_state: Res<LobbyState>,
+    standings: Standings,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if !control.is_decided() {
+        return;
+    }
+
+    let names = |peers: Vec<&PeerId>| {
+        peers
+            .into_iter()
+            .map(|peer| lobby_state.player_name(peer.0))
+            .collect::<Vec<_>>()
+    };
+    let result = MatchResult {
+        winners: names(control.active_players().collect()),
+        reason: MatchEndReason::Forfeit {
+            forfeited: names(
+                control
+                    .forfeited
+                    .difference(&control.disconnected)
+                    .collect(),
+            ),
+            disconnected: names(
+                control
+                    .disconnected
+                    .intersection(&control.forfeited)
+                    .collect(),
+            ),
+        },
+        players: standings.results(),
+        duration: standings.stats.game_time,
+    };
+
+    info!("Match over: {result:?}");
+    commands.insert_resource(result);
+    game_state.set(GameState::GameOver);
+}
+
+// Ends the match once the frame someone won on is confirmed. Until then a
+// late input could still take the win back. Every peer confirms the same
+// frames, so they all end the same way. The stats stopped counting on that
+// frame (see `record_game_stats`), so they and the simulated time in them
+// are the confirmed ones, however far past it this peer has predicted. That
+// time also covers play from before a rejoin, which `won_on` doesn't.
+fn finish_won_match(
+    mut commands: Commands,
+    session: Res<Session<GGRSConfig>>,
+    frame: Res<RollbackFrameCount>,
+    match_state: Res<MatchState>,
+    standings: Standings,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(winner) = match_state.winner else {
+        return;
+    };
+    let confirmed = match session.as_ref() {
+        Session::P2P(session) => session.confirmed_frame(),
+        // Spectators only ever simulate confirmed frames, SyncTest only local ones
+        Session::Spectator(_) | Session::SyncTest(_) => frame.0,
+    };
+    if confirmed < match_state.won_on {
+        return;
+    }
+
+    let result = MatchResult {
+        winners: vec![standings.name(winner)],
+        reason: MatchEndReason::Advancements {
+            advancements: match_state.advancements(winner),
+        },
+        players: standings.results(),
+        duration: standings.stats.game_time,
+    };
+
+    info!("Match over: {result:?}");
+    commands.insert_resource(result);
+    game_state.set(GameState::GameOver);
+}
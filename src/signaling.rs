@@ -0,0 +1,371 @@
+use bevy::prelude::*;
+use matchbox_protocol::{JsonPeerEvent, JsonPeerRequest, PeerId, PeerRequest};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::{Message, WebSocket};
+use uuid::Uuid;
+
+use crate::cli::CliArgs;
+use crate::networking::start_peer_socket;
+use crate::resources::{LobbyTransport, SignalingSettings};
+use crate::GameState;
+
+// Overrides the signaling URL from the settings, and is itself overridden by --signaling-url
+pub const SIGNALING_URL_ENV: &str = "RIBBON_SIGNALING_URL";
+
+// How long a connection thread waits on its socket before checking for
+// outgoing events, and how often the accept loop checks for shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Signaling
+// =========
+//
+// Matchbox peers find each other through a signaling server before WebRTC
+// takes over. Which server we use comes from the settings, then the
+// environment, then the command line, each one overriding the last. The host
+// can also run a small server of its own, so a LAN (or two windows on one
+// machine) doesn't need the internet at all.
+pub struct SignalingPlugin;
+
+impl Plugin for SignalingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SignalingSettings>()
+            .add_systems(Startup, apply_signaling_overrides)
+            .add_systems(
+                OnEnter(GameState::Lobby),
+                start_local_signaling_server.before(start_peer_socket),
+            )
+            .add_systems(OnEnter(GameState::MainMenu), stop_local_signaling_server);
+    }
+}
+
+fn apply_signaling_overrides(args: Option<Res<CliArgs>>, mut settings: ResMut<SignalingSettings>) {
+    let env_url = std::env::var(SIGNALING_URL_ENV).ok();
+    let cli_url = args.as_ref().and_then(|args| args.signaling_url.clone());
+
+    if let Some(url) = cli_url.or(env_url) {
+        settings.server_url = url;
+    }
+    if args.is_some_and(|args| args.host_signaling) {
+        settings.host_local_server = true;
+    }
+}
+
+// The signaling server this client should talk to for `room_id`
+pub fn room_url(
+    settings: &SignalingSettings,
+    local_server: Option<&LocalSignalingServer>,
+    room_id: &str,
+) -> String {
+    let base = match local_server {
+        // Our own server; always reachable over loopback
+        Some(server) => format!("ws://127.0.0.1:{}", server.0.local_addr().port()),
+        None => settings.server_url.clone(),
+    };
+    format!("{}/{}", base.trim_end_matches('/'), room_id)
+}
+
+// Local Signaling Server
+// ======================
+//
+// Only the host runs this, and only while they're in a lobby or a match.
+#[derive(Resource)]
+pub struct LocalSignalingServer(pub SignalingServer);
+
+fn start_local_signaling_server(
+    mut commands: Commands,
+    settings: Res<SignalingSettings>,
+    lobby_state: Res<crate::resources::LobbyState>,
+    existing: Option<Res<LocalSignalingServer>>,
+) {
+    if !lobby_state.is_host
+        || !settings.host_local_server
+        || lobby_state.transport != LobbyTransport::Signaling
+        || existing.is_some()
+    {
+        return;
+    }
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], settings.local_server_port));
+    match SignalingServer::bind(addr) {
+        Ok(server) => {
+            info!(
+                "Hosting signaling server on {}; LAN players can join via ws://<this machine's IP>:{}",
+                server.local_addr(),
+                server.local_addr().port()
+            );
+            commands.insert_resource(LocalSignalingServer(server));
+        }
+        Err(e) => error!("Failed to start local signaling server on {addr}: {e}"),
+    }
+}
+
+fn stop_local_signaling_server(mut commands: Commands) {
+    // Dropping the server shuts it down
+    commands.remove_resource::<LocalSignalingServer>();
+}
+
+// A full-mesh matchbox signaling server. Speaks the same JSON protocol as
+// `matchbox_server`: every peer that joins a room gets an id, everyone
+// already in the room is told to send it an offer, and signals are relayed
+// between peers in the same room until one of them leaves.
+pub struct SignalingServer {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+}
+
+// Everyone currently connected, and how to reach their connection thread
+type Peers = Arc<Mutex<HashMap<PeerId, ConnectedPeer>>>;
+
+struct ConnectedPeer {
+    room: String,
+    events: Sender<JsonPeerEvent>,
+}
+
+impl SignalingServer {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept_shutdown = shutdown.clone();
+        thread::Builder::new()
+            .name("signaling-accept".into())
+            .spawn(move || accept_loop(listener, accept_shutdown))?;
+
+        Ok(Self { local_addr, shutdown })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Sidecar mode: serve until the process is killed
+    pub fn run_blocking(addr: SocketAddr) -> io::Result<()> {
+        let server = Self::bind(addr)?;
+        info!("Signaling server listening on {}", server.local_addr());
+        loop {
+            thread::park();
+        }
+    }
+}
+
+impl Drop for SignalingServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
+
+fn accept_loop(listener: TcpListener, shutdown: Arc<AtomicBool>) {
+    let peers = Peers::default();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, remote)) => {
+                let peers = peers.clone();
+                let shutdown = shutdown.clone();
+                let spawned = thread::Builder::new()
+                    .name(format!("signaling-{remote}"))
+                    .spawn(move || {
+                        if let Err(e) = serve_peer(stream, peers, shutdown) {
+                            debug!("Signaling connection from {remote} ended: {e}");
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("Failed to spawn signaling connection thread: {e}");
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => warn!("Signaling server failed to accept a connection: {e}"),
+        }
+    }
+}
+
+fn serve_peer(stream: TcpStream, peers: Peers, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+
+    // The room is the request path, minus any matchbox-style query string
+    let (room_sender, room_receiver) = mpsc::channel();
+    #[allow(clippy::result_large_err)] // The callback's error type is tungstenite's
+    let mut socket = tungstenite::accept_hdr(stream, move |request: &Request, response: Response| {
+        let _ = room_sender.send(request.uri().path().trim_start_matches('/').to_string());
+        Ok(response)
+    })
+    .map_err(io::Error::other)?;
+    let room = room_receiver.try_recv().unwrap_or_default();
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let id = PeerId(Uuid::new_v4());
+    let (events, outbox) = mpsc::channel();
+    send_event(&mut socket, &JsonPeerEvent::IdAssigned(id))?;
+
+    {
+        let mut peers = peers.lock().unwrap();
+        // Whoever was here first makes the offer
+        for peer in peers.values().filter(|peer| peer.room == room) {
+            let _ = peer.events.send(JsonPeerEvent::NewPeer(id));
+        }
+        peers.insert(id, ConnectedPeer { room: room.clone(), events });
+    }
+    debug!("Peer {id} joined signaling room '{room}'");
+
+    let result = relay(&mut socket, id, &room, &peers, &outbox, &shutdown);
+
+    let mut peers = peers.lock().unwrap();
+    peers.remove(&id);
+    for peer in peers.values().filter(|peer| peer.room == room) {
+        let _ = peer.events.send(JsonPeerEvent::PeerLeft(id));
+    }
+    debug!("Peer {id} left signaling room '{room}'");
+
+    result
+}
+
+fn relay(
+    socket: &mut WebSocket<TcpStream>,
+    id: PeerId,
+    room: &str,
+    peers: &Peers,
+    outbox: &Receiver<JsonPeerEvent>,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    while !shutdown.load(Ordering::Relaxed) {
+        match socket.read() {
+            Ok(Message::Text(text)) => match text.parse::<JsonPeerRequest>() {
+                Ok(PeerRequest::Signal { receiver, data }) => {
+                    let peers = peers.lock().unwrap();
+                    // Signals never cross rooms
+                    if let Some(peer) = peers.get(&receiver).filter(|peer| peer.room == room) {
+                        let _ = peer.events.send(JsonPeerEvent::Signal { sender: id, data });
+                    }
+                }
+                Ok(PeerRequest::KeepAlive) => {}
+                Err(e) => warn!("Ignoring malformed signaling request from {id}: {e}"),
+            },
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(io::Error::other(e)),
+        }
+
+        while let Ok(event) = outbox.try_recv() {
+            send_event(socket, &event)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn send_event(socket: &mut WebSocket<TcpStream>, event: &JsonPeerEvent) -> io::Result<()> {
+    socket
+        .send(Message::Text(event.to_string().into()))
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matchbox_protocol::PeerEvent;
+    use serde_json::json;
+    use tungstenite::stream::MaybeTlsStream;
+
+    type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    fn connect(server: &SignalingServer, room: &str) -> Client {
+        let url = format!("ws://{}/{room}", server.local_addr());
+        let (client, _) = tungstenite::connect(url).expect("failed to connect");
+        if let MaybeTlsStream::Plain(stream) = client.get_ref() {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        client
+    }
+
+    fn next_event(client: &mut Client) -> JsonPeerEvent {
+        loop {
+            if let Message::Text(text) = client.read().expect("no event from server") {
+                return text.parse().expect("malformed event");
+            }
+        }
+    }
+
+    fn assigned_id(client: &mut Client) -> PeerId {
+        match next_event(client) {
+            PeerEvent::IdAssigned(id) => id,
+            other => panic!("expected IdAssigned, got {other:?}"),
+        }
+    }
+
+    fn send_signal(client: &mut Client, receiver: PeerId, data: serde_json::Value) {
+        let request = JsonPeerRequest::Signal { receiver, data };
+        client.send(Message::Text(request.to_string().into())).unwrap();
+    }
+
+    fn local_server() -> SignalingServer {
+        SignalingServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap()
+    }
+
+    #[test]
+    fn first_peer_is_told_to_offer_and_signals_are_relayed() {
+        let server = local_server();
+        let mut first = connect(&server, "room");
+        let first_id = assigned_id(&mut first);
+        let mut second = connect(&server, "room");
+        let second_id = assigned_id(&mut second);
+
+        assert_eq!(next_event(&mut first), PeerEvent::NewPeer(second_id));
+
+        send_signal(&mut first, second_id, json!({ "Offer": "sdp" }));
+        assert_eq!(
+            next_event(&mut second),
+            PeerEvent::Signal { sender: first_id, data: json!({ "Offer": "sdp" }) }
+        );
+
+        send_signal(&mut second, first_id, json!({ "Answer": "sdp" }));
+        assert_eq!(
+            next_event(&mut first),
+            PeerEvent::Signal { sender: second_id, data: json!({ "Answer": "sdp" }) }
+        );
+
+        second.close(None).unwrap();
+        assert_eq!(next_event(&mut first), PeerEvent::PeerLeft(second_id));
+    }
+
+    #[test]
+    fn rooms_are_isolated() {
+        let server = local_server();
+        let mut lonely = connect(&server, "one");
+        let lonely_id = assigned_id(&mut lonely);
+        let mut other = connect(&server, "two");
+        assigned_id(&mut other);
+
+        // A signal aimed across rooms is dropped, so the next thing the
+        // lonely peer hears about is a newcomer to its own room
+        send_signal(&mut other, lonely_id, json!("hello"));
+        let mut newcomer = connect(&server, "one");
+        let newcomer_id = assigned_id(&mut newcomer);
+        assert_eq!(next_event(&mut lonely), PeerEvent::NewPeer(newcomer_id));
+    }
+
+    #[test]
+    fn room_url_prefers_the_local_server() {
+        let settings = SignalingSettings {
+            server_url: "ws://example.com:3536/".to_string(),
+            ..default()
+        };
+        assert_eq!(room_url(&settings, None, "ABCD"), "ws://example.com:3536/ABCD");
+
+        let server = LocalSignalingServer(local_server());
+        let port = server.0.local_addr().port();
+        assert_eq!(room_url(&settings, Some(&server), "ABCD"), format!("ws://127.0.0.1:{port}/ABCD"));
+    }
+}
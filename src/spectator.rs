@@ -0,0 +1,200 @@
+use bevy::input::mouse::AccumulatedMouseMotion;
+use bevy::prelude::*;
+use bevy_ggrs::{Rollback, Session};
+
+use crate::components::*;
+use crate::networking::GGRSConfig;
+use crate::GameState;
+
+// Free camera speeds in metres per second, mouse look in radians per pixel
+const FREE_CAMERA_SPEED: f32 = 12.0;
+const FREE_CAMERA_FAST_SPEED: f32 = 30.0;
+const MOUSE_SENSITIVITY: f32 = 0.003;
+
+// Spectator Camera
+// ================
+//
+// Spectators have no player of their own for `camera_follow` to chase.
+// They either follow one of the players, cycling with Tab, or fly around
+// freely: WASD to move, Space and Ctrl to rise and sink, right mouse
+// button held to look around. F switches between the two.
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpectatorCamera>()
+            .add_systems(OnEnter(GameState::InGame), reset_spectator_camera)
+            .add_systems(
+                Update,
+                (spectator_camera_controls, move_spectator_camera)
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and(spectating)),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    // The player with this GGRS handle
+    Follow(usize),
+    Free,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SpectatorCamera {
+    pub mode: CameraMode,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Default for SpectatorCamera {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Follow(0),
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+}
+
+impl SpectatorCamera {
+    // The handle after (or before) the one we follow, among those in play
+    fn cycle(&mut self, handles: &[usize], forward: bool) {
+        let Some(&first) = handles.first() else {
+            return;
+        };
+        let current = match self.mode {
+            CameraMode::Follow(handle) => handles.iter().position(|&h| h == handle),
+            CameraMode::Free => None,
+        };
+        let next = match current {
+            Some(index) if forward => handles[(index + 1) % handles.len()],
+            Some(index) => handles[(index + handles.len() - 1) % handles.len()],
+            None => first,
+        };
+        self.mode = CameraMode::Follow(next);
+    }
+}
+
+pub fn spectating(session: Option<Res<Session<GGRSConfig>>>) -> bool {
+    matches!(session.as_deref(), Some(Session::Spectator(_)))
+}
+
+fn reset_spectator_camera(mut camera: ResMut<SpectatorCamera>) {
+    *camera = SpectatorCamera::default();
+}
+
+fn spectator_camera_controls(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut camera: ResMut<SpectatorCamera>,
+    players: Query<&Player, With<Rollback>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+) {
+    let mut handles = players
+        .iter()
+        .map(|player| player.network_id as usize)
+        .collect::<Vec<_>>();
+    handles.sort_unstable();
+
+    if keyboard.just_pressed(KeyCode::Tab) {
+        let backwards = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        camera.cycle(&handles, !backwards);
+    }
+
+    if keyboard.just_pressed(KeyCode::KeyF) {
+        match camera.mode {
+            CameraMode::Free => camera.cycle(&handles, true),
+            CameraMode::Follow(_) => {
+                // Carry on looking where the follow camera was looking
+                if let Ok(transform) = camera_query.single() {
+                    let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+                    camera.yaw = yaw;
+                    camera.pitch = pitch;
+                }
+                camera.mode = CameraMode::Free;
+            }
+        }
+    }
+}
+
+// Rollback players only; the camera is never one
+type NetworkPlayers = (With<Rollback>, Without<MainCamera>);
+
+fn move_spectator_camera(
+    mut camera: ResMut<SpectatorCamera>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    mouse_motion: Res<AccumulatedMouseMotion>,
+    players: Query<(&Player, &Transform), NetworkPlayers>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+    time: Res<Time>,
+) {
+    let Ok(mut camera_transform) = camera_query.single_mut() else {
+        return;
+    };
+
+    match camera.mode {
+        CameraMode::Follow(handle) => {
+            let Some((_, target)) = players
+                .iter()
+                .find(|(player, _)| player.network_id as usize == handle)
+            else {
+                return;
+            };
+            // Same framing as `camera_follow` gives a player
+            let desired_pos = target.translation + Vec3::new(0.0, 5.0, 10.0);
+            camera_transform.translation = camera_transform
+                .translation
+                .lerp(desired_pos, 2.0 * time.delta_secs());
+            camera_transform.look_at(target.translation + Vec3::Y, Vec3::Y);
+        }
+        CameraMode::Free => {
+            if mouse_buttons.pressed(MouseButton::Right) {
+                camera.yaw -= mouse_motion.delta.x * MOUSE_SENSITIVITY;
+                camera.pitch =
+                    (camera.pitch - mouse_motion.delta.y * MOUSE_SENSITIVITY).clamp(-1.5, 1.5);
+            }
+            camera_transform.rotation =
+                Quat::from_euler(EulerRot::YXZ, camera.yaw, camera.pitch, 0.0);
+
+            let axis = |positive: KeyCode, negative: KeyCode| {
+                keyboard.pressed(positive) as i8 as f32 - keyboard.pressed(negative) as i8 as f32
+            };
+            let flat_forward = Quat::from_rotation_y(camera.yaw) * Vec3::NEG_Z;
+            let flat_right = Quat::from_rotation_y(camera.yaw) * Vec3::X;
+            let direction = flat_forward * axis(KeyCode::KeyW, KeyCode::KeyS)
+                + flat_right * axis(KeyCode::KeyD, KeyCode::KeyA)
+                + Vec3::Y * axis(KeyCode::Space, KeyCode::ControlLeft);
+            let speed = if keyboard.pressed(KeyCode::ShiftLeft) {
+                FREE_CAMERA_FAST_SPEED
+            } else {
+                FREE_CAMERA_SPEED
+            };
+            camera_transform.translation +=
+                direction.normalize_or_zero() * speed * time.delta_secs();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tab_cycles_through_the_players_in_play() {
+        let mut camera = SpectatorCamera::default();
+        let handles = [0, 1, 3];
+        camera.cycle(&handles, true);
+        assert_eq!(camera.mode, CameraMode::Follow(1));
+        camera.cycle(&handles, true);
+        camera.cycle(&handles, true);
+        assert_eq!(camera.mode, CameraMode::Follow(0));
+        camera.cycle(&handles, false);
+        assert_eq!(camera.mode, CameraMode::Follow(3));
+
+        // Leaving the free camera picks up the first player again
+        camera.mode = CameraMode::Free;
+        camera.cycle(&handles, true);
+        assert_eq!(camera.mode, CameraMode::Follow(0));
+    }
+}
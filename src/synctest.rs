@@ -0,0 +1,234 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_ggrs::{
+    checksum_hasher, ggrs::PlayerType, ggrs::SessionBuilder, ChecksumFlag, ChecksumPart,
+    RollbackApp, RollbackFrameCount, SaveWorld, SaveWorldSet, Session,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::Hasher;
+
+use crate::advancement::MatchState;
+use crate::cli::CliArgs;
+use crate::components::*;
+use crate::networking::GGRSConfig;
+use crate::resources::{GameStats, SyncTestSettings};
+use crate::GameState;
+
+// How many frames of per-component checksums we hold on to. Comfortably
+// more than any check distance worth running.
+const HISTORY_FRAMES: i32 = 128;
+
+// SyncTest Dev Mode
+// =================
+//
+// A SyncTest session runs every local frame, then rolls back `check_distance`
+// frames and simulates them again. If the simulation is deterministic, the
+// second pass lands on exactly the same state. GGRS only tells us *that* a
+// frame's total checksum changed, so we also keep each component's part of
+// the checksum per frame and name the ones that moved.
+pub struct SyncTestPlugin;
+
+impl Plugin for SyncTestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SyncTestSettings>()
+            .init_resource::<ChecksumHistory>()
+            .add_event::<StartSyncTest>()
+            .add_systems(Startup, start_synctest_from_cli)
+            .add_systems(Update, start_synctest_session);
+
+        // Everything that's rolled back is checksummed. These feed the session
+        // checksum for P2P desync detection as well as the report below.
+        track_checksum_with::<Player>(app, hash_player);
+        track_checksum::<Bow>(app);
+        track_checksum::<Arrow>(app);
+        track_checksum::<DodgeAbility>(app);
+        track_checksum::<Respawning>(app);
+        track_checksum::<Transform>(app);
+        track_checksum::<Position>(app);
+        track_checksum::<Rotation>(app);
+        track_checksum::<LinearVelocity>(app);
+        track_checksum::<AngularVelocity>(app);
+        track_resource_checksum::<MatchState>(app);
+        track_resource_checksum::<GameStats>(app);
+    }
+}
+
+// Sent by the menu (or the CLI at startup) to kick off a SyncTest session
+#[derive(Event, Debug, Clone, Copy)]
+pub struct StartSyncTest;
+
+fn start_synctest_from_cli(
+    args: Option<Res<CliArgs>>,
+    mut settings: ResMut<SyncTestSettings>,
+    mut start: EventWriter<StartSyncTest>,
+) {
+    let Some(args) = args else {
+        return;
+    };
+    if let Some(check_distance) = args.check_distance {
+        settings.check_distance = check_distance;
+    }
+    if args.synctest {
+        start.write(StartSyncTest);
+    }
+}
+
+fn start_synctest_session(
+    mut commands: Commands,
+    mut events: EventReader<StartSyncTest>,
+    settings: Res<SyncTestSettings>,
+    mut history: ResMut<ChecksumHistory>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if events.read().last().is_none() {
+        return;
+    }
+
+    let mut session_builder = SessionBuilder::<GGRSConfig>::new()
+        .with_num_players(settings.num_players)
+        .with_check_distance(settings.check_distance);
+
+    // SyncTest has no network, so every handle is ours
+    for handle in 0..settings.num_players {
+        session_builder = session_builder
+            .add_player(PlayerType::Local, handle)
+            .expect("failed to add player");
+    }
+
+    let session = match session_builder.start_synctest_session() {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Failed to start SyncTest session: {e}");
+            return;
+        }
+    };
+
+    info!(
+        "Starting SyncTest session: {} players, check distance {}",
+        settings.num_players, settings.check_distance
+    );
+    *history = ChecksumHistory::default();
+    commands.insert_resource(Session::SyncTest(session));
+    game_state.set(GameState::InGame);
+}
+
+// Checksum Tracking
+// =================
+//
+// Hashing goes through `Debug`, which prints floats with just enough digits
+// to round-trip, so two values hash equal only if their bits do. Slow, but
+// this only checksums a handful of entities.
+fn hash_debug<C: Debug>(component: &C) -> u64 {
+    let mut hasher = checksum_hasher();
+    hasher.write(format!("{component:?}").as_bytes());
+    hasher.finish()
+}
+
+// `is_local` is true for a different player on every peer, so it can't be
+// part of a checksum the peers compare
+fn hash_player(player: &Player) -> u64 {
+    hash_debug(&Player {
+        is_local: false,
+        ..*player
+    })
+}
+
+fn track_checksum<C: Component + Debug>(app: &mut App) {
+    track_checksum_with::<C>(app, hash_debug::<C>);
+}
+
+fn track_checksum_with<C: Component>(app: &mut App, hasher: for<'a> fn(&'a C) -> u64) {
+    app.checksum_component::<C>(hasher).add_systems(
+        SaveWorld,
+        record_checksum::<C>
+            .after(SaveWorldSet::Checksum)
+            .run_if(in_synctest),
+    );
+}
+
+fn track_resource_checksum<R: Resource + Debug>(app: &mut App) {
+    app.checksum_resource::<R>(hash_debug::<R>).add_systems(
+        SaveWorld,
+        record_checksum::<R>
+            .after(SaveWorldSet::Checksum)
+            .run_if(in_synctest),
+    );
+}
+
+fn in_synctest(session: Option<Res<Session<GGRSConfig>>>) -> bool {
+    matches!(session.as_deref(), Some(Session::SyncTest(_)))
+}
+
+fn record_checksum<C: Send + Sync + 'static>(
+    frame: Res<RollbackFrameCount>,
+    part: Query<&ChecksumPart, With<ChecksumFlag<C>>>,
+    mut history: ResMut<ChecksumHistory>,
+) {
+    // The part entity is spawned by a command the first time, so it shows
+    // up one save late
+    let Ok(part) = part.single() else {
+        return;
+    };
+
+    let name = std::any::type_name::<C>().rsplit("::").next().unwrap_or_default();
+    if let Some(previous) = history.record(frame.0, name, part.0) {
+        error!(
+            "Desync on frame {}: {} diverged on resimulation ({:032X} -> {:032X})",
+            frame.0, name, previous, part.0
+        );
+    }
+}
+
+// Per-frame, per-component checksum parts from every time a frame was saved
+#[derive(Resource, Debug, Default)]
+pub struct ChecksumHistory {
+    frames: BTreeMap<i32, HashMap<String, u128>>,
+}
+
+impl ChecksumHistory {
+    // Stores `checksum` for `component` on `frame`. If that frame was saved
+    // before with a different value, returns the old one.
+    pub fn record(&mut self, frame: i32, component: &str, checksum: u128) -> Option<u128> {
+        let previous = self
+            .frames
+            .entry(frame)
+            .or_default()
+            .insert(component.to_string(), checksum);
+
+        // Frames far enough back will never be resimulated again
+        let oldest = frame - HISTORY_FRAMES;
+        self.frames = self.frames.split_off(&oldest);
+
+        previous.filter(|&previous| previous != checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_resimulation_is_not_a_desync() {
+        let mut history = ChecksumHistory::default();
+        assert_eq!(history.record(3, "Position", 42), None);
+        assert_eq!(history.record(3, "Position", 42), None);
+    }
+
+    #[test]
+    fn diverged_component_reports_its_old_checksum() {
+        let mut history = ChecksumHistory::default();
+        history.record(3, "Position", 42);
+        history.record(3, "Bow", 7);
+        assert_eq!(history.record(3, "Position", 43), Some(42));
+        assert_eq!(history.record(3, "Bow", 7), None);
+    }
+
+    #[test]
+    fn old_frames_are_forgotten() {
+        let mut history = ChecksumHistory::default();
+        history.record(0, "Player", 1);
+        history.record(HISTORY_FRAMES + 1, "Player", 1);
+        assert_eq!(history.record(0, "Player", 2), None);
+    }
+}
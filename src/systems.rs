@@ -2,17 +2,16 @@ use bevy::prelude::*;
 use avian3d::prelude::*;
 use crate::components::*;
 use crate::resources::*;
+use crate::rules::MatchRules;
+use crate::spectator::spectating;
 use crate::{GameState};
 
-const ARENA_LENGTH: f32 = 150.0;
-const ARENA_WIDTH: f32 = 50.0;
-
 pub struct GameSystemsPlugin;
 
 impl Plugin for GameSystemsPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<ArenaConfig>()
+            .init_resource::<MatchRules>()
             .init_resource::<GameStats>()
             .add_systems(OnEnter(GameState::InGame), (
                 setup_scene,
@@ -20,7 +19,8 @@ impl Plugin for GameSystemsPlugin {
                 spawn_player,
             ))
             .add_systems(Update, (
-                camera_follow.run_if(in_state(GameState::InGame)),
+                // Spectators have their own camera, see `spectator.rs`
+                camera_follow.run_if(in_state(GameState::InGame).and(not(spectating))),
             ));
     }
 }
@@ -30,10 +30,15 @@ pub fn setup_scene(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    rules: Res<MatchRules>,
 ) {
-    // Create ground plane (150m x 50m arena) with separate visual and physics
+    // One ground plane under every chunk a match can reach, laid down up
+    // front so the physics world never changes mid-match. Separate visual
+    // and physics.
+    let arena_config = &rules.arena;
+    let ground_length = arena_config.length * (2 * arena_config.chunks + 1) as f32;
     let ground_parent = commands.spawn((
-        Mesh3d(meshes.add(Plane3d::default().mesh().size(ARENA_LENGTH, ARENA_WIDTH))),
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(ground_length, arena_config.width))),
         MeshMaterial3d(materials.add(Color::srgb(0.3, 0.5, 0.3))),
         Transform::from_xyz(0.0, 0.0, 0.0), // Visual plane at Y=0
         Ground,
@@ -44,7 +49,7 @@ pub fn setup_scene(
     let collider_entity = commands.spawn((
         Transform::from_xyz(0.0, -0.5, 0.0), // Position collider so top is at Y=0
         RigidBody::Static,
-        Collider::cuboid(ARENA_LENGTH, 1.0, ARENA_WIDTH), // Thicker collider
+        Collider::cuboid(ground_length, 1.0, arena_config.width), // Thicker collider
         Groundable,
     )).id();
     
@@ -86,21 +91,25 @@ pub fn spawn_player(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    rules: Res<MatchRules>,
 ) {
     // Spawn player as 3D capsule
     commands.spawn((
-        Mesh3d(meshes.add(Capsule3d::new(0.4, 1.8))),
+        Mesh3d(meshes.add(Capsule3d::new(PLAYER_RADIUS, PLAYER_HEIGHT))),
         MeshMaterial3d(materials.add(Color::srgb(0.8, 0.2, 0.2))),
         Transform::from_xyz(0.0, 2.0, 0.0), // Start slightly above ground
         RigidBody::Dynamic,
-        Collider::capsule(1.8, 0.4),
+        Collider::capsule(PLAYER_RADIUS, PLAYER_HEIGHT),
         LockedAxes::ROTATION_LOCKED, // Prevent player from falling over
-        Player::default(),
+        rules.player(),
         // Add friction and restitution for better physics
         Friction::new(1.0),
         Restitution::new(0.1),
         // Add external impulse component for movement
         ExternalImpulse::default(),
+        // Combat components
+        Bow::default(),
+        DodgeAbility::default(),
         // Network components
         NetworkInput::default(),
         LocalPlayer, // Mark as local player for now
@@ -108,7 +117,7 @@ pub fn spawn_player(
 }
 
 pub fn camera_follow(
-    player_query: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+    player_query: Query<&Transform, (With<LocalPlayer>, Without<MainCamera>)>,
     mut camera_query: Query<&mut Transform, (With<MainCamera>, Without<Player>)>,
     time: Res<Time>,
 ) {
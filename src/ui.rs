@@ -1,7 +1,18 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_egui::{egui, EguiContexts, EguiPlugin, EguiContextPass};
+use bevy_matchbox::prelude::PeerId;
 use crate::resources::*;
+use crate::control::{MatchAction, MatchControl};
+use crate::lobby::LobbyAction;
+use crate::rejoin::{Rejoining, RequestRejoin};
+use crate::session_events::SessionHealth;
+use crate::spectator::{CameraMode, SpectatorCamera};
+use crate::netsim::{NetworkConditions, NetworkSimulator, NetworkSimulatorPanel};
+use crate::network_stats::{LinkQuality, NetworkReport, NetworkStatsOverlay, PeerLinks, RollbackStats};
+use crate::synctest::StartSyncTest;
+use crate::direct;
 use crate::{GameState};
+use std::time::Duration;
 use uuid::Uuid;
 
 pub struct UIPlugin;
@@ -15,6 +26,9 @@ impl Plugin for UIPlugin {
                 main_menu_ui.run_if(in_state(GameState::MainMenu)),
                 lobby_ui.run_if(in_state(GameState::Lobby)),
                 in_game_ui.run_if(in_state(GameState::InGame)),
+                game_over_ui.run_if(in_state(GameState::GameOver)),
+                network_stats_ui.run_if(in_state(GameState::InGame).and(overlay_visible)),
+                network_simulator_ui.run_if(simulator_panel_visible),
             ));
     }
 }
@@ -46,13 +60,25 @@ impl Default for UITheme {
     }
 }
 
+// Settings the main menu lets you edit before starting anything
+#[derive(SystemParam)]
+pub struct MenuSettings<'w> {
+    signaling: ResMut<'w, SignalingSettings>,
+    direct: ResMut<'w, DirectConnectSettings>,
+    synctest: ResMut<'w, SyncTestSettings>,
+}
+
 pub fn main_menu_ui(
     mut contexts: EguiContexts,
     mut game_state: ResMut<NextState<GameState>>,
     mut lobby_state: ResMut<LobbyState>,
     mut connection_info: ResMut<ConnectionInfo>,
+    mut settings: MenuSettings,
+    mut start_synctest: EventWriter<StartSyncTest>,
     theme: Res<UITheme>,
 ) {
+    let MenuSettings { signaling: signaling_settings, direct: direct_settings, synctest: synctest_settings } = &mut settings;
+
     // Apply dark theme
     apply_dark_theme(contexts.ctx_mut(), &theme);
 
@@ -109,6 +135,21 @@ pub fn main_menu_ui(
                                         .hint_text("Enter name...")
                                 );
                             });
+                            ui.add_space(5.0);
+
+                            ui.horizontal(|ui| {
+                                ui.label("Server:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut signaling_settings.server_url)
+                                        .desired_width(170.0)
+                                        .hint_text("ws://host:3536")
+                                );
+                            });
+                            ui.checkbox(&mut signaling_settings.host_local_server, "Host LAN server when creating")
+                                .on_hover_text(format!(
+                                    "Runs a signaling server on port {}. Others join with ws://<your IP>:{}",
+                                    signaling_settings.local_server_port, signaling_settings.local_server_port
+                                ));
                             ui.add_space(10.0);
                         });
                     });
@@ -118,21 +159,8 @@ pub fn main_menu_ui(
                     // Create Game button
                     if create_styled_button(ui, "▶ Create Game", &theme, true).clicked() {
                         let room_id = generate_room_code();
-                        let player_name = lobby_state.local_player_name.clone();
-                        lobby_state.room_id = Some(room_id.clone());
-                        lobby_state.is_host = true;
-                        lobby_state.max_players = 4;
-                        lobby_state.player_count = 1;
-                        lobby_state.players.clear();
-                        lobby_state.players.push(LobbyPlayer {
-                            id: Uuid::new_v4(),
-                            name: player_name,
-                            is_ready: false,
-                            network_handle: 0,
-                            is_local: true,
-                        });
-                        lobby_state.chat_messages.clear();
-                        add_system_message(&mut lobby_state, "Lobby created!");
+                        open_lobby(&mut lobby_state, room_id.clone(), true, LobbyTransport::Signaling);
+                        lobby_state.push_system_message("Lobby created!");
                         connection_info.room_id = room_id;
                         game_state.set(GameState::Lobby);
                     }
@@ -167,35 +195,76 @@ pub fn main_menu_ui(
                             
                             if create_styled_button(ui, "→ Join Game", &theme, join_enabled).clicked() 
                                 && join_enabled {
-                                let player_name = lobby_state.local_player_name.clone();
                                 let room_code = connection_info.room_id.clone();
-                                lobby_state.room_id = Some(connection_info.room_id.clone());
-                                lobby_state.is_host = false;
-                                lobby_state.max_players = 4;
-                                lobby_state.player_count = 2; // Assume host is already there
-                                lobby_state.players.clear();
-                                lobby_state.players.push(LobbyPlayer {
-                                    id: Uuid::new_v4(),
-                                    name: "Host".to_string(),
-                                    is_ready: false,
-                                    network_handle: 0,
-                                    is_local: false,
-                                });
-                                lobby_state.players.push(LobbyPlayer {
-                                    id: Uuid::new_v4(),
-                                    name: player_name,
-                                    is_ready: false,
-                                    network_handle: 1,
-                                    is_local: true,
-                                });
-                                lobby_state.chat_messages.clear();
-                                add_system_message(&mut lobby_state, &format!("Joined room: {}", room_code));
+                                open_lobby(&mut lobby_state, room_code.clone(), false, LobbyTransport::Signaling);
+                                lobby_state.push_system_message(&format!("Joined room: {}", room_code));
                                 game_state.set(GameState::Lobby);
                             }
                             ui.add_space(10.0);
                         });
                     });
 
+                    ui.add_space(15.0);
+
+                    // Direct Connect: no signaling server, just an address and a port
+                    ui.group(|ui| {
+                        ui.set_width(280.0);
+                        ui.vertical_centered(|ui| {
+                            ui.add_space(10.0);
+                            ui.label(
+                                egui::RichText::new("Direct Connect (LAN)")
+                                    .size(14.0)
+                                    .color(theme.text_color)
+                            );
+                            ui.add_space(10.0);
+
+                            ui.horizontal(|ui| {
+                                ui.label("Port:");
+                                ui.add(egui::DragValue::new(&mut direct_settings.port).range(1024..=65534));
+                            });
+                            let port = direct_settings.port;
+                            if create_styled_button(ui, "⌂ Host Direct", &theme, true)
+                                .on_hover_text(format!("Listens on UDP ports {} and {}", port, port + 1))
+                                .clicked()
+                            {
+                                // What everyone else types in to reach us
+                                let address = match direct::lan_address() {
+                                    Some(ip) => format!("{ip}:{port}"),
+                                    None => format!("<your IP>:{port}"),
+                                };
+                                open_lobby(&mut lobby_state, address.clone(), true, LobbyTransport::DirectHost { port });
+                                lobby_state.push_system_message(&format!("Lobby created! Others connect to {address}"));
+                                game_state.set(GameState::Lobby);
+                            }
+
+                            ui.add_space(10.0);
+
+                            ui.horizontal(|ui| {
+                                ui.label("Host:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut direct_settings.host_address)
+                                        .desired_width(140.0)
+                                        .hint_text("192.168.1.5:7000")
+                                );
+                            });
+                            ui.add_space(10.0);
+
+                            let connect_enabled = !direct_settings.host_address.trim().is_empty();
+                            if create_styled_button(ui, "→ Connect", &theme, connect_enabled).clicked()
+                                && connect_enabled {
+                                match direct::resolve_host(&direct_settings.host_address) {
+                                    Ok(host) => {
+                                        open_lobby(&mut lobby_state, host.to_string(), false, LobbyTransport::DirectJoin { host });
+                                        lobby_state.push_system_message(&format!("Connecting to {host}..."));
+                                        game_state.set(GameState::Lobby);
+                                    }
+                                    Err(e) => connection_info.connection_error = Some(e),
+                                }
+                            }
+                            ui.add_space(10.0);
+                        });
+                    });
+
                     ui.add_space(20.0);
 
                     // Single Player (for development/testing)
@@ -203,6 +272,17 @@ pub fn main_menu_ui(
                         game_state.set(GameState::InGame);
                     }
 
+                    ui.add_space(10.0);
+
+                    // SyncTest: rolls back and resimulates every frame to catch desyncs
+                    ui.horizontal(|ui| {
+                        ui.label("Check distance:");
+                        ui.add(egui::DragValue::new(&mut synctest_settings.check_distance).range(1..=16));
+                    });
+                    if create_styled_button(ui, "◆ SyncTest (Dev)", &theme, true).clicked() {
+                        start_synctest.write(StartSyncTest);
+                    }
+
                     ui.add_space(30.0);
 
                     // Display connection errors with styling
@@ -224,6 +304,7 @@ pub fn main_menu_ui(
 
             if ui.button("Join Game").clicked() && !connection_info.room_id.is_empty() {
                 lobby_state.room_id = Some(connection_info.room_id.clone());
+                lobby_state.transport = LobbyTransport::Signaling;
                 lobby_state.is_host = false;
                 lobby_state.max_players = 4;
                 lobby_state.player_count = 1;
@@ -252,7 +333,9 @@ pub fn lobby_ui(
     mut contexts: EguiContexts,
     mut game_state: ResMut<NextState<GameState>>,
     mut lobby_state: ResMut<LobbyState>,
+    mut lobby_actions: EventWriter<LobbyAction>,
     connection_info: Res<ConnectionInfo>,
+    links: Res<PeerLinks>,
     theme: Res<UITheme>,
 ) {
     apply_dark_theme(contexts.ctx_mut(), &theme);
@@ -311,7 +394,7 @@ pub fn lobby_ui(
                                     // Player avatar/icon
                                     let (avatar, avatar_color) = if player.is_local {
                                         ("★", theme.accent_color) // Local player
-                                    } else if i == 0 && lobby_state.is_host { 
+                                    } else if i == 0 { 
                                         ("♔", theme.warning_color) // Host
                                     } else { 
                                         ("●", theme.secondary_color) // Remote player
@@ -333,29 +416,31 @@ pub fn lobby_ui(
                                         
                                         // Ready status and connection
                                         ui.horizontal(|ui| {
-                                            let (ready_text, ready_color) = if player.is_ready {
+                                            let (ready_text, ready_color) = if player.is_spectator {
+                                                ("👁 Spectating", theme.primary_color)
+                                            } else if player.is_ready {
                                                 ("✓ Ready", theme.success_color)
                                             } else {
                                                 ("○ Not Ready", theme.secondary_color)
                                             };
                                             ui.label(egui::RichText::new(ready_text).size(12.0).color(ready_color));
                                             
-                                            // Connection indicator
-                                            let ping_color = match i % 3 {
-                                                0 => theme.success_color,
-                                                1 => theme.warning_color,
-                                                _ => theme.error_color,
-                                            };
-                                            ui.label(egui::RichText::new("●").size(10.0).color(ping_color));
+                                            // Connection indicator, from our probes to that peer
+                                            if !player.is_local {
+                                                let link = links.get(PeerId(player.id));
+                                                let (ping_color, ping_text) = ping_indicator(link, &theme);
+                                                ui.label(egui::RichText::new("●").size(10.0).color(ping_color))
+                                                    .on_hover_text(ping_text.clone());
+                                                ui.label(egui::RichText::new(ping_text).size(11.0).color(theme.secondary_color));
+                                            }
                                         });
                                     });
                                     
                                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                                         // Kick button for host
-                                        if lobby_state.is_host && !player.is_local && i != 0 {
-                                            if ui.small_button("×").on_hover_text("Kick player").clicked() {
-                                                player_to_kick = Some(i);
-                                            }
+                                        if lobby_state.is_host && !player.is_local && i != 0
+                                            && ui.small_button("×").on_hover_text("Kick player").clicked() {
+                                            player_to_kick = Some(player.id);
                                         }
                                     });
                                 });
@@ -364,12 +449,12 @@ pub fn lobby_ui(
                         }
                         
                         // Handle kick after the loop
-                        if let Some(index) = player_to_kick {
-                            kick_player(&mut lobby_state, index);
+                        if let Some(id) = player_to_kick {
+                            lobby_actions.write(LobbyAction::Kick(PeerId(id)));
                         }
 
                         // Empty slots
-                        for _i in lobby_state.players.len()..lobby_state.max_players {
+                        for _i in lobby_state.player_count..lobby_state.max_players {
                             ui.group(|ui| {
                                 ui.horizontal(|ui| {
                                     ui.label(egui::RichText::new("○").size(18.0).color(theme.secondary_color));
@@ -437,11 +522,12 @@ pub fn lobby_ui(
                     );
                     
                     let send_enabled = !lobby_state.current_chat_input.trim().is_empty();
-                    if ui.add_enabled(send_enabled, egui::Button::new("→")).clicked() 
-                        || (response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) {
-                        if send_enabled {
-                            send_chat_message(&mut lobby_state);
-                        }
+                    if (ui.add_enabled(send_enabled, egui::Button::new("→")).clicked() 
+                        || (response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))))
+                        && send_enabled {
+                        let text = lobby_state.current_chat_input.trim().to_string();
+                        lobby_state.current_chat_input.clear();
+                        lobby_actions.write(LobbyAction::Chat(text));
                     }
                 });
             });
@@ -458,7 +544,12 @@ pub fn lobby_ui(
                 // Room code
                 if let Some(room_id) = &lobby_state.room_id {
                     ui.horizontal(|ui| {
-                        ui.label("Room Code:");
+                        // Direct Connect lobbies go by the host's address
+                        if lobby_state.transport == LobbyTransport::Signaling {
+                            ui.label("Room Code:");
+                        } else {
+                            ui.label("Address:");
+                        }
                         ui.label(
                             egui::RichText::new(room_id)
                                 .color(theme.accent_color)
@@ -505,26 +596,16 @@ pub fn lobby_ui(
                 ui.vertical(|ui| {
                     ui.horizontal(|ui| {
                         ui.label("Max Players:");
+                        let mut max_players = lobby_state.max_players;
                         egui::ComboBox::from_id_salt("max_players")
-                            .selected_text(format!("{}", lobby_state.max_players))
+                            .selected_text(format!("{}", max_players))
                             .show_ui(ui, |ui| {
-                                ui.selectable_value(&mut lobby_state.max_players, 2, "2");
-                                ui.selectable_value(&mut lobby_state.max_players, 3, "3");
-                                ui.selectable_value(&mut lobby_state.max_players, 4, "4");
+                                ui.selectable_value(&mut max_players, 2, "2");
+                                ui.selectable_value(&mut max_players, 3, "3");
+                                ui.selectable_value(&mut max_players, 4, "4");
                             });
-                    });
-
-                    ui.add_space(15.0);
-
-                    // Dev buttons
-                    ui.label(egui::RichText::new("Dev Tools:").size(12.0).color(theme.secondary_color));
-                    ui.horizontal(|ui| {
-                        if ui.button("+ Add Bot").clicked() && lobby_state.players.len() < lobby_state.max_players {
-                            add_bot_player(&mut lobby_state);
-                        }
-                        
-                        if ui.button("Fill Lobby").clicked() {
-                            fill_lobby_with_bots(&mut lobby_state);
+                        if max_players != lobby_state.max_players {
+                            lobby_actions.write(LobbyAction::SetMaxPlayers(max_players));
                         }
                     });
                 });
@@ -539,14 +620,14 @@ pub fn lobby_ui(
             ui.horizontal_centered(|ui| {
                 ui.add_space(15.0);
                 
-                // Ready button for all players
+                // Ready button for everyone with a seat
                 let local_player = lobby_state.players.iter().find(|p| p.is_local);
                 
-                if let Some(player) = local_player {
+                if let Some(player) = local_player.filter(|p| !p.is_spectator) {
                     let ready_text = if player.is_ready { "✓ Ready!" } else { "Ready Up" };
                     let ready_color = if player.is_ready { theme.success_color } else { theme.primary_color };
                     if ui.add_sized([130.0, 40.0], egui::Button::new(egui::RichText::new(ready_text).color(egui::Color32::WHITE).size(16.0)).fill(ready_color)).clicked() {
-                        toggle_ready_status(&mut lobby_state);
+                        lobby_actions.write(LobbyAction::ToggleReady);
                     }
                 }
 
@@ -554,27 +635,32 @@ pub fn lobby_ui(
 
                 // Host controls
                 if lobby_state.is_host {
-                    let can_start = lobby_state.player_count >= 2 && !lobby_state.game_started;
+                    let everyone_ready = all_players_ready(&lobby_state.players);
+                    let can_start = lobby_state.player_count >= 2 && everyone_ready && !lobby_state.game_started;
                     if ui.add_sized([150.0, 40.0], egui::Button::new(
                         egui::RichText::new(if lobby_state.game_started { "Starting..." } else { "🚀 Start Game" })
                             .color(egui::Color32::WHITE)
                             .size(16.0)
                     ).fill(if can_start { theme.success_color } else { theme.secondary_color }))
                     .clicked() && can_start {
-                        // Signal that we're ready to start, but let the networking layer handle the actual transition
-                        lobby_state.game_started = true;
-                        add_system_message(&mut lobby_state, "Host is starting the game...");
-                        // Don't directly transition - let wait_for_players handle it for both clients
+                        // The lobby tells everyone to start, then starts us
+                        lobby_actions.write(LobbyAction::Start);
                     }
                     
                     if lobby_state.player_count < 2 && !lobby_state.game_started {
                         ui.colored_label(theme.warning_color, "⚠ Need at least 2 players to start");
+                    } else if !everyone_ready && !lobby_state.game_started {
+                        ui.colored_label(theme.warning_color, "⚠ Waiting for everyone to ready up");
                     }
                     
                     if lobby_state.game_started {
                         ui.colored_label(theme.success_color, "🔄 Initializing multiplayer session...");
                     }
                 } else {
+                    if local_player.is_some_and(|p| p.is_spectator) {
+                        ui.colored_label(theme.primary_color, "👁 The lobby is full, you'll watch the match");
+                        ui.add_space(10.0);
+                    }
                     if lobby_state.game_started {
                         ui.colored_label(theme.success_color, "🔄 Host is starting the game...");
                     } else {
@@ -602,13 +688,29 @@ pub fn lobby_ui(
         });
 }
 
+#[derive(SystemParam)]
+pub struct MatchControls<'w> {
+    control: Option<Res<'w, MatchControl>>,
+    connection: Res<'w, SessionHealth>,
+    lobby_state: ResMut<'w, LobbyState>,
+    actions: EventWriter<'w, MatchAction>,
+    rejoin: EventWriter<'w, RequestRejoin>,
+    rejoining: Option<Res<'w, Rejoining>>,
+    spectator_camera: Res<'w, SpectatorCamera>,
+    time: Res<'w, Time<Real>>,
+}
+
 pub fn in_game_ui(
     mut contexts: EguiContexts,
     mut game_state: ResMut<NextState<GameState>>,
     game_stats: Option<Res<GameStats>>,
-    lobby_state: Res<LobbyState>,
+    mut match_controls: MatchControls,
+    mut menu_open: Local<bool>,
     theme: Res<UITheme>,
 ) {
+    let MatchControls { control, connection, lobby_state, actions, rejoin, rejoining, spectator_camera, time } = &mut match_controls;
+    let spectating = control.as_deref().is_some_and(MatchControl::is_spectating);
+
     // Minimal in-game HUD with modern styling
     egui::Window::new("Game HUD")
         .anchor(egui::Align2::LEFT_TOP, egui::vec2(10.0, 10.0))
@@ -617,11 +719,29 @@ pub fn in_game_ui(
         .frame(egui::Frame::window(&contexts.ctx_mut().style()).fill(theme.background_color.gamma_multiply(0.8)))
         .show(contexts.ctx_mut(), |ui| {
             ui.vertical(|ui| {
-                ui.label(egui::RichText::new("🎮 Controls").color(theme.primary_color).size(14.0));
-                ui.label("WASD - Move");
-                ui.label("Space - Jump");
-                ui.label("↑ - Shoot/Aim");
-                ui.label("Shift - Dodge");
+                if let (true, Some(control)) = (spectating, control.as_deref()) {
+                    let watching = match spectator_camera.mode {
+                        CameraMode::Follow(handle) => control
+                            .handles
+                            .get(handle)
+                            .map(|peer| format!("Following {}", lobby_state.player_name(peer.0)))
+                            .unwrap_or_else(|| "Following nobody".to_string()),
+                        CameraMode::Free => "Free camera".to_string(),
+                    };
+                    ui.label(egui::RichText::new(format!("👁 Spectating - {watching}")).color(theme.primary_color).size(14.0));
+                    ui.label("Tab / Shift+Tab - Next / previous player");
+                    ui.label("F - Toggle free camera");
+                    ui.label("WASD, Space, Ctrl - Fly (free camera)");
+                    ui.label("Hold RMB - Look around (free camera)");
+                } else {
+                    ui.label(egui::RichText::new("🎮 Controls").color(theme.primary_color).size(14.0));
+                    ui.label("WASD - Move");
+                    ui.label("Space - Jump");
+                    ui.label("Mouse - Aim");
+                    ui.label("Hold LMB - Draw, release to shoot");
+                    ui.label("Shift - Dodge");
+                }
+                ui.label("F3 - Network stats");
                 
                 if let Some(stats) = game_stats {
                     ui.separator();
@@ -633,8 +753,113 @@ pub fn in_game_ui(
             });
         });
 
+    // GGRS holds the game while it waits, so say who we're waiting for
+    if !connection.interrupted.is_empty() {
+        egui::Window::new("Reconnecting")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, -80.0))
+            .title_bar(false)
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.spinner();
+                    for interruption in connection.interrupted.values() {
+                        let remaining = interruption.remaining(time.elapsed()).as_secs_f32().ceil();
+                        ui.label(
+                            egui::RichText::new(format!("Reconnecting to {}... {remaining:.0}s", interruption.name))
+                                .size(18.0)
+                                .color(theme.warning_color)
+                        );
+                    }
+                    ui.label(egui::RichText::new("After that they can still rejoin for a while").color(theme.secondary_color));
+                });
+            });
+    }
+
+    if !connection.awaiting_rejoin.is_empty() {
+        egui::Window::new("Awaiting Rejoin")
+            .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 90.0))
+            .title_bar(false)
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                for waiting in connection.awaiting_rejoin.values() {
+                    let remaining = waiting.remaining(time.elapsed()).as_secs_f32().ceil();
+                    ui.label(
+                        egui::RichText::new(format!("Holding {}'s slot... {remaining:.0}s", waiting.name))
+                            .color(theme.warning_color)
+                    );
+                }
+            });
+    }
+
+    // Everyone else dropping at once is most likely our own connection
+    if let Some(rejoining) = rejoining.as_deref() {
+        egui::Window::new("Rejoining")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .title_bar(false)
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.spinner();
+                    let remaining = rejoining.deadline.saturating_sub(time.elapsed()).as_secs_f32().ceil();
+                    ui.label(
+                        egui::RichText::new(format!("Rejoining the match... {remaining:.0}s"))
+                            .size(18.0)
+                            .color(theme.warning_color)
+                    );
+                });
+            });
+    } else if control.as_deref().is_some_and(MatchControl::lost_everyone) {
+        egui::Window::new("Connection Lost")
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .title_bar(false)
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.label(egui::RichText::new("Lost connection to every other player").size(18.0).color(theme.error_color));
+                    if matches!(lobby_state.transport, LobbyTransport::DirectHost { .. }) {
+                        // Everyone else found the match through our address
+                        ui.label(egui::RichText::new("They connected through you, so there's no match to rejoin").color(theme.secondary_color));
+                    } else {
+                        ui.label(egui::RichText::new("If it was your connection, rejoin to pick up where the match is now").color(theme.secondary_color));
+                        ui.add_space(10.0);
+                        if create_styled_button(ui, "🔄 Rejoin", &theme, true).clicked() {
+                            rejoin.write(RequestRejoin);
+                        }
+                    }
+                });
+            });
+    }
+
+    if let Some(desync) = &connection.last_desync {
+        egui::Area::new(egui::Id::new("desync_warning"))
+            .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -20.0))
+            .show(contexts.ctx_mut(), |ui| {
+                ui.colored_label(
+                    theme.error_color,
+                    format!("⚠ Desync with {} (frame {}), see the log", desync.name, desync.frame),
+                );
+            });
+    }
+
+    // Chat and votes only exist in a networked match
+    if let Some(control) = control.as_deref() {
+        match_chat_window(contexts.ctx_mut(), lobby_state, actions, &theme);
+
+        if control.is_paused() {
+            egui::Area::new(egui::Id::new("pause_banner"))
+                .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+                .show(contexts.ctx_mut(), |ui| {
+                    ui.label(egui::RichText::new("⏸️ Paused by vote").size(28.0).color(theme.warning_color));
+                });
+        }
+    }
+
     // Enhanced pause menu
     if contexts.ctx_mut().input(|i| i.key_pressed(egui::Key::Escape)) {
+        *menu_open = !*menu_open;
+    }
+
+    if *menu_open {
         egui::CentralPanel::default()
             .frame(egui::Frame::default().fill(theme.background_color.gamma_multiply(0.9)))
             .show(contexts.ctx_mut(), |ui| {
@@ -644,11 +869,39 @@ pub fn in_game_ui(
                     ui.add_space(30.0);
 
                     if create_styled_button(ui, "▶️ Resume", &theme, true).clicked() {
-                        // Just close the menu, game continues
+                        *menu_open = false;
                     }
 
                     ui.add_space(10.0);
 
+                    // The match keeps running behind this menu unless everyone agrees to stop it
+                    if let Some(control) = control.as_deref().filter(|_| !spectating) {
+                        let voted = control.pause_votes.contains(&control.local);
+                        let forfeited = control.has_forfeited(control.local);
+                        let votes = format!(
+                            "{}/{}",
+                            control.pause_votes.len(),
+                            control.active_players().count()
+                        );
+                        let vote_text = if voted {
+                            format!("Withdraw Pause Vote ({votes})")
+                        } else {
+                            format!("Vote to Pause ({votes})")
+                        };
+                        if create_styled_button(ui, &vote_text, &theme, !forfeited).clicked() && !forfeited {
+                            actions.write(MatchAction::VotePause(!voted));
+                        }
+
+                        ui.add_space(10.0);
+
+                        if create_styled_button(ui, "🏳 Forfeit", &theme, !forfeited).clicked() && !forfeited {
+                            actions.write(MatchAction::Forfeit);
+                            *menu_open = false;
+                        }
+
+                        ui.add_space(10.0);
+                    }
+
                     if create_styled_button(ui, "🏛️ Back to Lobby", &theme, true).clicked() {
                         game_state.set(GameState::Lobby);
                     }
@@ -661,6 +914,295 @@ pub fn in_game_ui(
     }
 }
 
+// Carries on the lobby chat during the match
+fn match_chat_window(
+    ctx: &mut egui::Context,
+    lobby_state: &mut LobbyState,
+    actions: &mut EventWriter<MatchAction>,
+    theme: &UITheme,
+) {
+    egui::Window::new("💬 Chat")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-15.0, -15.0))
+        .default_size(egui::vec2(320.0, 180.0))
+        .resizable(true)
+        .collapsible(true)
+        .default_open(false)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .id_salt("match_chat_scroll")
+                .max_height(140.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for message in &lobby_state.chat_messages {
+                        let (sender, color) = match message.message_type {
+                            ChatMessageType::Player => (format!("{}:", message.sender), theme.text_color),
+                            _ => ("⚙".to_string(), theme.accent_color),
+                        };
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(egui::RichText::new(sender).color(theme.primary_color).size(13.0));
+                            ui.label(egui::RichText::new(&message.message).color(color).size(13.0));
+                        });
+                    }
+                });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut lobby_state.current_chat_input)
+                        .desired_width(ui.available_width() - 40.0)
+                        .hint_text("Type message...")
+                );
+                let send_enabled = !lobby_state.current_chat_input.trim().is_empty();
+                if (ui.add_enabled(send_enabled, egui::Button::new("→")).clicked()
+                    || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))))
+                    && send_enabled {
+                    let text = lobby_state.current_chat_input.trim().to_string();
+                    lobby_state.current_chat_input.clear();
+                    actions.write(MatchAction::Chat(text));
+                }
+            });
+        });
+}
+
+pub fn game_over_ui(
+    mut contexts: EguiContexts,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut lobby_state: ResMut<LobbyState>,
+    result: Option<Res<MatchResult>>,
+    theme: Res<UITheme>,
+) {
+    egui::CentralPanel::default()
+        .frame(egui::Frame::default().fill(theme.background_color.gamma_multiply(0.9)))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(150.0);
+                ui.label(egui::RichText::new("Match Over").size(36.0).color(theme.primary_color).strong());
+                ui.add_space(20.0);
+
+                if let Some(result) = result.as_deref() {
+                    let winners = if result.winners.is_empty() {
+                        "Nobody wins".to_string()
+                    } else {
+                        format!("🏆 {} wins", result.winners.join(", "))
+                    };
+                    ui.label(egui::RichText::new(winners).size(24.0).color(theme.accent_color));
+                    ui.add_space(10.0);
+
+                    match &result.reason {
+                        MatchEndReason::Forfeit { forfeited, disconnected } => {
+                            for name in forfeited {
+                                ui.label(egui::RichText::new(format!("{name} forfeited")).color(theme.secondary_color));
+                            }
+                            for name in disconnected {
+                                ui.label(egui::RichText::new(format!("{name} disconnected")).color(theme.secondary_color));
+                            }
+                        }
+                        MatchEndReason::Advancements { advancements } => {
+                            ui.label(egui::RichText::new(format!("First to {advancements} advancements")).color(theme.secondary_color));
+                        }
+                    }
+
+                    ui.add_space(20.0);
+                    let minutes = result.duration as u32 / 60;
+                    let seconds = result.duration as u32 % 60;
+                    ui.label(format!("Match duration {minutes}:{seconds:02}"));
+                    ui.add_space(10.0);
+                    egui::Grid::new("match_results_grid").striped(true).min_col_width(80.0).show(ui, |ui| {
+                        for heading in ["Player", "Kills", "Deaths", "Accuracy"] {
+                            ui.label(egui::RichText::new(heading).color(theme.primary_color));
+                        }
+                        ui.end_row();
+
+                        for player in &result.players {
+                            ui.label(&player.name);
+                            ui.label(player.kills.to_string());
+                            ui.label(player.deaths.to_string());
+                            ui.label(match player.accuracy() {
+                                Some(accuracy) => format!("{:.0}% ({}/{})", accuracy * 100.0, player.shots_hit, player.shots_fired),
+                                None => "-".to_string(),
+                            });
+                            ui.end_row();
+                        }
+                    });
+                }
+
+                ui.add_space(30.0);
+                ui.allocate_ui(egui::vec2(250.0, 35.0), |ui| {
+                    if create_styled_button(ui, "🏠 Main Menu", &theme, true).clicked() {
+                        leave_lobby(&mut lobby_state, &mut game_state);
+                    }
+                });
+            });
+        });
+}
+
+fn overlay_visible(overlay: Res<NetworkStatsOverlay>) -> bool {
+    overlay.visible
+}
+
+// Toggled with F3 during a match
+pub fn network_stats_ui(
+    mut contexts: EguiContexts,
+    report: Res<NetworkReport>,
+    rollbacks: Res<RollbackStats>,
+    theme: Res<UITheme>,
+) {
+    egui::Window::new("📡 Network")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .resizable(false)
+        .collapsible(false)
+        .frame(egui::Frame::window(&contexts.ctx_mut().style()).fill(theme.background_color.gamma_multiply(0.8)))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!(
+                "Frame {} (confirmed {}), {} ahead",
+                report.current_frame, report.confirmed_frame, report.frames_ahead
+            ));
+            ui.label(format!(
+                "Rollbacks: {} ({} frames resimulated, deepest {})",
+                rollbacks.rollbacks, rollbacks.frames_resimulated, rollbacks.deepest
+            ));
+
+            if report.remotes.is_empty() {
+                ui.label(egui::RichText::new("No remote players").color(theme.secondary_color));
+                return;
+            }
+
+            ui.separator();
+            egui::Grid::new("network_stats_grid").striped(true).show(ui, |ui| {
+                for heading in ["Player", "RTT", "Loss", "Send queue", "Behind (us/them)", "kbps"] {
+                    ui.label(egui::RichText::new(heading).color(theme.primary_color));
+                }
+                ui.end_row();
+
+                for remote in &report.remotes {
+                    ui.label(&remote.name);
+                    match &remote.stats {
+                        Some(stats) => {
+                            ui.label(format!("{} ms", stats.ping));
+                            ui.label(format_loss(remote.packet_loss));
+                            ui.label(stats.send_queue_len.to_string());
+                            ui.label(format!("{} / {}", stats.local_frames_behind, stats.remote_frames_behind));
+                            ui.label(stats.kbps_sent.to_string());
+                        }
+                        None => {
+                            ui.label(egui::RichText::new("syncing...").color(theme.secondary_color));
+                            ui.label(format_loss(remote.packet_loss));
+                            for _ in 0..3 {
+                                ui.label("-");
+                            }
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+fn simulator_panel_visible(panel: Res<NetworkSimulatorPanel>) -> bool {
+    panel.visible
+}
+
+// Toggled with F4 anywhere. Changes reach running sessions straight away.
+pub fn network_simulator_ui(
+    mut contexts: EguiContexts,
+    mut simulator: ResMut<NetworkSimulator>,
+    theme: Res<UITheme>,
+) {
+    let mut conditions = simulator.conditions();
+    let mut latency = conditions.latency.as_millis() as u64;
+    let mut jitter = conditions.jitter.as_millis() as u64;
+    let mut loss = conditions.loss * 100.0;
+    let mut duplicate = conditions.duplicate * 100.0;
+    let mut reorder = conditions.reorder * 100.0;
+    let mut seed = simulator.seed;
+
+    egui::Window::new("🐢 Network Simulator")
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+        .resizable(false)
+        .collapsible(true)
+        .frame(egui::Frame::window(&contexts.ctx_mut().style()).fill(theme.background_color.gamma_multiply(0.9)))
+        .show(contexts.ctx_mut(), |ui| {
+            let (status, color) = if conditions.is_perfect() {
+                ("Off: packets pass straight through", theme.secondary_color)
+            } else {
+                ("On: applied to every packet, both ways", theme.warning_color)
+            };
+            ui.label(egui::RichText::new(status).color(color));
+            ui.separator();
+
+            egui::Grid::new("network_simulator_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Latency");
+                ui.add(egui::Slider::new(&mut latency, 0..=500).suffix(" ms"));
+                ui.end_row();
+                ui.label("Jitter");
+                ui.add(egui::Slider::new(&mut jitter, 0..=200).suffix(" ms"));
+                ui.end_row();
+                ui.label("Loss");
+                ui.add(egui::Slider::new(&mut loss, 0.0..=50.0).suffix("%"));
+                ui.end_row();
+                ui.label("Duplicate");
+                ui.add(egui::Slider::new(&mut duplicate, 0.0..=50.0).suffix("%"));
+                ui.end_row();
+                ui.label("Reorder");
+                ui.add(egui::Slider::new(&mut reorder, 0.0..=50.0).suffix("%"));
+                ui.end_row();
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(&mut seed)).on_hover_text("Takes effect from the next session");
+                ui.end_row();
+            });
+
+            if ui.button("Reset").clicked() {
+                latency = 0;
+                jitter = 0;
+                loss = 0.0;
+                duplicate = 0.0;
+                reorder = 0.0;
+            }
+        });
+
+    conditions = NetworkConditions {
+        latency: Duration::from_millis(latency),
+        jitter: Duration::from_millis(jitter),
+        loss: loss / 100.0,
+        duplicate: duplicate / 100.0,
+        reorder: reorder / 100.0,
+    };
+    if conditions != simulator.conditions() {
+        simulator.set_conditions(conditions);
+    }
+    if seed != simulator.seed {
+        simulator.seed = seed;
+    }
+}
+
+fn format_loss(loss: Option<f32>) -> String {
+    loss.map_or_else(|| "-".to_string(), |loss| format!("{:.0}%", loss * 100.0))
+}
+
+// Color and label for a peer's connection quality in the lobby
+fn ping_indicator(link: Option<&LinkQuality>, theme: &UITheme) -> (egui::Color32, String) {
+    let Some(rtt) = link.and_then(LinkQuality::rtt) else {
+        return (theme.secondary_color, "measuring...".to_string());
+    };
+    let rtt = rtt.as_millis();
+    let loss = link.and_then(LinkQuality::packet_loss).unwrap_or(0.0);
+
+    let color = if rtt < 80 && loss < 0.02 {
+        theme.success_color
+    } else if rtt < 160 && loss < 0.1 {
+        theme.warning_color
+    } else {
+        theme.error_color
+    };
+    let text = if loss > 0.0 {
+        format!("{rtt} ms, {:.0}% loss", loss * 100.0)
+    } else {
+        format!("{rtt} ms")
+    };
+    (color, text)
+}
+
 // Helper functions
 fn apply_dark_theme(ctx: &mut egui::Context, theme: &UITheme) {
     let mut visuals = egui::Visuals::dark();
@@ -680,7 +1222,7 @@ fn create_styled_button(ui: &mut egui::Ui, text: &str, theme: &UITheme, enabled:
         [ui.available_width(), 35.0],
         egui::Button::new(egui::RichText::new(text).color(text_color).size(14.0))
             .fill(button_color)
-            .corner_radius(egui::Rounding::same(6))
+            .corner_radius(egui::CornerRadius::same(6))
     )
 }
 
@@ -691,6 +1233,27 @@ fn generate_room_code() -> String {
     (0..6).map(|_| chars[rng.gen_range(0..chars.len())]).collect()
 }
 
+// A fresh lobby with only us in it. The host's roster fills in everyone else.
+fn open_lobby(lobby_state: &mut LobbyState, room_id: String, is_host: bool, transport: LobbyTransport) {
+    let player_name = lobby_state.local_player_name.clone();
+    lobby_state.room_id = Some(room_id);
+    lobby_state.transport = transport;
+    lobby_state.is_host = is_host;
+    lobby_state.max_players = 4;
+    lobby_state.player_count = 1;
+    lobby_state.game_started = false;
+    lobby_state.players.clear();
+    lobby_state.players.push(LobbyPlayer {
+        id: Uuid::new_v4(),
+        name: player_name,
+        is_ready: false,
+        network_handle: 0,
+        is_local: true,
+        is_spectator: false,
+    });
+    lobby_state.chat_messages.clear();
+}
+
 fn leave_lobby(lobby_state: &mut LobbyState, game_state: &mut NextState<GameState>) {
     lobby_state.room_id = None;
     lobby_state.players.clear();
@@ -701,82 +1264,6 @@ fn leave_lobby(lobby_state: &mut LobbyState, game_state: &mut NextState<GameStat
 }
 
 fn all_players_ready(players: &[LobbyPlayer]) -> bool {
-    !players.is_empty() && players.iter().all(|p| p.is_ready)
-}
-
-fn add_system_message(lobby_state: &mut LobbyState, message: &str) {
-    use crate::resources::{ChatMessage, ChatMessageType};
-    
-    lobby_state.chat_messages.push(ChatMessage {
-        sender: "System".to_string(),
-        message: message.to_string(),
-        timestamp: 0.0, // Would use actual time in production
-        message_type: ChatMessageType::System,
-    });
-}
-
-fn send_chat_message(lobby_state: &mut LobbyState) {
-    use crate::resources::{ChatMessage, ChatMessageType};
-    
-    if let Some(local_player) = lobby_state.players.iter().find(|p| p.is_local) {
-        let message = lobby_state.current_chat_input.trim().to_string();
-        if !message.is_empty() {
-            lobby_state.chat_messages.push(ChatMessage {
-                sender: local_player.name.clone(),
-                message,
-                timestamp: 0.0, // Would use actual time in production
-                message_type: ChatMessageType::Player,
-            });
-            lobby_state.current_chat_input.clear();
-        }
-    }
-}
-
-fn toggle_ready_status(lobby_state: &mut LobbyState) {
-    let mut player_name = String::new();
-    let mut new_status = false;
-    
-    if let Some(player) = lobby_state.players.iter_mut().find(|p| p.is_local) {
-        player.is_ready = !player.is_ready;
-        new_status = player.is_ready;
-        player_name = player.name.clone();
-    }
-    
-    if !player_name.is_empty() {
-        let status = if new_status { "ready" } else { "not ready" };
-        add_system_message(lobby_state, &format!("{} is now {}", player_name, status));
-    }
-}
-
-fn kick_player(lobby_state: &mut LobbyState, player_index: usize) {
-    if player_index < lobby_state.players.len() {
-        let kicked_player = lobby_state.players.remove(player_index);
-        lobby_state.player_count = lobby_state.player_count.saturating_sub(1);
-        add_system_message(lobby_state, &format!("{} was kicked from the lobby", kicked_player.name));
-    }
-}
-
-fn add_bot_player(lobby_state: &mut LobbyState) {
-    use crate::resources::LobbyPlayer;
-    
-    let bot_names = ["Archer", "Hunter", "Ranger", "Scout", "Sniper", "Robin", "Legolas"];
-    let next_id = lobby_state.players.len();
-    let bot_name = format!("{} (Bot)", bot_names[next_id % bot_names.len()]);
-    
-    lobby_state.players.push(LobbyPlayer {
-        id: Uuid::new_v4(),
-        name: bot_name.clone(),
-        is_ready: rand::random(), // Randomly ready or not
-        network_handle: next_id as u32,
-        is_local: false,
-    });
-    
-    lobby_state.player_count += 1;
-    add_system_message(lobby_state, &format!("{} joined the lobby", bot_name));
+    let mut seated = players.iter().filter(|p| !p.is_spectator).peekable();
+    seated.peek().is_some() && seated.all(|p| p.is_ready)
 }
-
-fn fill_lobby_with_bots(lobby_state: &mut LobbyState) {
-    while lobby_state.players.len() < lobby_state.max_players {
-        add_bot_player(lobby_state);
-    }
-} 
\ No newline at end of file
@@ -0,0 +1,344 @@
+// Two-Peer Harness
+// ================
+//
+// Two headless apps with the real gameplay plugins, joined by an in-memory
+// GGRS socket instead of WebRTC. Each app runs a real P2P session with
+// real rollbacks; only the network and the hands on the keyboard are fake.
+// Inputs come from a script keyed on frame and player handle, and every
+// saved frame's checksum is recorded so the two peers can be compared once
+// those frames are confirmed.
+//
+// `SyncTest` runs the same app on a GGRS SyncTest session instead, with
+// both handles local. GGRS rolls every frame back and simulates it again,
+// which only lands on the same checksum if everything the frame touches is
+// registered for rollback.
+#![allow(dead_code)] // Not every test binary uses every helper
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_ggrs::ggrs::{Message, NonBlockingSocket, PlayerHandle, PlayerType, SessionBuilder};
+use bevy_ggrs::{
+    Checksum, ConfirmedFrameCount, LocalInputs, LocalPlayers, ReadInputs, RollbackFrameCount,
+    SaveWorld, SaveWorldSet,
+};
+use bevy_matchbox::prelude::PeerId;
+use ribbon_game::input::read_local_inputs;
+use ribbon_game::netsim::{NetworkConditions, NetworkSimulator, SimulatorClock};
+use ribbon_game::networking::{p2p_session, GGRSConfig, PackedInput, FPS};
+use ribbon_game::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+// Enough updates for any test to get where it's going, short of a hang
+const MAX_UPDATES: usize = 10_000;
+
+pub type Script = Arc<dyn Fn(i32, PlayerHandle) -> NetworkInput + Send + Sync>;
+
+// Messages waiting for one address, each with who sent it
+type Mailbox = Vec<(PeerId, Message)>;
+
+// Mailboxes by address. Every socket on the same loopback sees the others.
+#[derive(Clone, Default)]
+pub struct Loopback {
+    mailboxes: Arc<Mutex<HashMap<PeerId, Mailbox>>>,
+}
+
+impl Loopback {
+    pub fn socket(&self, local: PeerId) -> LoopbackSocket {
+        LoopbackSocket {
+            local,
+            loopback: self.clone(),
+        }
+    }
+}
+
+pub struct LoopbackSocket {
+    local: PeerId,
+    loopback: Loopback,
+}
+
+impl NonBlockingSocket<PeerId> for LoopbackSocket {
+    fn send_to(&mut self, msg: &Message, addr: &PeerId) {
+        let mut mailboxes = self.loopback.mailboxes.lock().unwrap();
+        mailboxes
+            .entry(*addr)
+            .or_default()
+            .push((self.local, msg.clone()));
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut mailboxes = self.loopback.mailboxes.lock().unwrap();
+        mailboxes.remove(&self.local).unwrap_or_default()
+    }
+}
+
+#[derive(Resource)]
+struct InputScript(Script);
+
+// Every checksum bevy_ggrs saved, last save of a frame wins
+#[derive(Resource, Default)]
+pub struct Checksums(pub BTreeMap<i32, u128>);
+
+// Frames saved again with a different checksum. A P2P rollback does that
+// whenever it corrects a prediction; a SyncTest never should.
+#[derive(Resource, Default)]
+pub struct Diverged(pub Vec<i32>);
+
+pub struct TwoPeers {
+    pub peers: [App; 2],
+    // Each peer's network time, moved on a frame per update like its `Time`
+    clocks: [SimulatorClock; 2],
+}
+
+impl TwoPeers {
+    pub fn new(script: impl Fn(i32, PlayerHandle) -> NetworkInput + Send + Sync + 'static) -> Self {
+        Self::with_conditions(NetworkConditions::default(), script)
+    }
+
+    // Both peers' sockets go through the network simulator
+    pub fn with_conditions(
+        conditions: NetworkConditions,
+        script: impl Fn(i32, PlayerHandle) -> NetworkInput + Send + Sync + 'static,
+    ) -> Self {
+        let script: Script = Arc::new(script);
+        let ids = [PeerId(Uuid::from_u128(1)), PeerId(Uuid::from_u128(2))];
+        let loopback = Loopback::default();
+        let clocks = [SimulatorClock::manual(), SimulatorClock::manual()];
+        let peers = [0, 1].map(|peer| {
+            let local = ids[peer];
+            let mut simulator = NetworkSimulator::default();
+            simulator.clock = clocks[peer].clone();
+            simulator.set_conditions(conditions);
+            let socket = simulator.wrap(loopback.socket(local));
+            let session = p2p_session(local, &ids, &[], socket).expect("session starts");
+            peer_app(session, script.clone())
+        });
+        Self { peers, clocks }
+    }
+
+    // Same resource on both peers, e.g. different rules for a test
+    pub fn insert_resource<R: Resource + Clone>(&mut self, resource: R) {
+        for peer in &mut self.peers {
+            peer.insert_resource(resource.clone());
+        }
+    }
+
+    pub fn state(&self, peer: usize) -> GameState {
+        *self.peers[peer]
+            .world()
+            .resource::<State<GameState>>()
+            .get()
+    }
+
+    pub fn update(&mut self) {
+        for peer in 0..2 {
+            self.update_peer(peer);
+        }
+    }
+
+    // One peer only, e.g. to let it predict ahead of the other
+    pub fn update_peer(&mut self, peer: usize) {
+        self.clocks[peer].advance(frame_time());
+        self.peers[peer].update();
+    }
+
+    pub fn frame(&self, peer: usize) -> i32 {
+        self.peers[peer].world().resource::<RollbackFrameCount>().0
+    }
+
+    pub fn confirmed_frame(&self, peer: usize) -> i32 {
+        (*self.peers[peer].world().resource::<ConfirmedFrameCount>()).into()
+    }
+
+    // Runs until both peers have simulated `frames` frames
+    pub fn run_frames(&mut self, frames: i32) {
+        self.run_until(|peers| (0..2).all(|peer| peers.frame(peer) >= frames));
+    }
+
+    // Runs until both peers have every input up to `frame`
+    pub fn confirm(&mut self, frame: i32) {
+        self.run_until(|peers| (0..2).all(|peer| peers.confirmed_frame(peer) >= frame));
+    }
+
+    pub fn run_until(&mut self, done: impl Fn(&Self) -> bool) {
+        for _ in 0..MAX_UPDATES {
+            if done(self) {
+                return;
+            }
+            self.update();
+        }
+        panic!(
+            "peers stuck at frames {} and {} (confirmed {} and {})",
+            self.frame(0),
+            self.frame(1),
+            self.confirmed_frame(0),
+            self.confirmed_frame(1)
+        );
+    }
+
+    pub fn checksums(&self, peer: usize) -> &BTreeMap<i32, u128> {
+        &self.peers[peer].world().resource::<Checksums>().0
+    }
+
+    // Both peers saved every frame before `frame` with the same checksum
+    pub fn assert_in_sync(&mut self, frame: i32) {
+        self.confirm(frame);
+        let [first, second] = [0, 1].map(|peer| {
+            self.checksums(peer)
+                .range(..frame)
+                .map(|(&frame, &checksum)| (frame, checksum))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(first.len(), frame as usize, "every frame was saved");
+        if let Some(((frame, ours), (_, theirs))) = first
+            .iter()
+            .zip(&second)
+            .find(|(ours, theirs)| ours != theirs)
+        {
+            panic!("peers diverged on frame {frame}: {ours:032X} vs {theirs:032X}");
+        }
+        assert_eq!(first, second);
+    }
+
+    // Every player's handle and position on one peer, in handle order
+    pub fn players(&mut self, peer: usize) -> Vec<(u32, Vec3)> {
+        let world = self.peers[peer].world_mut();
+        let mut query = world.query_filtered::<(&Player, &Transform), With<bevy_ggrs::Rollback>>();
+        let mut players = query
+            .iter(world)
+            .map(|(player, transform)| (player.network_id, transform.translation))
+            .collect::<Vec<_>>();
+        players.sort_by_key(|(handle, _)| *handle);
+        players
+    }
+
+    // How many players on one peer are waiting to respawn
+    pub fn respawning(&mut self, peer: usize) -> usize {
+        let world = self.peers[peer].world_mut();
+        world
+            .query_filtered::<(), (With<Player>, With<Respawning>)>()
+            .iter(world)
+            .count()
+    }
+}
+
+pub struct SyncTest {
+    pub app: App,
+}
+
+impl SyncTest {
+    pub fn new(
+        check_distance: usize,
+        script: impl Fn(i32, PlayerHandle) -> NetworkInput + Send + Sync + 'static,
+    ) -> Self {
+        let mut session_builder = SessionBuilder::<GGRSConfig>::new()
+            .with_num_players(2)
+            .with_check_distance(check_distance);
+        for handle in 0..2 {
+            session_builder = session_builder
+                .add_player(PlayerType::Local, handle)
+                .expect("handles are free");
+        }
+        let session = session_builder
+            .start_synctest_session()
+            .expect("session starts");
+        let app = peer_app(bevy_ggrs::Session::SyncTest(session), Arc::new(script));
+        Self { app }
+    }
+
+    pub fn frame(&self) -> i32 {
+        self.app.world().resource::<RollbackFrameCount>().0
+    }
+
+    pub fn run_frames(&mut self, frames: i32) {
+        for _ in 0..MAX_UPDATES {
+            if self.frame() >= frames {
+                return;
+            }
+            self.app.update();
+        }
+        // GGRS won't go past a frame that resimulated differently
+        panic!(
+            "stuck at frame {}, frames {:?} diverged",
+            self.frame(),
+            self.diverged()
+        );
+    }
+
+    pub fn diverged(&self) -> &[i32] {
+        &self.app.world().resource::<Diverged>().0
+    }
+}
+
+fn peer_app(session: bevy_ggrs::Session<GGRSConfig>, script: Script) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin, TransformPlugin))
+        .init_state::<GameState>()
+        // What DefaultPlugins and the game's own plugins would otherwise provide
+        .init_resource::<ButtonInput<KeyCode>>()
+        .init_resource::<ButtonInput<MouseButton>>()
+        .init_resource::<Assets<Mesh>>()
+        .init_resource::<Assets<StandardMaterial>>()
+        .init_resource::<bevy::scene::SceneSpawner>()
+        .init_resource::<GameStats>()
+        .add_plugins((
+            physics::PhysicsPlugin,
+            networking::NetworkingPlugin,
+            combat::CombatPlugin,
+            advancement::AdvancementPlugin,
+            respawn::RespawnPlugin,
+            session_events::SessionEventsPlugin,
+            // Registers the checksum parts
+            synctest::SyncTestPlugin,
+        ))
+        // One rollback frame per update, however fast the test runs
+        .insert_resource(TimeUpdateStrategy::ManualDuration(frame_time()))
+        .insert_resource(InputScript(script))
+        .init_resource::<Checksums>()
+        .init_resource::<Diverged>()
+        .add_systems(OnEnter(GameState::InGame), systems::setup_scene)
+        .add_systems(ReadInputs, scripted_inputs.after(read_local_inputs))
+        .add_systems(SaveWorld, record_checksum.after(SaveWorldSet::Checksum))
+        .insert_resource(session);
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::InGame);
+    app.finish();
+    app.cleanup();
+    app
+}
+
+fn frame_time() -> Duration {
+    Duration::from_secs_f64(1.0 / FPS as f64)
+}
+
+// Replaces whatever the keyboard said with the script
+fn scripted_inputs(
+    mut commands: Commands,
+    script: Res<InputScript>,
+    frame: Res<RollbackFrameCount>,
+    local_players: Res<LocalPlayers>,
+) {
+    let inputs = local_players
+        .0
+        .iter()
+        .map(|&handle| (handle, PackedInput::from((script.0)(frame.0, handle))))
+        .collect();
+    commands.insert_resource(LocalInputs::<GGRSConfig>(inputs));
+}
+
+fn record_checksum(
+    frame: Res<RollbackFrameCount>,
+    checksum: Res<Checksum>,
+    mut checksums: ResMut<Checksums>,
+    mut diverged: ResMut<Diverged>,
+) {
+    let previous = checksums.0.insert(frame.0, checksum.0);
+    if previous.is_some_and(|previous| previous != checksum.0) {
+        diverged.0.push(frame.0);
+    }
+}
@@ -0,0 +1,42 @@
+mod harness;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use harness::SyncTest;
+use ribbon_game::{NetworkInput, Player};
+
+// The players start three meters apart and walk into each other, hopping
+// now and then, so the solver has contacts to warm-start from
+fn shove(frame: i32, handle: usize) -> NetworkInput {
+    NetworkInput {
+        movement: Vec2::new(if handle == 0 { 1.0 } else { -1.0 }, 0.0),
+        jump: frame % 15 == 0,
+        ..default()
+    }
+}
+
+// Whether the two players' bodies are touching right now
+fn players_touch(synctest: &mut SyncTest) -> bool {
+    let world = synctest.app.world_mut();
+    let players = world
+        .query_filtered::<Entity, With<Player>>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    let [first, second] = players[..] else {
+        panic!("expected two players, got {players:?}");
+    };
+    world.resource::<ContactGraph>().contains(first, second)
+}
+
+#[test]
+fn shoving_players_resimulate_bit_identically() {
+    let mut synctest = SyncTest::new(7, shove);
+    let mut touched = false;
+    for frame in 1..=60 {
+        synctest.run_frames(frame);
+        touched |= players_touch(&mut synctest);
+    }
+    assert_eq!(synctest.diverged(), [] as [i32; 0]);
+    // Make sure the scenario actually exercised contacts
+    assert!(touched, "the players never met");
+}